@@ -0,0 +1,9 @@
+// Generates the gRPC service from proto/vectra.proto. The proto is parsed in-process so
+// building doesn't need protoc installed. The client is only used by the tests, which bring
+// their own channel, so it is generated without the transport helpers.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/vectra.proto");
+    let fds = protox::compile(["proto/vectra.proto"], ["proto"])?;
+    tonic_build::configure().build_client(true).build_transport(false).compile_fds(fds)?;
+    Ok(())
+}
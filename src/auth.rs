@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use axum::{extract::{Path, Request, State}, http::{header, HeaderMap}, middleware::Next, response::Response};
+use serde::Deserialize;
+use crate::error::ApiError;
+
+/// One API key from the `--auth-config` file. `read`/`write` list database names, `"*"`
+/// matches any database, and write access implies read access.
+#[derive(Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    key: String,
+    // SHA-256 of `key`, filled in by `AuthConfig::load`
+    #[serde(skip)]
+    digest: [u8; 32],
+    #[serde(default)]
+    admin: bool,
+    #[serde(default)]
+    read: Vec<String>,
+    #[serde(default)]
+    write: Vec<String>,
+}
+
+impl ApiKey {
+    pub fn can_read(&self, db: &str) -> bool { self.admin || matches(&self.read, db) || matches(&self.write, db) }
+
+    pub fn can_write(&self, db: &str) -> bool { self.admin || matches(&self.write, db) }
+
+    pub fn can_admin(&self) -> bool { self.admin }
+}
+
+fn matches(patterns: &[String], db: &str) -> bool {
+    patterns.iter().any(|p| p == "*" || p == db)
+}
+
+/// Keys accepted by the server, loaded from a JSON file of the form
+/// `{"keys": [{"name": "...", "key": "...", "admin": false, "read": ["*"], "write": ["docs"]}]}`.
+pub struct AuthConfig { keys: Vec<Arc<ApiKey>> }
+
+#[derive(Deserialize)]
+struct KeyFile { keys: Vec<ApiKey> }
+
+impl AuthConfig {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let file: KeyFile = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+        let keys = file.keys.into_iter().map(|mut k| { k.digest = sha256(&k.key); Arc::new(k) }).collect();
+        let config = AuthConfig { keys };
+        for (i, k) in config.keys.iter().enumerate() {
+            if k.key.is_empty() { return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: key '{}' is empty", path, k.name))); }
+            if config.keys[..i].iter().any(|o| o.key == k.key) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: key '{}' is listed twice", path, k.name)));
+            }
+        }
+        Ok(config)
+    }
+
+    // Digests are compared rather than the keys, so every comparison covers the same 32 bytes
+    // whatever the token's length, and against every key without short-circuiting, so timing
+    // doesn't leak how much matched or which key it was.
+    fn find(&self, token: &str) -> Option<Arc<ApiKey>> {
+        let digest = sha256(token);
+        let mut found = None;
+        for k in &self.keys {
+            if digests_eq(&k.digest, &digest) { found = Some(k.clone()); }
+        }
+        found
+    }
+
+    /// The key a token belongs to, without checking any scope.
+    pub fn identify(&self, token: Option<&str>) -> Option<Arc<ApiKey>> {
+        token.and_then(|t| self.find(t))
+    }
+
+    /// Resolves a token to its key, failing with 401 if it is missing or unknown and 403 if
+    /// the key lacks `scope` on `db`.
+    pub fn authorize(&self, token: Option<&str>, scope: Scope, db: &str) -> Result<Arc<ApiKey>, ApiError> {
+        let key = self.identify(token).ok_or(ApiError::Unauthorized)?;
+        let allowed = match scope {
+            Scope::Any => true,
+            Scope::Read => key.can_read(db),
+            Scope::Write => key.can_write(db),
+            Scope::Admin => key.admin,
+        };
+        if !allowed {
+            let what = match scope {
+                Scope::Admin => "admin access".to_string(),
+                Scope::Write => format!("write access to '{}'", db),
+                _ => format!("read access to '{}'", db),
+            };
+            return Err(ApiError::Forbidden(format!("key '{}' lacks {}", key.name, what)));
+        }
+        Ok(key)
+    }
+}
+
+fn sha256(s: &str) -> [u8; 32] {
+    ring::digest::digest(&ring::digest::SHA256, s.as_bytes()).as_ref().try_into().expect("SHA-256 digests are 32 bytes")
+}
+
+fn digests_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What a route needs from the caller's key.
+#[derive(Clone, Copy)]
+pub enum Scope {
+    /// Any valid key
+    Any,
+    /// Read access to the `:name` database
+    Read,
+    /// Write access to the `:name` database
+    Write,
+    /// Creating, dropping and administering databases
+    Admin,
+}
+
+/// Middleware state: the configured keys (`None` disables auth) and the scope a route requires.
+#[derive(Clone)]
+pub struct Guard { pub auth: Option<Arc<AuthConfig>>, pub scope: Scope }
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(v) = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        return v.strip_prefix("Bearer ").map(str::trim);
+    }
+    headers.get("x-api-key").and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+/// Rejects the request with 401 if it carries no valid key and 403 if the key lacks the
+/// route's scope. On success the key is added to the request extensions for handlers.
+pub async fn require(State(guard): State<Guard>, params: Option<Path<HashMap<String, String>>>, mut req: Request, next: Next) -> Result<Response, ApiError> {
+    let Some(auth) = &guard.auth else { return Ok(next.run(req).await) };
+    let db = params.as_ref().and_then(|Path(p)| p.get("name")).map(String::as_str).unwrap_or("");
+    let key = auth.authorize(bearer_token(req.headers()), guard.scope, db)?;
+    req.extensions_mut().insert(key);
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,661 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{extract::{DefaultBodyLimit, Request, State}, http::{header, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{delete, get, post}, Extension, Json, Router};
+use futures_util::future::join_all;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use crate::auth::{ApiKey, AuthConfig, Guard, Scope};
+use crate::codec::Format;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery};
+use crate::limits::Limits;
+use crate::server::{self, FindItem, InsertReq, RowError};
+use crate::ver::{self, SearchMode};
+
+// Metadata key holding a vector's cluster-wide id on the worker that stores it.
+const ID_KEY: &str = "_id";
+// Ids are reserved on disk this many at a time, like change event sequence numbers.
+const ID_BLOCK: u64 = 1024;
+const WORKER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A database split across workers, as stored in `<dir>/cluster.json`. Shard `i` is the
+/// database `<name>_shard_<i>` on `shards[i]`.
+#[derive(Clone, Serialize, Deserialize)]
+struct Sharded { dimension: usize, shards: Vec<String>, reserved_id: u64 }
+
+struct Entry { db: Sharded, next_id: u64 }
+
+// Where a shard keeps each cluster id: the worker's own index for it, so a read by id is one
+// direct lookup. Insert answers fill it in. Until `complete` is set, ids may be missing: after
+// a restart, or after a write whose outcome is unknown. The next id not found then rebuilds the
+// map with one scroll of the shard. `doubts` counts such writes, so a rebuild racing one of
+// them doesn't claim to be complete.
+#[derive(Default)]
+struct Located { complete: bool, doubts: u64, index: HashMap<u64, usize> }
+
+enum Place { At(usize), Absent, Unknown }
+
+fn shard_name(name: &str, shard: usize) -> String {
+    format!("{}_shard_{}", name, shard)
+}
+
+// Which shard holds an id. The mix (murmur3's finalizer) spreads sequential ids evenly, and
+// must never change: placement of stored vectors depends on it.
+fn shard_of(id: u64, shards: usize) -> usize {
+    let mut x = id;
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^= x >> 33;
+    (x % shards as u64) as usize
+}
+
+/// The coordinator: it owns the shard map and talks to `vectra serve` workers over their
+/// REST API. It stores no vectors itself.
+pub struct Cluster {
+    path: PathBuf,
+    workers: Vec<String>,
+    shards: usize,
+    dbs: Mutex<BTreeMap<String, Entry>>,
+    // names whose shards are being created; taken while holding `dbs`
+    creating: Mutex<HashSet<String>>,
+    // per database, one map per shard; kept in memory only
+    located: Mutex<HashMap<String, Vec<Located>>>,
+    client: reqwest::Client,
+    limits: Limits,
+}
+
+impl Cluster {
+    /// Loads the shard map in `<dir>/cluster.json`. New databases get `shards` shards (one per
+    /// worker when `None`), dealt round-robin over `workers`; existing ones keep their placement.
+    pub fn open(dir: &str, workers: Vec<String>, shards: Option<usize>, worker_key: Option<&str>, limits: Limits) -> io::Result<Self> {
+        let workers: Vec<String> = workers.into_iter().map(|w| w.trim_end_matches('/').to_string()).collect();
+        if workers.is_empty() { return Err(io::Error::new(io::ErrorKind::InvalidInput, "at least one --worker is needed")); }
+        for w in &workers {
+            let url = reqwest::Url::parse(w).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid worker url '{}': {}", w, e)))?;
+            if !matches!(url.scheme(), "http" | "https") { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("worker url must be http or https: {}", w))); }
+        }
+        let path = PathBuf::from(dir).join("cluster.json");
+        let saved: BTreeMap<String, Sharded> = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        fs::create_dir_all(dir)?;
+        // a restart resumes above every id handed out before, even after a crash
+        let dbs = saved.into_iter().map(|(name, db)| (name, Entry { next_id: db.reserved_id, db })).collect();
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(key) = worker_key {
+            let mut v = reqwest::header::HeaderValue::from_str(key).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "the worker key is not a valid header value"))?;
+            v.set_sensitive(true);
+            headers.insert("x-api-key", v);
+        }
+        let client = reqwest::Client::builder().default_headers(headers).timeout(WORKER_TIMEOUT).build().map_err(io::Error::other)?;
+        let shards = shards.unwrap_or(workers.len()).max(1);
+        Ok(Cluster { path, workers, shards, dbs: Mutex::new(dbs), creating: Mutex::new(HashSet::new()), located: Mutex::new(HashMap::new()), client, limits })
+    }
+
+    // Rewrites the shard map; a temporary file and rename keep it whole if we crash.
+    fn save(&self, dbs: &BTreeMap<String, Entry>) -> io::Result<()> {
+        let map: BTreeMap<&String, &Sharded> = dbs.iter().map(|(k, e)| (k, &e.db)).collect();
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&map).map_err(io::Error::other)?)?;
+        fs::rename(tmp, &self.path)
+    }
+
+    fn get(&self, name: &str) -> Result<Sharded, ApiError> {
+        self.dbs.lock()?.get(name).map(|e| e.db.clone()).ok_or_else(|| ApiError::DbNotFound(name.to_string()))
+    }
+
+    // Hands out `count` consecutive ids, reserving another block on disk when needed.
+    fn assign_ids(&self, name: &str, count: usize) -> Result<u64, ApiError> {
+        let mut dbs = self.dbs.lock()?;
+        let entry = dbs.get_mut(name).ok_or_else(|| ApiError::DbNotFound(name.to_string()))?;
+        let first = entry.next_id;
+        entry.next_id += count as u64;
+        if entry.next_id > entry.db.reserved_id {
+            let before = entry.db.reserved_id;
+            entry.db.reserved_id = entry.next_id.div_ceil(ID_BLOCK) * ID_BLOCK;
+            if let Err(e) = self.save(&dbs) {
+                if let Some(entry) = dbs.get_mut(name) { entry.db.reserved_id = before; entry.next_id = first; }
+                return Err(e.into());
+            }
+        }
+        Ok(first)
+    }
+
+    fn place(&self, name: &str, shard: usize, id: u64) -> Result<Place, ApiError> {
+        let located = self.located.lock()?;
+        let Some(l) = located.get(name).and_then(|shards| shards.get(shard)) else { return Ok(Place::Unknown) };
+        Ok(match l.index.get(&id) {
+            Some(&i) => Place::At(i),
+            None if l.complete => Place::Absent,
+            None => Place::Unknown,
+        })
+    }
+
+    fn with_located(&self, name: &str, shards: usize, shard: usize, f: impl FnOnce(&mut Located)) -> Result<(), ApiError> {
+        let mut located = self.located.lock()?;
+        f(&mut located.entry(name.to_string()).or_insert_with(|| (0..shards).map(|_| Located::default()).collect())[shard]);
+        Ok(())
+    }
+
+    // Notes the shard-local index of each (cluster id, index) pair a shard stored.
+    fn stored(&self, name: &str, db: &Sharded, shard: usize, pairs: impl IntoIterator<Item = (u64, usize)>) -> Result<(), ApiError> {
+        self.with_located(name, db.shards.len(), shard, |l| l.index.extend(pairs))
+    }
+
+    // A write to the shard failed without saying whether it landed.
+    fn unsure(&self, name: &str, db: &Sharded, shard: usize) -> Result<(), ApiError> {
+        self.with_located(name, db.shards.len(), shard, |l| { l.complete = false; l.doubts += 1; })
+    }
+
+    // Reads every cluster id a shard holds. Inserts landing meanwhile are recorded by their own
+    // requests, so the map is complete once the scroll ends.
+    async fn index_shard(&self, name: &str, db: &Sharded, shard: usize) -> Result<(), ApiError> {
+        let w = &db.shards[shard];
+        let mut doubts = 0;
+        self.with_located(name, db.shards.len(), shard, |l| doubts = l.doubts)?;
+        let mut cursor: Option<String> = None;
+        let mut found = Vec::new();
+        loop {
+            let body = serde_json::json!({ "cursor": cursor, "limit": 1000, "with_values": false });
+            let req = self.client.post(format!("{}/db/{}/scroll", w, shard_name(name, shard))).json(&body);
+            let (page, _) = self.call::<ScrollPage>(w, req).await?;
+            found.extend(page.items.iter().filter_map(|i| Some((i.metadata.get(ID_KEY)?.parse().ok()?, i.id as usize))));
+            cursor = page.next_cursor;
+            if cursor.is_none() { break; }
+        }
+        self.with_located(name, db.shards.len(), shard, |l| { l.index.extend(found); l.complete = l.doubts == doubts; })
+    }
+
+    async fn send(&self, worker: &str, req: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        req.send().await.map_err(|e| ApiError::Worker(format!("worker {} is unreachable: {}", worker, e)))
+    }
+
+    // Decodes a worker's success body. A worker turning down the request itself (see `rejection`)
+    // is passed on as is; any other error answer becomes a 502 quoting the worker's message and code.
+    async fn expect<T: DeserializeOwned>(worker: &str, resp: reqwest::Response) -> Result<(T, bool), ApiError> {
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = resp.headers().get(header::RETRY_AFTER).and_then(|v| v.to_str().ok()?.parse().ok());
+            let text = resp.text().await.unwrap_or_default();
+            let body = serde_json::from_str::<WorkerError>(&text).ok();
+            if status.is_client_error() {
+                if let Some(err) = body.as_ref().and_then(|b| rejection(status, &b.error, retry_after)) { return Err(err); }
+            }
+            let why = body.map(|b| format!("{} ({})", b.error.message, b.error.code)).unwrap_or(text);
+            return Err(ApiError::Worker(format!("worker {} answered {}: {}", worker, status, why)));
+        }
+        let incomplete = resp.headers().get("x-search-incomplete").is_some_and(|v| v == "true");
+        let body = resp.json().await.map_err(|e| ApiError::Worker(format!("worker {} sent an unreadable answer: {}", worker, e)))?;
+        Ok((body, incomplete))
+    }
+
+    async fn call<T: DeserializeOwned>(&self, worker: &str, req: reqwest::RequestBuilder) -> Result<(T, bool), ApiError> {
+        Cluster::expect(worker, self.send(worker, req).await?).await
+    }
+
+    // Runs one request per shard at once and collects the answers in shard order.
+    async fn each_shard<T: DeserializeOwned>(&self, name: &str, db: &Sharded, path: &str, body: &impl Serialize) -> Result<(Vec<T>, bool), ApiError> {
+        let calls = db.shards.iter().enumerate().map(|(i, w)| {
+            self.call::<T>(w, self.client.post(format!("{}/db/{}/{}", w, shard_name(name, i), path)).json(body))
+        });
+        let mut out = Vec::with_capacity(db.shards.len());
+        let mut incomplete = false;
+        for res in join_all(calls).await {
+            let (t, partial) = res?;
+            out.push(t);
+            incomplete |= partial;
+        }
+        Ok((out, incomplete))
+    }
+}
+
+#[derive(Deserialize)]
+struct WorkerError { error: WorkerErrorDetail }
+
+#[derive(Deserialize)]
+struct WorkerErrorDetail { code: String, message: String }
+
+// Worker error codes that describe the client's request, passed on with the worker's status.
+const PASSED_THROUGH: [&str; 7] = ["bad_request", "invalid_body", "unknown_metric", "invalid_cursor", "dimension_mismatch", "too_large", "unsupported_media_type"];
+
+// The error to hand the client for a worker's 4xx, if it is the client's to fix or wait out. A
+// 401, 403, 404 or 409 from a worker means the worker key or the shard map is wrong; those stay 502s.
+fn rejection(status: StatusCode, detail: &WorkerErrorDetail, retry_after: Option<u64>) -> Option<ApiError> {
+    match detail.code.as_str() {
+        "rate_limited" => Some(ApiError::RateLimited { retry_after: retry_after.unwrap_or(1) }),
+        "too_many_searches" => Some(ApiError::TooManySearches),
+        code => PASSED_THROUGH.iter().find(|c| **c == code).map(|c| ApiError::WorkerRejected { status, code: c, message: detail.message.clone() }),
+    }
+}
+
+// Releases a name reserved by `create` when creation finishes or is abandoned.
+struct Creating<'a> { cluster: &'a Cluster, name: String }
+
+impl Drop for Creating<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut creating) = self.cluster.creating.lock() { creating.remove(&self.name); }
+    }
+}
+
+/// The coordinator's REST API: the database and vector routes of `vectra serve`, spread
+/// over the workers, with the same keys and error bodies.
+pub fn router(cluster: Arc<Cluster>, auth: Option<Arc<AuthConfig>>) -> Router {
+    let protect = |routes: Router<Arc<Cluster>>, scope| routes
+        .route_layer(middleware::from_fn_with_state(Guard { auth: auth.clone(), scope }, crate::auth::require));
+    let read = protect(Router::new()
+        .route("/db/:name/find", post(find))
+        .route("/db/:name/find_batch", post(find_batch))
+        .route("/db/:name/info", get(info))
+        .route("/db/:name/vectors/:id", get(get_vector)), Scope::Read);
+    let write = protect(Router::new()
+        .route("/db/:name/insert", post(insert))
+        .route("/db/:name/insert_batch", post(insert_batch)), Scope::Write);
+    let admin = protect(Router::new()
+        .route("/create", post(create))
+        .route("/db/:name", delete(drop_db))
+        .route("/status", get(status)), Scope::Admin);
+    let any = protect(Router::new()
+        .route("/dbs", get(list_dbs)), Scope::Any);
+    let public = Router::new()
+        .route("/healthz", get(server::healthz))
+        .route("/readyz", get(readyz));
+    let body_limit = cluster.limits.max_body_bytes;
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .merge(admin)
+        .merge(any)
+        .merge(public)
+        .layer(DefaultBodyLimit::max(body_limit))
+        .layer(TraceLayer::new_for_http()
+            .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+            .on_response(DefaultOnResponse::new().level(tracing::Level::INFO)))
+        .with_state(cluster)
+}
+
+#[derive(Deserialize)]
+struct CreateReq { name: String, dimension: usize, shards: Option<usize> }
+
+#[derive(Serialize)]
+struct ShardCreate<'a> { name: &'a str, dimension: usize }
+
+// Creates every shard before recording the database; shards made before a failure are dropped again.
+async fn create(State(cluster): State<Arc<Cluster>>, ApiJson(req): ApiJson<CreateReq>) -> Result<Json<server::OkResp>, ApiError> {
+    let cluster: &Cluster = &cluster;
+    ver::validate_name(&req.name)?;
+    let shards = req.shards.unwrap_or(cluster.shards);
+    if shards == 0 { return Err(ApiError::BadRequest("shards must be at least 1".to_string())); }
+    // reserve the name before any worker is asked, so two creates can't both build shards for it
+    let (_reserved, offset) = {
+        let dbs = cluster.dbs.lock()?;
+        let mut creating = cluster.creating.lock()?;
+        if dbs.contains_key(&req.name) || !creating.insert(req.name.clone()) { return Err(ApiError::DbExists(req.name)); }
+        // start each database on a different worker so single-shard databases spread out too
+        (Creating { cluster, name: req.name.clone() }, dbs.len() + creating.len() - 1)
+    };
+    let placement: Vec<String> = (0..shards).map(|i| cluster.workers[(offset + i) % cluster.workers.len()].clone()).collect();
+    let calls = placement.iter().enumerate().map(|(i, w)| {
+        let name = shard_name(&req.name, i);
+        let body = ShardCreate { name: &name, dimension: req.dimension };
+        let req = cluster.client.post(format!("{}/create", w)).json(&body);
+        async move { cluster.call::<serde_json::Value>(w, req).await }
+    });
+    let results = join_all(calls).await;
+    if let Some(err) = results.iter().position(|r| r.is_err()) {
+        let made = results.iter().enumerate().filter(|(_, r)| r.is_ok()).map(|(i, _)| (i, &placement[i]));
+        join_all(made.map(|(i, w)| cluster.send(w, cluster.client.delete(format!("{}/db/{}", w, shard_name(&req.name, i)))))).await;
+        return Err(results.into_iter().nth(err).and_then(Result::err).unwrap_or(ApiError::Internal("shard creation failed".to_string())));
+    }
+    let mut dbs = cluster.dbs.lock()?;
+    dbs.insert(req.name.clone(), Entry { db: Sharded { dimension: req.dimension, shards: placement, reserved_id: 0 }, next_id: 0 });
+    if let Err(e) = cluster.save(&dbs) {
+        dbs.remove(&req.name);
+        return Err(e.into());
+    }
+    cluster.located.lock()?.insert(req.name.clone(), (0..shards).map(|_| Located { complete: true, ..Default::default() }).collect());
+    tracing::info!(db = %req.name, shards, "created sharded database");
+    Ok(Json(server::OkResp { ok: true }))
+}
+
+async fn drop_db(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>) -> Result<Json<server::OkResp>, ApiError> {
+    let cluster: &Cluster = &cluster;
+    let db = cluster.get(&name)?;
+    let calls = db.shards.iter().enumerate().map(|(i, w)| {
+        let req = cluster.client.delete(format!("{}/db/{}", w, shard_name(&name, i)));
+        async move {
+            let resp = cluster.send(w, req).await?;
+            // already gone is fine
+            if resp.status() == StatusCode::NOT_FOUND { return Ok(()); }
+            Cluster::expect::<serde_json::Value>(w, resp).await.map(|_| ())
+        }
+    });
+    join_all(calls).await.into_iter().collect::<Result<Vec<()>, ApiError>>()?;
+    let mut dbs = cluster.dbs.lock()?;
+    dbs.remove(&name);
+    cluster.save(&dbs)?;
+    cluster.located.lock()?.remove(&name);
+    Ok(Json(server::OkResp { ok: true }))
+}
+
+fn check_row(db: &Sharded, row: &InsertReq) -> Result<(), ApiError> {
+    if row.values.len() != db.dimension { return Err(ApiError::DimensionMismatch { expected: db.dimension, actual: row.values.len() }); }
+    if row.meta.contains_key(ID_KEY) { return Err(ApiError::BadRequest(format!("metadata key '{}' is reserved for the cluster id", ID_KEY))); }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ShardRow<'a> { values: &'a [f64], meta: HashMap<&'a str, String> }
+
+impl<'a> ShardRow<'a> {
+    fn new(id: u64, row: &'a InsertReq) -> Self {
+        let mut meta: HashMap<&str, String> = row.meta.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+        meta.insert(ID_KEY, id.to_string());
+        ShardRow { values: &row.values, meta }
+    }
+}
+
+#[derive(Deserialize)]
+struct ShardInsertResp { id: usize, durability: String }
+
+#[derive(Serialize)]
+struct InsertResp { ok: bool, id: u64, shard: usize, durability: String }
+
+async fn insert(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>, ApiJson(row): ApiJson<InsertReq>) -> Result<Json<InsertResp>, ApiError> {
+    let db = cluster.get(&name)?;
+    check_row(&db, &row)?;
+    let id = cluster.assign_ids(&name, 1)?;
+    let shard = shard_of(id, db.shards.len());
+    let w = &db.shards[shard];
+    let req = cluster.client.post(format!("{}/db/{}/insert", w, shard_name(&name, shard))).json(&ShardRow::new(id, &row));
+    let (resp, _) = match cluster.call::<ShardInsertResp>(w, req).await {
+        Ok(done) => done,
+        Err(e) => { if matches!(e, ApiError::Worker(_)) { cluster.unsure(&name, &db, shard)?; } return Err(e); }
+    };
+    cluster.stored(&name, &db, shard, [(id, resp.id)])?;
+    Ok(Json(InsertResp { ok: true, id, shard, durability: resp.durability }))
+}
+
+#[derive(Deserialize)]
+struct ShardBatchResp { ids: Vec<Option<usize>>, errors: Vec<ShardRowError> }
+
+#[derive(Deserialize)]
+struct ShardRowError { row: usize, error: String }
+
+#[derive(Serialize)]
+struct InsertBatchResp { ok: bool, inserted: usize, ids: Vec<Option<u64>>, errors: Vec<RowError> }
+
+// Rows are checked here, given ids, grouped by shard and sent to the workers at once. A worker
+// that fails turns its rows into row errors; the other shards' rows still count.
+async fn insert_batch(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>, req: Request) -> Result<Json<InsertBatchResp>, ApiError> {
+    let cluster: &Cluster = &cluster;
+    let db = cluster.get(&name)?;
+    let rows = match Format::of_request(req.headers()) {
+        Format::Json => { let (parts, body) = req.into_parts(); server::read_insert_rows(Format::Json, &parts.headers, body, &cluster.limits).await? }
+        _ => return Err(ApiError::UnsupportedMediaType("the coordinator takes JSON or NDJSON batches".to_string())),
+    };
+    let mut ids: Vec<Option<u64>> = vec![None; rows.len()];
+    let mut errors = Vec::new();
+    let mut accepted = Vec::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        match row.and_then(|r| check_row(&db, &r).map(|()| r)) {
+            Ok(r) => accepted.push((i, r)),
+            Err(e) => errors.push(RowError { row: i, code: e.code(), error: e.to_string() }),
+        }
+    }
+    let first = if accepted.is_empty() { 0 } else { cluster.assign_ids(&name, accepted.len())? };
+    let mut by_shard: Vec<Vec<(usize, u64, InsertReq)>> = (0..db.shards.len()).map(|_| Vec::new()).collect();
+    for (n, (row, r)) in accepted.into_iter().enumerate() {
+        let id = first + n as u64;
+        by_shard[shard_of(id, db.shards.len())].push((row, id, r));
+    }
+    let calls = by_shard.iter().enumerate().filter(|(_, rows)| !rows.is_empty()).map(|(shard, rows)| {
+        let w = &db.shards[shard];
+        let body: Vec<ShardRow> = rows.iter().map(|(_, id, r)| ShardRow::new(*id, r)).collect();
+        let req = cluster.client.post(format!("{}/db/{}/insert_batch", w, shard_name(&name, shard))).json(&body);
+        async move { (shard, rows, cluster.call::<ShardBatchResp>(w, req).await) }
+    });
+    for (shard, rows, res) in join_all(calls).await {
+        if let Err(ApiError::Worker(_)) = &res { cluster.unsure(&name, &db, shard)?; }
+        match res {
+            Ok((resp, _)) => {
+                let mut stored = Vec::new();
+                for (i, (row, id, _)) in rows.iter().enumerate() {
+                    if let Some(local) = resp.ids.get(i).copied().flatten() { ids[*row] = Some(*id); stored.push((*id, local)); }
+                }
+                cluster.stored(&name, &db, shard, stored)?;
+                errors.extend(resp.errors.into_iter().filter_map(|e| rows.get(e.row).map(|(row, _, _)| RowError { row: *row, code: "worker_error", error: e.error })));
+            }
+            Err(e) => errors.extend(rows.iter().map(|(row, _, _)| RowError { row: *row, code: e.code(), error: e.to_string() })),
+        }
+    }
+    errors.sort_by_key(|e| e.row);
+    let inserted = ids.iter().filter(|id| id.is_some()).count();
+    Ok(Json(InsertBatchResp { ok: errors.is_empty(), inserted, ids, errors }))
+}
+
+// The search fields of `find`, passed to every shard unchanged.
+#[derive(Serialize, Deserialize)]
+struct SearchOpts { k: Option<usize>, f: Option<String>, radius: Option<f64>, max_results: Option<usize>, filter: Option<HashMap<String, String>>, timeout_ms: Option<u64>, on_timeout: Option<String> }
+
+impl SearchOpts {
+    // The checks `serve` makes, so a bad search is turned down before any worker is asked.
+    // Returns how many merged matches to keep: k, or in radius mode max_results (--max-k by default).
+    fn check(&self, limits: &Limits) -> Result<usize, ApiError> {
+        server::parse_metric(self.f.as_deref())?;
+        Ok(match server::bounded_mode(limits, self.k, self.radius, self.max_results)? {
+            SearchMode::TopK(k) => k,
+            SearchMode::Radius { max_results, .. } => max_results.unwrap_or(limits.max_k),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FindReq { values: Vec<f64>, #[serde(flatten)] opts: SearchOpts }
+
+#[derive(Serialize, Deserialize)]
+struct FindBatchReq { queries: Vec<Vec<f64>>, #[serde(flatten)] opts: SearchOpts }
+
+// Every shard ranks its own nearest; the overall nearest are the nearest of those. Shard-local
+// indexes are replaced by cluster ids.
+fn merge(lists: Vec<Vec<FindItem>>, keep: usize) -> Vec<FindItem> {
+    let mut all: Vec<FindItem> = lists.into_iter().flatten().map(|mut item| {
+        if let Some(id) = item.metadata.remove(ID_KEY).and_then(|id| id.parse().ok()) { item.index = id; }
+        item
+    }).collect();
+    all.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    all.truncate(keep);
+    all
+}
+
+fn search_response<T: Serialize>(body: T, incomplete: bool) -> Response {
+    let mut resp = Json(body).into_response();
+    if incomplete { resp.headers_mut().insert("x-search-incomplete", header::HeaderValue::from_static("true")); }
+    resp
+}
+
+async fn find(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>, ApiJson(req): ApiJson<FindReq>) -> Result<Response, ApiError> {
+    let db = cluster.get(&name)?;
+    let keep = req.opts.check(&cluster.limits)?;
+    if req.values.len() != db.dimension { return Err(ApiError::DimensionMismatch { expected: db.dimension, actual: req.values.len() }); }
+    let (lists, incomplete) = cluster.each_shard::<Vec<FindItem>>(&name, &db, "find", &req).await?;
+    Ok(search_response(merge(lists, keep), incomplete))
+}
+
+async fn find_batch(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>, ApiJson(req): ApiJson<FindBatchReq>) -> Result<Response, ApiError> {
+    let db = cluster.get(&name)?;
+    let keep = req.opts.check(&cluster.limits)?;
+    cluster.limits.check_batch("queries", req.queries.len())?;
+    if let Some(q) = req.queries.iter().find(|q| q.len() != db.dimension) {
+        return Err(ApiError::DimensionMismatch { expected: db.dimension, actual: q.len() });
+    }
+    let (per_shard, incomplete) = cluster.each_shard::<Vec<Vec<FindItem>>>(&name, &db, "find_batch", &req).await?;
+    let mut per_query: Vec<Vec<Vec<FindItem>>> = (0..req.queries.len()).map(|_| Vec::new()).collect();
+    for lists in per_shard {
+        for (q, list) in lists.into_iter().enumerate() {
+            if let Some(slot) = per_query.get_mut(q) { slot.push(list); }
+        }
+    }
+    Ok(search_response(per_query.into_iter().map(|lists| merge(lists, keep)).collect::<Vec<_>>(), incomplete))
+}
+
+#[derive(Deserialize)]
+struct VectorQuery { with_values: Option<bool> }
+
+#[derive(Deserialize)]
+struct ScrollPage { items: Vec<ScrollItem>, next_cursor: Option<String> }
+
+#[derive(Serialize, Deserialize)]
+struct ScrollItem { id: u64, #[serde(skip_serializing_if = "Option::is_none")] values: Option<Vec<f64>>, metadata: HashMap<String, String> }
+
+// The id's hash names the one shard to ask, and the shard's map of ids the index to read there.
+async fn get_vector(State(cluster): State<Arc<Cluster>>, ApiPath((name, id)): ApiPath<(String, u64)>, ApiQuery(q): ApiQuery<VectorQuery>) -> Result<Json<ScrollItem>, ApiError> {
+    let db = cluster.get(&name)?;
+    let shard = shard_of(id, db.shards.len());
+    let place = match cluster.place(&name, shard, id)? {
+        Place::Unknown => { cluster.index_shard(&name, &db, shard).await?; cluster.place(&name, shard, id)? }
+        known => known,
+    };
+    let Place::At(local) = place else { return Err(ApiError::VectorNotFound(id as usize)) };
+    let w = &db.shards[shard];
+    let req = cluster.client.get(format!("{}/db/{}/vectors/{}", w, shard_name(&name, shard), local)).query(&[("with_values", q.with_values.unwrap_or(true))]);
+    let (mut item, _) = cluster.call::<ScrollItem>(w, req).await?;
+    if item.metadata.remove(ID_KEY).as_deref() != Some(id.to_string().as_str()) {
+        return Err(ApiError::Worker(format!("worker {} no longer holds id {} at index {} of shard {}", w, id, local, shard)));
+    }
+    item.id = id;
+    Ok(Json(item))
+}
+
+#[derive(Deserialize)]
+struct ShardInfo { count: usize, metadata_schema: HashMap<String, Vec<String>> }
+
+#[derive(Serialize)]
+struct ShardStatus { shard: usize, worker: String, db: String, count: usize }
+
+#[derive(Serialize)]
+struct InfoResp { name: String, dimension: usize, count: usize, shards: Vec<ShardStatus>, metadata_schema: HashMap<String, Vec<String>> }
+
+async fn info(State(cluster): State<Arc<Cluster>>, ApiPath(name): ApiPath<String>) -> Result<Json<InfoResp>, ApiError> {
+    let db = cluster.get(&name)?;
+    let calls = db.shards.iter().enumerate().map(|(i, w)| cluster.call::<ShardInfo>(w, cluster.client.get(format!("{}/db/{}/info", w, shard_name(&name, i)))));
+    let mut shards = Vec::with_capacity(db.shards.len());
+    let mut schema: HashMap<String, HashSet<String>> = HashMap::new();
+    for (i, res) in join_all(calls).await.into_iter().enumerate() {
+        let (info, _) = res?;
+        for (k, types) in info.metadata_schema.into_iter().filter(|(k, _)| k != ID_KEY) { schema.entry(k).or_default().extend(types); }
+        shards.push(ShardStatus { shard: i, worker: db.shards[i].clone(), db: shard_name(&name, i), count: info.count });
+    }
+    let metadata_schema = schema.into_iter().map(|(k, set)| { let mut v: Vec<String> = set.into_iter().collect(); v.sort(); (k, v) }).collect();
+    Ok(Json(InfoResp { name, dimension: db.dimension, count: shards.iter().map(|s| s.count).sum(), shards, metadata_schema }))
+}
+
+#[derive(Deserialize)]
+struct Listed { name: String, count: usize }
+
+#[derive(Serialize)]
+struct DbSummary { name: String, dimension: usize, shards: usize, count: usize }
+
+// One `/dbs` call per worker covers every shard on it.
+async fn list_dbs(State(cluster): State<Arc<Cluster>>, key: Option<Extension<Arc<ApiKey>>>) -> Result<Json<Vec<DbSummary>>, ApiError> {
+    let dbs: Vec<(String, Sharded)> = cluster.dbs.lock()?.iter()
+        .filter(|(name, _)| key.as_ref().is_none_or(|Extension(k)| k.can_read(name)))
+        .map(|(name, e)| (name.clone(), e.db.clone()))
+        .collect();
+    let mut workers: Vec<&String> = dbs.iter().flat_map(|(_, db)| &db.shards).collect();
+    workers.sort();
+    workers.dedup();
+    let calls = workers.iter().map(|w| cluster.call::<Vec<Listed>>(w, cluster.client.get(format!("{}/dbs", w))));
+    let mut counts: HashMap<(&str, String), usize> = HashMap::new();
+    for (w, res) in workers.iter().zip(join_all(calls).await) {
+        for l in res?.0 { counts.insert((w.as_str(), l.name), l.count); }
+    }
+    let out = dbs.iter().map(|(name, db)| DbSummary {
+        name: name.clone(),
+        dimension: db.dimension,
+        shards: db.shards.len(),
+        count: db.shards.iter().enumerate().map(|(i, w)| counts.get(&(w.as_str(), shard_name(name, i))).copied().unwrap_or(0)).sum(),
+    }).collect();
+    Ok(Json(out))
+}
+
+#[derive(Serialize)]
+struct WorkerStatus { url: String, ok: bool, #[serde(skip_serializing_if = "Option::is_none")] error: Option<String> }
+
+async fn probe(cluster: &Cluster) -> Vec<WorkerStatus> {
+    let calls = cluster.workers.iter().map(|w| async move {
+        let res = cluster.call::<serde_json::Value>(w, cluster.client.get(format!("{}/healthz", w)).timeout(Duration::from_secs(5))).await;
+        WorkerStatus { url: w.clone(), ok: res.is_ok(), error: res.err().map(|e| e.to_string()) }
+    });
+    join_all(calls).await
+}
+
+#[derive(Serialize)]
+struct ReadyResp { ready: bool, problems: Vec<String> }
+
+async fn readyz(State(cluster): State<Arc<Cluster>>) -> (StatusCode, Json<ReadyResp>) {
+    let problems: Vec<String> = probe(&cluster).await.into_iter().filter_map(|w| w.error).collect();
+    let status = if problems.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ReadyResp { ready: problems.is_empty(), problems }))
+}
+
+#[derive(Serialize)]
+struct StatusResp { workers: Vec<WorkerStatus>, default_shards: usize, dbs: BTreeMap<String, Sharded> }
+
+async fn status(State(cluster): State<Arc<Cluster>>) -> Result<Json<StatusResp>, ApiError> {
+    let dbs = cluster.dbs.lock()?.iter().map(|(k, e)| (k.clone(), e.db.clone())).collect();
+    Ok(Json(StatusResp { workers: probe(&cluster).await, default_shards: cluster.shards, dbs }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(index: usize, distance: f64, id: Option<u64>) -> FindItem {
+        let metadata = id.map(|id| (ID_KEY.to_string(), id.to_string())).into_iter().collect();
+        FindItem { index, distance, values: Vec::new(), metadata }
+    }
+
+    #[test]
+    fn shard_of_spreads_sequential_ids() {
+        for shards in [2, 3, 7] {
+            let mut counts = vec![0usize; shards];
+            for id in 0..30_000 { counts[shard_of(id, shards)] += 1; }
+            let even = 30_000 / shards;
+            for c in counts { assert!(c.abs_diff(even) < even / 20, "{} shards: {} ids on one, expected about {}", shards, c, even); }
+        }
+        assert!((0..100).all(|id| shard_of(id, 1) == 0));
+    }
+
+    #[test]
+    fn shard_of_is_stable() {
+        // stored vectors are found by these; a change here strands them
+        assert_eq!((0..8).map(|id| shard_of(id, 4)).collect::<Vec<_>>(), [0, 0, 3, 2, 1, 1, 3, 1]);
+        assert_eq!(shard_of(12345, 5), 3);
+    }
+
+    #[test]
+    fn merge_orders_truncates_and_maps_ids() {
+        let lists = vec![
+            vec![item(0, 0.1, Some(40)), item(1, 0.5, Some(41))],
+            vec![item(0, 0.2, Some(7)), item(3, 0.3, Some(9)), item(4, 0.9, Some(12))],
+            vec![],
+        ];
+        let merged = merge(lists, 3);
+        assert_eq!(merged.iter().map(|i| i.distance).collect::<Vec<_>>(), [0.1, 0.2, 0.3]);
+        assert_eq!(merged.iter().map(|i| i.index).collect::<Vec<_>>(), [40, 7, 9]);
+        assert!(merged.iter().all(|i| !i.metadata.contains_key(ID_KEY)), "_id is internal");
+    }
+
+    #[test]
+    fn merge_keeps_shard_index_without_an_id() {
+        let merged = merge(vec![vec![item(5, 1.0, None)], vec![item(2, 0.5, Some(100))]], 10);
+        assert_eq!(merged.iter().map(|i| i.index).collect::<Vec<_>>(), [100, 5]);
+    }
+}
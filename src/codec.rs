@@ -0,0 +1,204 @@
+use std::str::FromStr;
+use axum::{async_trait, body::Bytes, extract::{FromRequest, FromRequestParts, Request}, http::{header, request::Parts, HeaderMap, Uri}, response::{IntoResponse, Response}, Json};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::error::{ApiError, ApiJson};
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const RAW_F64: &str = "application/octet-stream";
+
+/// Body encodings the REST API speaks, picked by `Content-Type` for requests and `Accept`
+/// for responses. JSON is the default for both.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MsgPack,
+    /// Packed little-endian f64 with no framing; only vector data travels this way
+    RawF64,
+}
+
+impl Format {
+    // Media type without parameters; unknown types map to None.
+    fn from_mime(mime: &str) -> Option<Format> {
+        let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MsgPack),
+            RAW_F64 => Some(Format::RawF64),
+            _ => None,
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MsgPack => MSGPACK,
+            Format::RawF64 => RAW_F64,
+        }
+    }
+
+    /// The request body's format; anything unrecognised goes to the JSON extractor, which
+    /// reports it as before.
+    pub fn of_request(headers: &HeaderMap) -> Format {
+        headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).and_then(Format::from_mime).unwrap_or(Format::Json)
+    }
+}
+
+/// Decodes little-endian f64s, rejecting a ragged tail and non-finite values.
+pub fn floats(bytes: &[u8]) -> Result<Vec<f64>, ApiError> {
+    if !bytes.len().is_multiple_of(8) {
+        return Err(ApiError::InvalidBody(format!("raw body is {} bytes, not a whole number of f64 values", bytes.len())));
+    }
+    let values: Vec<f64> = bytes.chunks_exact(8).map(|c| f64::from_le_bytes(c.try_into().unwrap_or_default())).collect();
+    if let Some(i) = values.iter().position(|v| !v.is_finite()) {
+        return Err(ApiError::InvalidBody(format!("value {} is not finite", i)));
+    }
+    Ok(values)
+}
+
+/// Request types the negotiated extractor can build. Types that carry vector data override
+/// `from_raw` to accept a raw f64 body, taking their other fields from the query string.
+pub trait Decode: DeserializeOwned {
+    fn from_raw(_values: Vec<f64>, _query: &[(String, String)]) -> Result<Self, ApiError> {
+        Err(ApiError::UnsupportedMediaType(format!("this endpoint takes JSON or MessagePack, not {}", RAW_F64)))
+    }
+}
+
+/// Parses one query-string parameter for a raw request.
+pub fn param<T: FromStr>(query: &[(String, String)], key: &str) -> Result<Option<T>, ApiError> {
+    match query.iter().rev().find(|(k, _)| k == key) {
+        Some((_, v)) => v.parse().map(Some).map_err(|_| ApiError::BadRequest(format!("invalid query parameter {}={}", key, v))),
+        None => Ok(None),
+    }
+}
+
+pub fn query_pairs(uri: &Uri) -> Result<Vec<(String, String)>, ApiError> {
+    serde_urlencoded::from_str(uri.query().unwrap_or("")).map_err(|e| ApiError::BadRequest(format!("invalid query string: {}", e)))
+}
+
+// Buffers the body under `DefaultBodyLimit`, mapping an oversized body to 413.
+pub async fn body_bytes<S: Send + Sync>(req: Request, state: &S) -> Result<Bytes, ApiError> {
+    Bytes::from_request(req, state).await.map_err(|r| {
+        if r.status() == axum::http::StatusCode::PAYLOAD_TOO_LARGE { ApiError::TooLarge(r.body_text()) } else { ApiError::BadRequest(r.body_text()) }
+    })
+}
+
+/// A request body in JSON, MessagePack or (where `T` supports it) raw f64.
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<T: Decode, S: Send + Sync> FromRequest<S> for Payload<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, ApiError> {
+        match Format::of_request(req.headers()) {
+            Format::Json => ApiJson::<T>::from_request(req, state).await.map(|ApiJson(t)| Payload(t)),
+            Format::MsgPack => {
+                let bytes = body_bytes(req, state).await?;
+                rmp_serde::from_slice(&bytes).map(Payload).map_err(|e| ApiError::InvalidBody(e.to_string()))
+            }
+            Format::RawF64 => {
+                let query = query_pairs(req.uri())?;
+                let bytes = body_bytes(req, state).await?;
+                T::from_raw(floats(&bytes)?, &query).map(Payload)
+            }
+        }
+    }
+}
+
+/// The response format picked from `Accept` among JSON and MessagePack: the highest-`q` media
+/// range this endpoint can produce, JSON when there is no `Accept` header, and `406` when it
+/// names nothing the endpoint speaks. Negotiation runs as an extractor, so a request that
+/// can't be answered is refused before the handler changes anything.
+pub struct Accept(pub Format);
+
+/// Like `Accept`, for endpoints that can also answer with a single vector as raw f64.
+pub struct AcceptVector(pub Format);
+
+const REPLY_FORMATS: [Format; 2] = [Format::Json, Format::MsgPack];
+const VECTOR_FORMATS: [Format; 3] = [Format::Json, Format::MsgPack, Format::RawF64];
+
+// The `q` parameter of a media range, 1 when absent; None when it doesn't parse.
+fn quality(range: &str) -> Option<f32> {
+    let q = range.split(';').skip(1)
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"));
+    match q {
+        Some((_, v)) => v.trim().parse().ok().filter(|q: &f32| (0.0..=1.0).contains(q)),
+        None => Some(1.0),
+    }
+}
+
+// `type/subtype` outranks `type/*`, which outranks `*/*`, when their `q` values tie.
+fn specificity(range: &str) -> u8 {
+    match range.split(';').next().unwrap_or("").trim() {
+        "*/*" => 0,
+        r if r.ends_with("/*") => 1,
+        _ => 2,
+    }
+}
+
+// Picks the first of `supported` that the best-ranked acceptable media range allows.
+fn negotiate(headers: &HeaderMap, supported: &[Format]) -> Result<Format, ApiError> {
+    let ranges: Vec<&str> = headers.get_all(header::ACCEPT).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter(|r| !r.trim().is_empty())
+        .collect();
+    if ranges.is_empty() { return Ok(Format::Json); }
+    // q=0 refuses a type outright, even where a wildcard would otherwise allow it
+    let refused: Vec<Format> = ranges.iter().filter(|r| quality(r) == Some(0.0) && specificity(r) == 2).filter_map(|r| Format::from_mime(r)).collect();
+    let allowed = |f: &Format| supported.contains(f) && !refused.contains(f);
+    let mut ranked: Vec<(f32, u8, &str)> = ranges.iter().filter_map(|r| quality(r).filter(|q| *q > 0.0).map(|q| (q, specificity(r), *r))).collect();
+    // the sort is stable, so header order breaks ties
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+    let found = ranked.iter().find_map(|(_, _, r)| match r.split(';').next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+        "*/*" | "application/*" => supported.iter().copied().find(allowed),
+        essence => Format::from_mime(essence).filter(allowed),
+    });
+    found.ok_or_else(|| {
+        let names: Vec<&str> = supported.iter().map(|f| f.mime()).collect();
+        ApiError::NotAcceptable(format!("this endpoint answers with {}, and Accept allows none of them", names.join(", ")))
+    })
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Accept {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, ApiError> {
+        negotiate(&parts.headers, &REPLY_FORMATS).map(Accept)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AcceptVector {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, ApiError> {
+        negotiate(&parts.headers, &VECTOR_FORMATS).map(AcceptVector)
+    }
+}
+
+/// A response body in the accepted format. Raw f64 is refused here; handlers that can
+/// answer with bare vector data take `AcceptVector` and do so before building a `Reply`.
+pub struct Reply<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Reply<T> {
+    fn into_response(self) -> Response {
+        match self.0 {
+            Format::Json => Json(self.1).into_response(),
+            // named fields so MessagePack maps mirror the JSON objects
+            Format::MsgPack => match rmp_serde::to_vec_named(&self.1) {
+                Ok(bytes) => ([(header::CONTENT_TYPE, MSGPACK)], bytes).into_response(),
+                Err(e) => ApiError::Internal(e.to_string()).into_response(),
+            },
+            Format::RawF64 => ApiError::NotAcceptable(format!("{} responses are only available for single vectors; accept JSON or MessagePack", RAW_F64)).into_response(),
+        }
+    }
+}
+
+/// Packs vector data as little-endian f64.
+pub fn raw_response(values: &[f64]) -> Response {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    ([(header::CONTENT_TYPE, RAW_F64)], bytes).into_response()
+}
@@ -0,0 +1,209 @@
+use std::io;
+use serde::Serialize;
+use axum::{extract::{rejection::{JsonRejection, PathRejection, QueryRejection}, FromRequest, FromRequestParts}, http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+
+/// Errors returned by REST handlers. Each variant has a stable `code` that clients can
+/// branch on; the message is for humans and may change.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("invalid request body: {0}")]
+    InvalidBody(String),
+    #[error("unknown metric: {0}")]
+    UnknownMetric(String),
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
+    #[error("database not found: {0}")]
+    DbNotFound(String),
+    #[error("vector not found: {0}")]
+    VectorNotFound(usize),
+    #[error("webhook not found: {0}")]
+    WebhookNotFound(String),
+    #[error("database already exists: {0}")]
+    DbExists(String),
+    #[error("database {0} is being created, dropped, renamed or copied, retry later")]
+    Busy(String),
+    #[error("dimension mismatch: db={expected}, input={actual}")]
+    DimensionMismatch { expected: usize, actual: usize },
+    #[error("missing or invalid API key")]
+    Unauthorized,
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("this server is a read-only follower of {0}; send writes to the leader")]
+    ReadOnly(String),
+    #[error("{0}")]
+    TooLarge(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{0}")]
+    NotAcceptable(String),
+    #[error("rate limit exceeded, retry in {retry_after}s")]
+    RateLimited { retry_after: u64 },
+    #[error("too many concurrent searches, retry later")]
+    TooManySearches,
+    #[error("search did not finish within {0} ms")]
+    Timeout(u128),
+    #[error("cache is full of unflushed data, retry later")]
+    CacheFull,
+    #[error("{0}")]
+    Worker(String),
+    /// A worker turned down a request it was passed as invalid; its status and code are kept
+    #[error("{message}")]
+    WorkerRejected { status: StatusCode, code: &'static str, message: String },
+    #[error("lock poisoned")]
+    Lock,
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::UnknownMetric(_) => "unknown_metric",
+            ApiError::InvalidCursor(_) => "invalid_cursor",
+            ApiError::DbNotFound(_) => "db_not_found",
+            ApiError::VectorNotFound(_) => "vector_not_found",
+            ApiError::WebhookNotFound(_) => "webhook_not_found",
+            ApiError::DbExists(_) => "db_exists",
+            ApiError::Busy(_) => "db_busy",
+            ApiError::DimensionMismatch { .. } => "dimension_mismatch",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::ReadOnly(_) => "read_only",
+            ApiError::TooLarge(_) => "too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::NotAcceptable(_) => "not_acceptable",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::TooManySearches => "too_many_searches",
+            ApiError::Timeout(_) => "timeout",
+            ApiError::CacheFull => "cache_full",
+            ApiError::Worker(_) => "worker_error",
+            ApiError::WorkerRejected { code, .. } => code,
+            ApiError::Lock | ApiError::Internal(_) => "internal",
+            ApiError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => "db_not_found",
+                io::ErrorKind::AlreadyExists => "db_exists",
+                io::ErrorKind::InvalidInput => "bad_request",
+                _ => "io",
+            },
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::UnknownMetric(_) | ApiError::InvalidCursor(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidBody(_) | ApiError::DimensionMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::DbNotFound(_) | ApiError::VectorNotFound(_) | ApiError::WebhookNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::DbExists(_) | ApiError::Busy(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::ReadOnly(_) => StatusCode::FORBIDDEN,
+            ApiError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::RateLimited { .. } | ApiError::TooManySearches => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::CacheFull => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Worker(_) => StatusCode::BAD_GATEWAY,
+            ApiError::WorkerRejected { status, .. } => *status,
+            ApiError::Lock | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        }
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for ApiError {
+    fn from(_: std::sync::PoisonError<T>) -> Self { ApiError::Lock }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(r: JsonRejection) -> Self {
+        // well-formed JSON with the wrong shape is 422, an oversized body 413, anything else
+        // (syntax, content type) is 400
+        match r {
+            JsonRejection::JsonDataError(_) => ApiError::InvalidBody(r.body_text()),
+            _ if r.status() == StatusCode::PAYLOAD_TOO_LARGE => ApiError::TooLarge(r.body_text()),
+            _ => ApiError::BadRequest(r.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(r: PathRejection) -> Self { ApiError::BadRequest(r.body_text()) }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(r: QueryRejection) -> Self { ApiError::BadRequest(r.body_text()) }
+}
+
+/// Body of every error response.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorBody { pub error: ErrorDetail }
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorDetail {
+    /// Stable machine-readable code, e.g. `db_not_found`
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody { error: ErrorDetail { code: self.code(), message: self.to_string() } };
+        let mut resp = (self.status(), Json(body)).into_response();
+        match self {
+            ApiError::Unauthorized => { resp.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer")); }
+            ApiError::RateLimited { retry_after } => { resp.headers_mut().insert(header::RETRY_AFTER, retry_after.into()); }
+            _ => {}
+        }
+        resp
+    }
+}
+
+/// gRPC callers get the closest status code, with the REST error code in the
+/// `vectra-error-code` trailer.
+impl From<ApiError> for tonic::Status {
+    fn from(e: ApiError) -> Self {
+        use tonic::{metadata::{MetadataMap, MetadataValue}, Code};
+        let code = match e.status() {
+            _ if matches!(e, ApiError::Busy(_)) => Code::Aborted,
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY | StatusCode::UNSUPPORTED_MEDIA_TYPE | StatusCode::NOT_ACCEPTABLE => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::AlreadyExists,
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => Code::Unavailable,
+            StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+        let mut md = MetadataMap::new();
+        md.insert("vectra-error-code", MetadataValue::from_static(e.code()));
+        if let ApiError::RateLimited { retry_after } = e { md.insert("retry-after", retry_after.into()); }
+        tonic::Status::with_metadata(code, e.to_string(), md)
+    }
+}
+
+/// `Json` extractor whose rejections are reported as `ApiError` JSON bodies.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// `Path` extractor whose rejections are reported as `ApiError` JSON bodies.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// `Query` extractor whose rejections are reported as `ApiError` JSON bodies.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
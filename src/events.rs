@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use axum::{extract::State, http::HeaderMap, response::sse::{self, KeepAlive, Sse}, Extension};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
+use utoipa::{IntoParams, ToSchema};
+use crate::auth::ApiKey;
+use crate::error::{ApiError, ApiQuery};
+use crate::server::AppState;
+
+// Sequence numbers are reserved on disk this many at a time, so a restart (even after a crash)
+// resumes above anything already handed out without writing the file on every event.
+const SEQ_BLOCK: u64 = 1024;
+
+/// One change to a database, as streamed by `GET /events`.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Event {
+    /// Increases by one per event and never repeats, across restarts too
+    pub seq: u64,
+    pub time: DateTime<Utc>,
+    pub db: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// Vectors `first_id..first_id + count` were added; `total` is the size afterwards
+    Insert { first_id: usize, count: usize, total: usize },
+    Create { dimension: usize },
+    Drop,
+    Rename { to: String },
+    Clone { to: String },
+    /// A flush wrote `count` vectors to disk
+    Flush { count: usize },
+}
+
+/// Every `type` an event can have.
+pub const EVENT_TYPES: [&str; 6] = ["insert", "create", "drop", "rename", "clone", "flush"];
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Insert { .. } => "insert",
+            EventKind::Create { .. } => "create",
+            EventKind::Drop => "drop",
+            EventKind::Rename { .. } => "rename",
+            EventKind::Clone { .. } => "clone",
+            EventKind::Flush { .. } => "flush",
+        }
+    }
+}
+
+/// The change feed: a bounded buffer of recent events for clients resuming from a sequence
+/// number, plus a broadcast channel for live subscribers.
+pub struct EventLog {
+    inner: Mutex<Inner>,
+    live: broadcast::Sender<Arc<Event>>,
+    closed: watch::Sender<bool>,
+    capacity: usize,
+    seq_file: Option<PathBuf>,
+}
+
+struct Inner { buffer: VecDeque<Arc<Event>>, next: u64, reserved: u64 }
+
+// Events replayed to a subscriber. `reset` is set when some events after the requested
+// sequence are no longer buffered, so the client has to re-read whatever it caches.
+struct Replay { events: Vec<Arc<Event>>, reset: Option<u64> }
+
+impl EventLog {
+    /// A log that keeps the last `capacity` events and doesn't persist sequence numbers.
+    pub fn memory(capacity: usize) -> Self {
+        EventLog::with_start(capacity, 1, None)
+    }
+
+    /// A log whose sequence numbers continue from those reserved in `<dir>/events.seq`.
+    pub fn open(dir: &str, capacity: usize) -> io::Result<Self> {
+        let path = PathBuf::from(dir).join("events.seq");
+        let start = match fs::read_to_string(&path) {
+            Ok(s) => s.trim().parse::<u64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(e),
+        };
+        fs::create_dir_all(dir)?;
+        Ok(EventLog::with_start(capacity, start, Some(path)))
+    }
+
+    fn with_start(capacity: usize, start: u64, seq_file: Option<PathBuf>) -> Self {
+        let (live, _) = broadcast::channel(capacity.clamp(16, 4096));
+        let reserved = if seq_file.is_some() { start } else { u64::MAX };
+        EventLog { inner: Mutex::new(Inner { buffer: VecDeque::new(), next: start, reserved }), live, closed: watch::channel(false).0, capacity, seq_file }
+    }
+
+    /// Assigns the next sequence number and delivers the event. Callers publish while still
+    /// holding the database's write lock so events for one database are in the order applied.
+    pub fn publish(&self, db: &str, kind: EventKind) {
+        let Ok(mut inner) = self.inner.lock() else { return };
+        if inner.next >= inner.reserved {
+            if let Some(path) = &self.seq_file {
+                let reserved = inner.next + SEQ_BLOCK;
+                match fs::write(path, reserved.to_string()) {
+                    Ok(()) => inner.reserved = reserved,
+                    // keep going: numbers stay monotonic in this process, only a restart could repeat them
+                    Err(e) => tracing::error!(error = %e, "could not reserve event sequence numbers"),
+                }
+            }
+        }
+        let event = Arc::new(Event { seq: inner.next, time: Utc::now(), db: db.to_string(), kind });
+        inner.next += 1;
+        if self.capacity > 0 {
+            if inner.buffer.len() == self.capacity { inner.buffer.pop_front(); }
+            inner.buffer.push_back(event.clone());
+        }
+        // no subscribers is not an error
+        let _ = self.live.send(event);
+    }
+
+    /// Sequence number of the latest event; 0 before the first one.
+    pub fn head(&self) -> u64 {
+        self.inner.lock().map(|i| i.next - 1).unwrap_or(0)
+    }
+
+    /// Ends every open subscription, so graceful shutdown isn't held up by idle streams.
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    // Buffered events after `after`. A gap before the oldest buffered event (or a sequence
+    // this log never issued) is reported as a reset at the last sequence the client can skip to.
+    fn replay(inner: &Inner, after: u64) -> Replay {
+        let head = inner.next - 1;
+        let oldest = inner.buffer.front().map_or(inner.next, |e| e.seq);
+        if after > head || after + 1 < oldest {
+            return Replay { events: inner.buffer.iter().cloned().collect(), reset: Some(oldest - 1) };
+        }
+        Replay { events: inner.buffer.iter().filter(|e| e.seq > after).cloned().collect(), reset: None }
+    }
+
+    /// Follows the log from just after `after`, or from now when it is `None`, until the log
+    /// is closed. Subscribing and snapshotting the buffer share one lock, so nothing falls between.
+    pub fn follow(self: &Arc<Self>, after: Option<u64>) -> Result<Feed, ApiError> {
+        let inner = self.inner.lock()?;
+        let rx = self.live.subscribe();
+        let head = inner.next - 1;
+        let replay = match after {
+            Some(after) => EventLog::replay(&inner, after),
+            None => Replay { events: Vec::new(), reset: None },
+        };
+        let mut pending: VecDeque<Item> = replay.reset.map(Item::Reset).into_iter().collect();
+        pending.extend(replay.events.into_iter().map(Item::Event));
+        Ok(Feed { log: self.clone(), rx, closed: self.closed.subscribe(), pending, last: after.unwrap_or(head) })
+    }
+
+    fn since(&self, after: u64) -> Replay {
+        match self.inner.lock() {
+            Ok(inner) => EventLog::replay(&inner, after),
+            Err(_) => Replay { events: Vec::new(), reset: Some(after) },
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FeedQuery {
+    /// Replay buffered events after this sequence number first; `Last-Event-ID` takes precedence
+    since: Option<u64>,
+    /// Comma-separated database names; all readable databases when absent
+    db: Option<String>,
+    /// Comma-separated event types, e.g. `insert,flush`
+    types: Option<String>,
+}
+
+struct Filter { key: Option<Arc<ApiKey>>, dbs: Option<Vec<String>>, types: Option<Vec<String>> }
+
+impl Filter {
+    fn matches(&self, e: &Event) -> bool {
+        let list = |l: &Option<Vec<String>>, v: &str| l.as_ref().is_none_or(|l| l.iter().any(|x| x == v));
+        self.key.as_ref().is_none_or(|k| k.can_read(&e.db)) && list(&self.dbs, &e.db) && list(&self.types, e.kind.name())
+    }
+}
+
+fn split(list: Option<String>) -> Option<Vec<String>> {
+    list.map(|l| l.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+}
+
+/// What a `Feed` yields: the next event, or notice that events up to a sequence were missed.
+pub enum Item { Event(Arc<Event>), Reset(u64) }
+
+fn to_sse(item: Item) -> sse::Event {
+    match item {
+        Item::Event(e) => sse::Event::default()
+            .id(e.seq.to_string())
+            .event(e.kind.name())
+            .json_data(&*e)
+            .unwrap_or_else(|err| sse::Event::default().event("error").data(err.to_string())),
+        Item::Reset(seq) => sse::Event::default().id(seq.to_string()).event("reset").data(format!("{{\"seq\":{}}}", seq)),
+    }
+}
+
+/// One subscriber's position in the log.
+pub struct Feed {
+    log: Arc<EventLog>,
+    rx: broadcast::Receiver<Arc<Event>>,
+    closed: watch::Receiver<bool>,
+    pending: VecDeque<Item>,
+    last: u64,
+}
+
+impl Feed {
+    /// The next item in sequence order; `None` once the log is closed.
+    pub async fn next(&mut self) -> Option<Item> {
+        loop {
+            if let Some(item) = self.pop() { return Some(item); }
+            if *self.closed.borrow() { return None; }
+            tokio::select! {
+                _ = self.closed.changed() => return None,
+                r = self.rx.recv() => match r {
+                    Ok(e) => self.pending.push_back(Item::Event(e)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.catch_up(),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+
+    /// The next item already published, without waiting and whether or not the log is closed;
+    /// `None` when there is nothing more for now.
+    pub fn try_next(&mut self) -> Option<Item> {
+        loop {
+            if let Some(item) = self.pop() { return Some(item); }
+            match self.rx.try_recv() {
+                Ok(e) => self.pending.push_back(Item::Event(e)),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => self.catch_up(),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Item> {
+        while let Some(item) = self.pending.pop_front() {
+            match item {
+                Item::Event(e) if e.seq <= self.last => {}
+                Item::Event(e) => { self.last = e.seq; return Some(Item::Event(e)); }
+                Item::Reset(seq) => { self.last = seq; return Some(Item::Reset(seq)); }
+            }
+        }
+        None
+    }
+
+    // This subscriber fell behind the channel; catch up from the buffer instead.
+    fn catch_up(&mut self) {
+        let replay = self.log.since(self.last);
+        self.pending.extend(replay.reset.map(Item::Reset));
+        self.pending.extend(replay.events.into_iter().map(Item::Event));
+    }
+}
+
+/// Streams change events as server-sent events. Each event's SSE id is its sequence number,
+/// so `EventSource` reconnects resume where they left off. Events for databases the caller's
+/// key can't read are left out.
+#[utoipa::path(get, path = "/events", tag = "databases", params(FeedQuery),
+    responses((status = 200, description = "An SSE stream; the event name is the type and the data is the event as JSON. A `reset` event means events were missed and cached state should be re-read.", body = Event, content_type = "text/event-stream")))]
+pub(crate) async fn subscribe(State(state): State<AppState>, key: Option<Extension<Arc<ApiKey>>>, headers: HeaderMap, ApiQuery(q): ApiQuery<FeedQuery>) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, ApiError> {
+    let resume = match headers.get("last-event-id").and_then(|v| v.to_str().ok()) {
+        Some(id) => Some(id.trim().parse::<u64>().map_err(|_| ApiError::BadRequest(format!("invalid Last-Event-ID '{}'", id)))?),
+        None => q.since,
+    };
+    let feed = state.events().follow(resume)?;
+    let filter = Filter { key: key.map(|Extension(k)| k), dbs: split(q.db), types: split(q.types) };
+    let stream = futures_util::stream::unfold((feed, filter), |(mut feed, filter)| async move {
+        loop {
+            match feed.next().await? {
+                Item::Event(e) if !filter.matches(&e) => continue,
+                item => return Some((Ok(to_sse(item)), (feed, filter))),
+            }
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,210 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{extract::ConnectInfo, Router};
+use tonic::{metadata::MetadataMap, Request, Response, Status, Streaming};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use crate::auth::{ApiKey, AuthConfig, Scope};
+use crate::error::ApiError;
+use crate::limits;
+use crate::server::{may_create, AppState, BatchOutcome, FindItem, InsertReq, SearchParams};
+
+pub mod pb {
+    tonic::include_proto!("vectra.v1");
+}
+
+use pb::vectra_server::{Vectra, VectraServer};
+
+/// The gRPC service from `proto/vectra.proto`, served on its own port by `serve --grpc-addr`.
+/// It shares the REST server's state, so both see the same cache, keys and limits.
+pub fn router(state: AppState, auth: Option<Arc<AuthConfig>>) -> Router {
+    let max_message = state.limits().max_body_bytes;
+    let svc = VectraServer::new(Service { state, auth }).max_decoding_message_size(max_message);
+    tonic::service::Routes::new(svc)
+        .into_axum_router()
+        .layer(TraceLayer::new_for_grpc()
+            .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+            .on_response(DefaultOnResponse::new().level(tracing::Level::INFO)))
+}
+
+struct Service { state: AppState, auth: Option<Arc<AuthConfig>> }
+
+// Same token sources as REST: `authorization: Bearer <key>` or `x-api-key`.
+fn token(md: &MetadataMap) -> Option<&str> {
+    if let Some(v) = md.get("authorization").and_then(|v| v.to_str().ok()) {
+        return v.strip_prefix("Bearer ").map(str::trim);
+    }
+    md.get("x-api-key").and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+// Chunks of a stream are committed as they fill, so one that fails after others went in says
+// how many rows were kept: in the message, and as JSON `{"committed", "first_id"}` in the details.
+fn partially_committed(err: Status, out: &BatchOutcome) -> Status {
+    let Some(&first_id) = out.ids.iter().flatten().next() else { return err };
+    let count = out.ids.iter().flatten().count();
+    let details = serde_json::json!({ "committed": count, "first_id": first_id }).to_string();
+    let message = format!("{} ({} rows from id {} were committed before the failure)", err.message(), count, first_id);
+    Status::with_details_and_metadata(err.code(), message, details.into(), err.metadata().clone())
+}
+
+// `grpc-timeout` is an integer followed by a unit: H, M, S, m (ms), u (µs) or n (ns).
+fn grpc_timeout(md: &MetadataMap) -> Option<Duration> {
+    let v = md.get("grpc-timeout")?.to_str().ok()?;
+    let (n, unit) = v.split_at(v.len().checked_sub(1)?);
+    let n: u64 = n.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(n.saturating_mul(3600)),
+        "M" => Duration::from_secs(n.saturating_mul(60)),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    })
+}
+
+impl Service {
+    // The checks the REST middleware makes: the caller's rate limit, then a key with `scope` on
+    // `db`. Returns the key when auth is on.
+    fn admit<T>(&self, req: &Request<T>, scope: Scope, db: &str) -> Result<Option<Arc<ApiKey>>, ApiError> {
+        let token = token(req.metadata());
+        let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+        let known = self.auth.as_ref().and_then(|auth| auth.identify(token));
+        self.state.limits().charge(&limits::client_id(known.as_deref(), peer))?;
+        match &self.auth {
+            Some(auth) => Ok(Some(auth.authorize(token, scope, db)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn search_params(&self, md: &MetadataMap, o: Option<pb::SearchOptions>) -> SearchParams {
+        let o = o.unwrap_or_default();
+        SearchParams {
+            k: o.k.map(|k| k as usize),
+            metric: (!o.metric.is_empty()).then_some(o.metric),
+            radius: o.radius,
+            max_results: o.max_results.map(|m| m as usize),
+            filter: (!o.filter.is_empty()).then_some(o.filter),
+            timeout: o.timeout_ms.map(Duration::from_millis).or_else(|| grpc_timeout(md)),
+            partial: o.partial,
+        }
+    }
+}
+
+fn to_matches(items: Vec<FindItem>) -> Vec<pb::Match> {
+    items.into_iter()
+        .map(|i| pb::Match { index: i.index as u64, distance: i.distance, values: i.values, metadata: i.metadata })
+        .collect()
+}
+
+fn batch_response(out: BatchOutcome, durability: &str) -> pb::InsertBatchResponse {
+    pb::InsertBatchResponse {
+        inserted: out.ids.iter().filter(|id| id.is_some()).count() as u64,
+        ids: out.ids.iter().map(|id| id.map_or(-1, |i| i as i64)).collect(),
+        errors: out.errors.into_iter().map(|e| pb::RowError { row: e.row as u64, code: e.code.to_string(), error: e.error }).collect(),
+        total: out.total as u64,
+        durability: durability.to_string(),
+    }
+}
+
+#[tonic::async_trait]
+impl Vectra for Service {
+    async fn create_db(&self, req: Request<pb::CreateDbRequest>) -> Result<Response<pb::Ack>, Status> {
+        self.admit(&req, Scope::Admin, "")?;
+        let req = req.into_inner();
+        self.state.create(req.name, req.dimension as usize).await?;
+        Ok(Response::new(pb::Ack {}))
+    }
+
+    async fn drop_db(&self, req: Request<pb::DbRef>) -> Result<Response<pb::Ack>, Status> {
+        self.admit(&req, Scope::Admin, "")?;
+        self.state.drop_db(&req.get_ref().name).await?;
+        Ok(Response::new(pb::Ack {}))
+    }
+
+    async fn info(&self, req: Request<pb::DbRef>) -> Result<Response<pb::InfoResponse>, Status> {
+        let name = &req.get_ref().name;
+        self.admit(&req, Scope::Read, name)?;
+        let info = self.state.info(name).await?;
+        Ok(Response::new(pb::InfoResponse {
+            name: info.name,
+            dimension: info.dimension as u64,
+            count: info.count as u64,
+            metadata_schema: info.metadata_schema.into_iter().map(|(k, types)| (k, pb::TypeList { types })).collect(),
+        }))
+    }
+
+    async fn insert(&self, req: Request<pb::InsertRequest>) -> Result<Response<pb::InsertResponse>, Status> {
+        let key = self.admit(&req, Scope::Write, &req.get_ref().db)?;
+        let req = req.into_inner();
+        let total = self.state.insert_one(&req.db, InsertReq { values: req.values, meta: req.meta }, may_create(key.as_ref())).await?;
+        Ok(Response::new(pb::InsertResponse { id: total as u64 - 1, total: total as u64, durability: self.state.durability().to_string() }))
+    }
+
+    async fn insert_batch(&self, req: Request<pb::InsertBatchRequest>) -> Result<Response<pb::InsertBatchResponse>, Status> {
+        let key = self.admit(&req, Scope::Write, &req.get_ref().db)?;
+        let req = req.into_inner();
+        let rows = req.rows.into_iter().map(|r| Ok(InsertReq { values: r.values, meta: r.meta })).collect();
+        let out = self.state.insert_rows(&req.db, rows, may_create(key.as_ref())).await?;
+        Ok(Response::new(batch_response(out, self.state.durability())))
+    }
+
+    async fn insert_stream(&self, req: Request<Streaming<pb::InsertRequest>>) -> Result<Response<pb::InsertBatchResponse>, Status> {
+        let max_batch = self.state.limits().max_batch.max(1);
+        // the target database is only known from the first message, so admission waits for it
+        let (md, ext, mut stream) = req.into_parts();
+        let Some(first) = stream.message().await? else {
+            return Ok(Response::new(batch_response(BatchOutcome { ids: Vec::new(), errors: Vec::new(), total: 0 }, self.state.durability())));
+        };
+        let db = first.db.clone();
+        let create = may_create(self.admit(&Request::from_parts(md, ext, ()), Scope::Write, &db)?.as_ref());
+
+        let mut out = BatchOutcome { ids: Vec::new(), errors: Vec::new(), total: 0 };
+        let mut pending = vec![Ok(InsertReq { values: first.values, meta: first.meta })];
+        let res: Result<(), Status> = async {
+            loop {
+                let next = stream.message().await?;
+                let done = next.is_none();
+                if let Some(m) = next {
+                    if !m.db.is_empty() && m.db != db {
+                        return Err(ApiError::BadRequest(format!("stream writes to '{}', got a row for '{}'", db, m.db)).into());
+                    }
+                    pending.push(Ok(InsertReq { values: m.values, meta: m.meta }));
+                    if pending.len() < max_batch { continue; }
+                }
+                if !pending.is_empty() {
+                    let offset = out.ids.len();
+                    let chunk = self.state.insert_rows(&db, std::mem::take(&mut pending), create).await?;
+                    out.ids.extend(chunk.ids);
+                    out.errors.extend(chunk.errors.into_iter().map(|mut e| { e.row += offset; e }));
+                    out.total = chunk.total;
+                }
+                if done { return Ok(()); }
+            }
+        }.await;
+        if let Err(e) = res { return Err(partially_committed(e, &out)); }
+        Ok(Response::new(batch_response(out, self.state.durability())))
+    }
+
+    async fn find(&self, req: Request<pb::FindRequest>) -> Result<Response<pb::FindResponse>, Status> {
+        self.admit(&req, Scope::Read, &req.get_ref().db)?;
+        let (md, _, req) = req.into_parts();
+        let params = self.search_params(&md, req.options);
+        let (mut res, complete) = self.state.search(&req.db, vec![req.values], params).await?;
+        Ok(Response::new(pb::FindResponse { matches: to_matches(res.pop().unwrap_or_default()), incomplete: !complete }))
+    }
+
+    async fn find_batch(&self, req: Request<pb::FindBatchRequest>) -> Result<Response<pb::FindBatchResponse>, Status> {
+        self.admit(&req, Scope::Read, &req.get_ref().db)?;
+        let (md, _, req) = req.into_parts();
+        let params = self.search_params(&md, req.options);
+        let dim = self.state.dimension(&req.db).await?;
+        if dim == 0 || req.queries.len() % dim != 0 {
+            return Err(ApiError::BadRequest(format!("queries holds {} values, not a multiple of the dimension {}", req.queries.len(), dim)).into());
+        }
+        let queries = req.queries.chunks_exact(dim).map(<[f64]>::to_vec).collect();
+        let (res, complete) = self.state.search(&req.db, queries, params).await?;
+        let results = res.into_iter().map(|items| pb::FindResponse { matches: to_matches(items), incomplete: !complete }).collect();
+        Ok(Response::new(pb::FindBatchResponse { results, incomplete: !complete }))
+    }
+}
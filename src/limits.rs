@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::{extract::{ConnectInfo, Request, State}, middleware::Next, response::Response};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::auth::{self, ApiKey, AuthConfig};
+use crate::error::ApiError;
+
+/// Per-request cost limits and the shared state that enforces them.
+pub struct Limits {
+    /// Largest `k` (or radius `max_results`) a search may ask for
+    pub max_k: usize,
+    /// Most rows in one insert batch, queries in one find batch, or items in one scroll page
+    pub max_batch: usize,
+    /// Largest request body in bytes
+    pub max_body_bytes: usize,
+    /// Search timeout for requests that don't set `timeout_ms`
+    pub query_timeout: Option<Duration>,
+    searches: Option<Arc<Semaphore>>,
+    rate: Option<RateLimiter>,
+}
+
+impl Limits {
+    /// `max_searches` of 0 leaves concurrent searches uncapped; `rate` is (requests per
+    /// second, burst) per client and `None` disables rate limiting.
+    pub fn new(max_k: usize, max_batch: usize, max_body_bytes: usize, query_timeout: Option<Duration>, max_searches: usize, rate: Option<(f64, f64)>) -> Self {
+        Limits {
+            max_k,
+            max_batch,
+            max_body_bytes,
+            query_timeout,
+            searches: (max_searches > 0).then(|| Arc::new(Semaphore::new(max_searches))),
+            rate: rate.map(|(per_sec, burst)| RateLimiter { per_sec, burst, buckets: Mutex::new(HashMap::new()) }),
+        }
+    }
+
+    pub fn check_k(&self, what: &str, k: usize) -> Result<(), ApiError> {
+        if k > self.max_k { return Err(ApiError::TooLarge(format!("{}={} exceeds the limit of {}", what, k, self.max_k))); }
+        Ok(())
+    }
+
+    pub fn check_batch(&self, what: &str, n: usize) -> Result<(), ApiError> {
+        if n > self.max_batch { return Err(ApiError::TooLarge(format!("{} {} exceeds the limit of {}", n, what, self.max_batch))); }
+        Ok(())
+    }
+
+    /// A slot for one search, held until the search finishes. Fails fast rather than queueing
+    /// so a flood of expensive queries can't pile up behind the cap.
+    pub fn search_permit(&self) -> Result<Option<OwnedSemaphorePermit>, ApiError> {
+        match &self.searches {
+            Some(s) => s.clone().try_acquire_owned().map(Some).map_err(|_| ApiError::TooManySearches),
+            None => Ok(None),
+        }
+    }
+
+    /// Charges one request to `client` when rate limiting is on.
+    pub fn charge(&self, client: &str) -> Result<(), ApiError> {
+        match &self.rate {
+            Some(rate) => rate.take(client),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rate-limit identity: the API key when auth is on, otherwise the peer IP.
+pub fn client_id(key: Option<&ApiKey>, peer: Option<SocketAddr>) -> String {
+    match (key, peer) {
+        (Some(key), _) => format!("key:{}", key.name),
+        (None, Some(addr)) => format!("ip:{}", addr.ip()),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+// Token bucket per client: holds up to `burst` tokens and refills at `per_sec`.
+struct RateLimiter { per_sec: f64, burst: f64, buckets: Mutex<HashMap<String, (f64, Instant)>> }
+
+// Buckets that have refilled completely carry no state, so they are dropped once the map grows.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+impl RateLimiter {
+    fn take(&self, client: &str) -> Result<(), ApiError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock()?;
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let (per_sec, burst) = (self.per_sec, self.burst);
+            buckets.retain(|_, (tokens, at)| *tokens + now.duration_since(*at).as_secs_f64() * per_sec < burst);
+        }
+        let (tokens, at) = buckets.entry(client.to_string()).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.per_sec).min(self.burst);
+        *at = now;
+        if *tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - *tokens) / self.per_sec);
+            return Err(ApiError::RateLimited { retry_after: wait.as_secs().max(1) });
+        }
+        *tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Middleware state for `rate_limit`: the limits and the configured keys (`None` when auth is off).
+#[derive(Clone)]
+pub struct RateGuard { pub limits: Arc<Limits>, pub auth: Option<Arc<AuthConfig>> }
+
+/// Charges one token to the caller: a valid API key by name, anything else by peer IP.
+/// Runs outside the auth guard so requests with a missing or wrong key are limited too.
+pub async fn rate_limit(State(guard): State<RateGuard>, req: Request, next: Next) -> Result<Response, ApiError> {
+    if guard.limits.rate.is_some() {
+        let key = guard.auth.as_ref().and_then(|a| a.identify(auth::bearer_token(req.headers())));
+        let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+        guard.limits.charge(&client_id(key.as_deref(), peer))?;
+    }
+    Ok(next.run(req).await)
+}
@@ -1,13 +1,27 @@
 mod ver;
-use clap::{Parser, Subcommand};
+mod error;
+mod server;
+mod wal;
+mod metrics;
+mod auth;
+mod tls;
+mod limits;
+mod grpc;
+mod codec;
+mod events;
+mod webhooks;
+mod openapi;
+mod replica;
+mod cluster;
+use clap::{Parser, Subcommand, ValueEnum};
 use chrono::Utc;
-use ver::{Vector, MetadataEntry, MetadataValue, Database, Metric, distance};
+use ver::{Vector, MetadataEntry, MetadataValue, Database, Metric, SearchMode};
+use error::ApiError;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use axum::{extract::{Path, State}, routing::{post, get}, Json, Router};
-use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use serde::Serialize;
 use rusqlite::{Connection, types::ValueRef};
+use rayon::prelude::*;
 use std::fs;
 
 #[derive(Parser)]
@@ -18,6 +32,10 @@ struct Cli {
     #[arg(short, long, default_value = "data")] 
     dir: String,
 
+    /// Diagnostic log format on stderr; verbosity comes from RUST_LOG (default: info)
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -30,14 +48,76 @@ enum Commands {
     /// Insert a vector into a database with optional metadata key=value pairs
     Insert { name: String, #[arg(short = 'v', num_args = 1.., value_delimiter = ',')] values: Vec<f64>, #[arg(short = 'm', num_args = 0.., value_delimiter = ',')] meta: Vec<String> },
 
-    /// Find nearest vectors in a database
-    Find { name: String, #[arg(short = 'v', num_args = 1.., value_delimiter = ',')] values: Vec<f64>, #[arg(short = 'k', default_value_t = 10)] k: usize, #[arg(short = 'f', default_value = "eu")] f: String },
+    /// Find nearest vectors in a database (top-k, or every vector within --radius)
+    Find { name: String, #[arg(short = 'v', num_args = 1.., value_delimiter = ',')] values: Vec<f64>, #[arg(short = 'k', default_value_t = 10)] k: usize, #[arg(short = 'f', default_value = "eu")] f: String,
+           /// Return every vector with distance <= radius instead of the k nearest
+           #[arg(short = 'r', long = "radius")] radius: Option<f64>,
+           /// Cap on the number of results in radius mode
+           #[arg(long = "max-results")] max_results: Option<usize> },
+
+    /// Run many queries from a file (one vector per line, comma or space separated) in parallel
+    FindBatch { name: String, #[arg(short = 'q', long = "queries")] queries: String, #[arg(short = 'k', default_value_t = 10)] k: usize, #[arg(short = 'f', default_value = "eu")] f: String,
+                #[arg(short = 'r', long = "radius")] radius: Option<f64>,
+                #[arg(long = "max-results")] max_results: Option<usize> },
 
     /// Serve REST API
     Serve { #[arg(short = 'a', long = "addr", default_value = "127.0.0.1:8080")] addr: String,
             #[arg(long = "cache-max-mb", default_value_t = 128)] cache_max_mb: usize,
             #[arg(long = "flush-interval-sec", default_value_t = 5)] flush_interval_sec: u64,
-            #[arg(long = "cache-ttl-sec", default_value_t = 600)] cache_ttl_sec: u64 },
+            #[arg(long = "cache-ttl-sec", default_value_t = 600)] cache_ttl_sec: u64,
+            /// When inserts are acknowledged: write-back (memory), write-through (disk) or wal (durable log)
+            #[arg(long = "write-mode", value_enum, default_value_t = server::WriteMode::WriteBack)] write_mode: server::WriteMode,
+            /// Log searches slower than this at warn level (0 disables)
+            #[arg(long = "slow-query-ms", default_value_t = 500)] slow_query_ms: u64,
+            /// JSON file of API keys; when set every route except /healthz, /readyz and the API docs needs a key
+            #[arg(long = "auth-config")] auth_config: Option<String>,
+            /// PEM certificate chain; serves HTTPS instead of HTTP
+            #[arg(long = "tls-cert", requires = "tls_key")] tls_cert: Option<String>,
+            /// PEM private key for --tls-cert
+            #[arg(long = "tls-key", requires = "tls_cert")] tls_key: Option<String>,
+            /// PEM CA bundle; when set, clients must present a certificate it signed (mutual TLS)
+            #[arg(long = "tls-client-ca", requires = "tls_cert")] tls_client_ca: Option<String>,
+            /// How often to check the TLS files for changes and reload them
+            #[arg(long = "tls-reload-sec", default_value_t = 30)] tls_reload_sec: u64,
+            /// Largest k (or radius max_results) a search may request
+            #[arg(long = "max-k", default_value_t = 1000)] max_k: usize,
+            /// Most rows per insert batch, queries per find batch, or items per scroll page
+            #[arg(long = "max-batch", default_value_t = 10_000)] max_batch: usize,
+            /// Largest request body in MB
+            #[arg(long = "max-body-mb", default_value_t = 64)] max_body_mb: usize,
+            /// Search timeout for requests without timeout_ms (0 = none)
+            #[arg(long = "query-timeout-ms", default_value_t = 30_000)] query_timeout_ms: u64,
+            /// Searches allowed to run at once; more are rejected with 429 (0 = no cap)
+            #[arg(long = "max-concurrent-searches", default_value_t = 64)] max_concurrent_searches: usize,
+            /// Requests per second per API key (or client IP without auth); 0 disables rate limiting
+            #[arg(long = "rate-limit", default_value_t = 0.0)] rate_limit: f64,
+            /// Requests a client may burst above the rate (defaults to one second's worth)
+            #[arg(long = "rate-burst")] rate_burst: Option<f64>,
+            /// Also serve the gRPC API (proto/vectra.proto) on this address
+            #[arg(long = "grpc-addr")] grpc_addr: Option<String>,
+            /// Recent change events kept for /events subscribers resuming from a sequence number
+            #[arg(long = "event-buffer", default_value_t = 10_000)] event_buffer: usize,
+            /// Run as a read-only follower of the vectra server at this URL, copying its databases
+            #[arg(long = "follow")] follow: Option<String>,
+            /// API key to present to the leader; it needs read access to every database to copy
+            #[arg(long = "follow-key", requires = "follow")] follow_key: Option<String> },
+
+    /// Serve one REST API over several `serve` workers, splitting each database into shards
+    Coordinate { #[arg(short = 'a', long = "addr", default_value = "127.0.0.1:8090")] addr: String,
+            /// Worker base URL; repeat or comma-separate for several
+            #[arg(long = "worker", required = true, num_args = 1.., value_delimiter = ',')] workers: Vec<String>,
+            /// Shards per new database (defaults to one per worker)
+            #[arg(long = "shards")] shards: Option<usize>,
+            /// API key sent to the workers; it needs admin access
+            #[arg(long = "worker-key")] worker_key: Option<String>,
+            /// JSON file of API keys for clients of the coordinator, as for serve
+            #[arg(long = "auth-config")] auth_config: Option<String>,
+            /// Largest k (or radius max_results) a search may request
+            #[arg(long = "max-k", default_value_t = 1000)] max_k: usize,
+            /// Most rows per insert batch or queries per find batch
+            #[arg(long = "max-batch", default_value_t = 10_000)] max_batch: usize,
+            /// Largest request body in MB
+            #[arg(long = "max-body-mb", default_value_t = 64)] max_body_mb: usize },
 
     /// Import from SQLite table
     ImportSqlite {
@@ -55,8 +135,56 @@ enum Commands {
         #[arg(long, default_value_t = 200_000)] batch_size: usize,
     },
 
+    /// Copy a local database, shard files included, into a running server or coordinator
+    Push { name: String,
+           /// Base URL of the `serve` or `coordinate` process to create the database on
+           #[arg(long)] to: String,
+           /// API key for the target; it needs admin access to create the database
+           #[arg(long)] key: Option<String>,
+           /// Rows per insert_batch request (at most the target's --max-batch)
+           #[arg(long = "batch-size", default_value_t = 1000)] batch_size: usize },
+
+    /// Page through stored vectors in id order, optionally filtered by metadata
+    #[command(alias = "dump")]
+    Scan { name: String,
+           /// Cursor returned by a previous scan (start from the beginning if omitted)
+           #[arg(long)] cursor: Option<String>,
+           /// Max vectors to print (all if omitted)
+           #[arg(short = 'l', long)] limit: Option<usize>,
+           /// Metadata filter key=value pairs (all must match)
+           #[arg(short = 'm', num_args = 0.., value_delimiter = ',')] filter: Vec<String>,
+           /// Print metadata only
+           #[arg(long = "no-values")] no_values: bool },
+
     /// Show DB info (dimension, count, metadata schema)
     Info { name: String },
+
+    /// List all databases in the data directory
+    List,
+
+    /// Delete a database and all of its shard files
+    Drop { name: String },
+
+    /// Rename a database
+    Rename { name: String, to: String },
+
+    /// Copy a database under a new name
+    Clone { name: String, to: String },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat { Text, Json }
+
+fn init_tracing(format: LogFormat) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    // colours only for a terminal, not for a log file or a pipe
+    let ansi = std::io::IsTerminal::is_terminal(&std::io::stderr());
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).with_ansi(ansi);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
 fn parse_meta(pairs: Vec<String>) -> Vec<MetadataEntry> {
@@ -68,52 +196,19 @@ fn parse_meta(pairs: Vec<String>) -> Vec<MetadataEntry> {
     }).collect()
 }
 
-#[derive(Clone)]
-struct AppState {
-    dir: String,
-    dbs: Arc<Mutex<HashMap<String, CacheEntry>>>,
-    cache_max_bytes: usize,
-    flush_interval: Duration,
-    cache_ttl: Duration
-}
-
-struct CacheEntry { db: Database, last_access: Instant, dirty: bool }
-
-fn evict_if_needed(
-    map: &mut HashMap<String, CacheEntry>,
-    max_bytes: usize,
-    ttl: Duration
-) {
-    // TTL eviction
-    let now = Instant::now();
-    let keys_to_remove: Vec<String> = map.iter()
-        .filter(|(_, e)| now.duration_since(e.last_access) > ttl && !e.dirty)
-        .map(|(k, _)| k.clone())
-        .collect();
-    for k in keys_to_remove { map.remove(&k); }
-
-    // Byte-size based eviction: estimate bytes and evict clean & oldest first
-    let mut total_bytes: usize = map.values().map(estimate_entry_bytes).sum();
-    if total_bytes <= max_bytes { return; }
-    let mut entries: Vec<(String, Instant, bool, usize)> = map.iter().map(|(k, e)| (k.clone(), e.last_access, e.dirty, estimate_entry_bytes(e))).collect();
-    entries.sort_by_key(|(_, last, dirty, _)| (*dirty, *last));
-    while total_bytes > max_bytes {
-        if let Some((k, _, _, sz)) = entries.first().cloned() { map.remove(&k); total_bytes = total_bytes.saturating_sub(sz); entries.remove(0); } else { break; }
+// A radius is a distance bound: NaN, infinities and negative values never describe a useful search.
+fn check_radius(radius: Option<f64>) -> Result<(), String> {
+    match radius {
+        Some(r) if !r.is_finite() || r < 0.0 => Err(format!("radius must be a finite, non-negative number, got {}", r)),
+        _ => Ok(()),
     }
 }
 
-fn estimate_entry_bytes(e: &CacheEntry) -> usize {
-    // rough estimate: vectors values + metadata strings
-    let mut bytes = 0usize;
-    for v in &e.db.vectors {
-        bytes = bytes.saturating_add(v.data().len() * std::mem::size_of::<f64>());
-        for m in v.metadata() {
-            bytes = bytes.saturating_add(m.key().len());
-            // value rough size
-            bytes = bytes.saturating_add(32);
-        }
+fn search_mode(k: Option<usize>, radius: Option<f64>, max_results: Option<usize>) -> SearchMode {
+    match radius {
+        Some(radius) => SearchMode::Radius { radius, max_results },
+        None => SearchMode::TopK(k.unwrap_or(10)),
     }
-    bytes
 }
 
 fn map_value_ref_to_metadata(v: ValueRef<'_>) -> Option<MetadataValue> {
@@ -134,74 +229,40 @@ fn map_value_ref_to_metadata(v: ValueRef<'_>) -> Option<MetadataValue> {
     }
 }
 
-#[derive(Deserialize)]
-struct CreateReq { name: String, dimension: usize }
-
-#[derive(Deserialize)]
-struct InsertReq { values: Vec<f64>, meta: HashMap<String, String> }
-
-#[derive(Deserialize)]
-struct FindReq { values: Vec<f64>, k: Option<usize>, f: Option<String> }
-
-#[derive(Serialize)]
-struct FindItem { index: usize, distance: f64, values: Vec<f64>, metadata: HashMap<String, String> }
-
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 struct InfoResp { name: String, dimension: usize, count: usize, metadata_schema: HashMap<String, Vec<String>> }
 
-async fn create_db(State(state): State<AppState>, Json(req): Json<CreateReq>) -> Result<Json<serde_json::Value>, String> {
-    let mut map = state.dbs.lock().map_err(|_| "lock")?;
-    if map.contains_key(&req.name) { return Err("exists".into()); }
-    let db = Database::new(req.name.clone(), req.dimension);
-    db.save_to_dir(&state.dir).map_err(|e| e.to_string())?;
-    map.insert(req.name.clone(), CacheEntry { db, last_access: Instant::now(), dirty: false });
-    evict_if_needed(&mut map, state.cache_max_bytes, state.cache_ttl);
-    Ok(Json(serde_json::json!({"ok": true})))
+// Cursor tokens are opaque to clients; today they encode the next vector id.
+fn parse_cursor(cursor: Option<&str>) -> Result<usize, ApiError> {
+    match cursor {
+        None | Some("") => Ok(0),
+        Some(c) => usize::from_str_radix(c, 16).map_err(|_| ApiError::InvalidCursor(c.to_string())),
+    }
 }
 
-async fn insert_vec(State(state): State<AppState>, Path(name): Path<String>, Json(req): Json<InsertReq>) -> Result<Json<serde_json::Value>, String> {
-    let mut map = state.dbs.lock().map_err(|_| "lock")?;
-    let entry = map.entry(name.clone()).or_insert_with(|| {
-        let db = Database::load_from_dir(&state.dir, &name).unwrap_or(Database::new(name.clone(), req.values.len()));
-        CacheEntry { db, last_access: Instant::now(), dirty: false }
-    });
-    if entry.db.dimension != req.values.len() { return Err(format!("dimension mismatch: db={}, input={}", entry.db.dimension, req.values.len())); }
-    let mut meta = Vec::new();
-    for (k,v) in req.meta.iter() { meta.push(MetadataEntry::new(k.clone(), MetadataValue::String(v.clone()))); }
-    meta.push(MetadataEntry::new("created_at".to_string(), MetadataValue::DateTime(Utc::now())));
-    let v = Vector::new(req.values, meta);
-    entry.db.insert(v).map_err(|e| e.to_string())?;
-    entry.dirty = true;
-    entry.last_access = Instant::now();
-    let total = entry.db.vectors.len();
-    evict_if_needed(&mut map, state.cache_max_bytes, state.cache_ttl);
-    Ok(Json(serde_json::json!({"ok": true, "total": total})))
+// Returns matching (id, vector) pairs starting at `start`, plus the cursor for the next page.
+fn scroll_db<'a>(db: &'a Database, start: usize, limit: usize, filter: Option<&HashMap<String, String>>) -> (Vec<(usize, &'a Vector<f64>)>, Option<String>) {
+    let mut page = Vec::new();
+    let mut next = None;
+    for (id, v) in db.vectors.iter().enumerate().skip(start) {
+        if filter.is_some_and(|f| !v.matches(f)) { continue; }
+        if page.len() == limit { next = Some(format!("{:x}", id)); break; }
+        page.push((id, v));
+    }
+    (page, next)
 }
 
-async fn find_vec(State(state): State<AppState>, Path(name): Path<String>, Json(req): Json<FindReq>) -> Result<Json<Vec<FindItem>>, String> {
-    let mut map = state.dbs.lock().map_err(|_| "lock")?;
-    if !map.contains_key(&name) {
-        let db = Database::load_from_dir(&state.dir, &name).map_err(|_| "not found")?;
-        map.insert(name.clone(), CacheEntry { db, last_access: Instant::now(), dirty: false });
-    }
-    let entry = map.get_mut(&name).unwrap();
-    if entry.db.dimension != req.values.len() { return Err(format!("dimension mismatch: db={}, input={}", entry.db.dimension, req.values.len())); }
-    let metric = Metric::from_code(req.f.as_deref().unwrap_or("eu")).ok_or("unknown metric")?;
-    let mut scored: Vec<(usize, f64)> = entry.db.vectors.iter().enumerate()
-        .map(|(i, v)| (i, distance(v.data(), &req.values, &metric)))
-        .collect();
-    scored.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
-    let k = req.k.unwrap_or(10);
-    let mut res = Vec::new();
-    for (idx, dist) in scored.into_iter().take(k) {
-        let mut meta_map = HashMap::new();
-        for m in entry.db.vectors[idx].metadata() { meta_map.insert(m.key().to_string(), m.value().to_string()); }
-        let values = entry.db.vectors[idx].data().to_vec();
-        res.push(FindItem { index: idx, distance: dist, values, metadata: meta_map });
+fn parse_query_file(path: &str) -> std::io::Result<Vec<Vec<f64>>> {
+    let content = fs::read_to_string(path)?;
+    let mut queries = Vec::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let q: Result<Vec<f64>, _> = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty()).map(|t| t.parse::<f64>()).collect();
+        let q = q.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("line {}: {}", lineno + 1, e)))?;
+        queries.push(q);
     }
-    entry.last_access = Instant::now();
-    evict_if_needed(&mut map, state.cache_max_bytes, state.cache_ttl);
-    Ok(Json(res))
+    Ok(queries)
 }
 
 fn metadata_type_name(v: &MetadataValue) -> &'static str {
@@ -214,6 +275,7 @@ fn metadata_type_name(v: &MetadataValue) -> &'static str {
     }
 }
 
+#[allow(dead_code)]
 fn build_metadata_schema(db: &Database) -> HashMap<String, Vec<String>> {
     use std::collections::{HashMap, HashSet};
     let mut m: HashMap<String, HashSet<&'static str>> = HashMap::new();
@@ -225,16 +287,37 @@ fn build_metadata_schema(db: &Database) -> HashMap<String, Vec<String>> {
     out
 }
 
-async fn info_db(State(state): State<AppState>, Path(name): Path<String>) -> Result<Json<InfoResp>, String> {
-    let info = compute_db_info(&state.dir, &name).map_err(|e| e.to_string())?;
-    Ok(Json(info))
+// Metadata keys seen so far and the value types each one has held.
+type SchemaTypes = std::collections::HashMap<String, std::collections::HashSet<&'static str>>;
+
+fn add_schema(schema: &mut SchemaTypes, db: &Database) {
+    for v in db.vectors.iter() {
+        for e in v.metadata() { schema.entry(e.key().to_string()).or_default().insert(metadata_type_name(e.value())); }
+    }
+}
+
+fn info_resp(name: &str, dimension: usize, count: usize, schema: SchemaTypes) -> InfoResp {
+    let metadata_schema = schema.into_iter().map(|(k, set)| { let mut v: Vec<String> = set.into_iter().map(|s| s.to_string()).collect(); v.sort(); (k, v) }).collect();
+    InfoResp { name: name.to_string(), dimension, count, metadata_schema }
 }
 
+// Info for a database already in memory, unflushed rows included.
+fn db_info(name: &str, db: &Database) -> InfoResp {
+    let mut schema = SchemaTypes::new();
+    add_schema(&mut schema, db);
+    info_resp(name, db.dimension, db.vectors.len(), schema)
+}
+
+// Info read from the files. Rows still only in a write-ahead log are counted by replaying
+// it in memory, without touching the log.
 fn compute_db_info(dir: &str, name: &str) -> std::io::Result<InfoResp> {
-    use std::collections::{HashMap, HashSet};
+    if wal::exists(dir, name) {
+        let (db, _) = wal::load(dir, name, false)?;
+        return Ok(db_info(name, &db));
+    }
     let mut dimension: usize = 0;
     let mut count: usize = 0;
-    let mut schema: HashMap<String, HashSet<&'static str>> = HashMap::new();
+    let mut schema = SchemaTypes::new();
 
     let mut consider_path = |path: &std::path::Path| -> std::io::Result<()> {
         let mut file = fs::File::open(path)?;
@@ -245,9 +328,7 @@ fn compute_db_info(dir: &str, name: &str) -> std::io::Result<InfoResp> {
         if dimension == 0 { dimension = db.dimension; }
         if db.dimension != dimension { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "dimension mismatch in shards")); }
         count += db.vectors.len();
-        for v in db.vectors.iter() {
-            for e in v.metadata() { schema.entry(e.key().to_string()).or_default().insert(metadata_type_name(e.value())); }
-        }
+        add_schema(&mut schema, &db);
         Ok(())
     };
 
@@ -257,87 +338,170 @@ fn compute_db_info(dir: &str, name: &str) -> std::io::Result<InfoResp> {
         for entry in entries.flatten() {
             let path = entry.path();
             if let Some(fname) = path.file_name().and_then(|s| s.to_str()) {
-                if fname.starts_with(&format!("{}_part_", name)) && fname.ends_with(".bin") {
+                if ver::is_part_file(fname, name) {
                     consider_path(&path)?;
                 }
             }
         }
     }
     if dimension == 0 { return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "database not found")); }
-    let mut schema_out: HashMap<String, Vec<String>> = HashMap::new();
-    for (k, set) in schema { let mut v: Vec<String> = set.into_iter().map(|s| s.to_string()).collect(); v.sort(); schema_out.insert(k, v); }
-    Ok(InfoResp { name: name.to_string(), dimension, count, metadata_schema: schema_out })
+    Ok(info_resp(name, dimension, count, schema))
+}
+
+// Creates `db` on a server or coordinator and sends its rows over in id order, so a fresh
+// database gets the same ids. Metadata values travel as strings.
+async fn push_db(db: &Database, to: &str, key: Option<&str>, batch_size: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let http = reqwest::Client::new();
+    let send = |req: reqwest::RequestBuilder| async move {
+        let req = match key { Some(k) => req.bearer_auth(k), None => req };
+        let resp = req.send().await?;
+        if !resp.status().is_success() { return Err(format!("{} answered {}: {}", to, resp.status(), resp.text().await.unwrap_or_default()).into()); }
+        Ok::<serde_json::Value, Box<dyn std::error::Error>>(resp.json().await?)
+    };
+    send(http.post(format!("{}/create", to)).json(&serde_json::json!({"name": db.name, "dimension": db.dimension}))).await?;
+    for (n, chunk) in db.vectors.chunks(batch_size).enumerate() {
+        let rows: Vec<serde_json::Value> = chunk.iter().map(|v| {
+            let meta: HashMap<&str, String> = v.metadata().iter().map(|m| (m.key(), m.value().to_string())).collect();
+            serde_json::json!({"values": v.data(), "meta": meta})
+        }).collect();
+        let resp = send(http.post(format!("{}/db/{}/insert_batch", to, db.name)).json(&rows)).await?;
+        if resp["inserted"].as_u64() != Some(chunk.len() as u64) {
+            return Err(format!("batch starting at id {} was not fully inserted: {}", n * batch_size, resp["errors"]).into());
+        }
+        tracing::info!(sent = n * batch_size + chunk.len(), total = db.vectors.len(), "push progress");
+    }
+    Ok(())
+}
+
+// Serve `app` on a bound listener until `handle` shuts it down, over TLS when configured.
+// Connections may speak HTTP/1.1 or HTTP/2 (the gRPC API needs the latter).
+async fn serve_on(listener: std::net::TcpListener, app: axum::Router, tls: Option<axum_server::tls_rustls::RustlsConfig>, handle: axum_server::Handle) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let svc = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    match tls {
+        Some(config) => axum_server::from_tcp_rustls(listener, config).handle(handle).serve(svc).await,
+        None => axum_server::from_tcp(listener).handle(handle).serve(svc).await,
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>>{
     let cli = Cli::parse();
+    init_tracing(cli.log_format);
     match cli.command {
         Commands::Create { name, dimension } => {
+            ver::validate_name(&name)?;
             let db = Database::new(name.clone(), dimension);
             db.save_to_dir(&cli.dir)?;
             println!("created db '{}' with dimension {} in {}", name, dimension, cli.dir);
         }
         Commands::Insert { name, values, meta } => {
-            let mut db = Database::load_from_dir(&cli.dir, &name)?;
-            if db.dimension != values.len() { eprintln!("dimension mismatch: db={}, input={}", db.dimension, values.len()); std::process::exit(1); }
+            let (mut db, _) = wal::load(&cli.dir, &name, false)?;
+            if db.dimension != values.len() { tracing::error!(expected = db.dimension, actual = values.len(), "dimension mismatch"); std::process::exit(1); }
             let mut m = parse_meta(meta);
             m.push(MetadataEntry::new("created_at".to_string(), MetadataValue::DateTime(Utc::now())));
             let v = Vector::new(values, m);
             db.insert(v)?;
             db.save_to_dir(&cli.dir)?;
+            // the snapshot now holds everything the log had
+            wal::discard(&cli.dir, &name)?;
             println!("inserted into '{}' (total={})", name, db.vectors.len());
         }
-        Commands::Find { name, values, k, f } => {
-            let db = Database::load_from_dir(&cli.dir, &name)?;
-            if db.dimension != values.len() { eprintln!("dimension mismatch: db={}, input={}", db.dimension, values.len()); std::process::exit(1); }
+        Commands::Find { name, values, k, f, radius, max_results } => {
+            let (db, _) = wal::load(&cli.dir, &name, false)?;
+            if db.dimension != values.len() { tracing::error!(expected = db.dimension, actual = values.len(), "dimension mismatch"); std::process::exit(1); }
             let metric = Metric::from_code(&f).ok_or("unknown metric code")?;
-            let mut scored: Vec<(usize, f64)> = db.vectors.iter().enumerate()
-                .map(|(i, v)| (i, distance(v.data(), &values, &metric)))
-                .collect();
-            scored.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
-            for (i,(idx, dist)) in scored.into_iter().take(k).enumerate() {
+            check_radius(radius)?;
+            let scored = db.search(&values, &metric, &search_mode(Some(k), radius, max_results), None);
+            for (i,(idx, dist)) in scored.into_iter().enumerate() {
                 let v = &db.vectors[idx];
                 let src = v.metadata().iter().find(|m| m.key() == "source").map(|m| m.value().to_string()).unwrap_or_else(|| "".to_string());
                 println!("{}\tidx={}\tdist={:.6}\tsource={}\tvalues={:?}", i, idx, dist, src, v.data());
             }
         }
-        Commands::Serve { addr, cache_max_mb, flush_interval_sec, cache_ttl_sec } => {
-            let state = AppState {
-                dir: cli.dir.clone(),
-                dbs: Arc::new(Mutex::new(HashMap::new())),
-                cache_max_bytes: cache_max_mb * 1024 * 1024,
-                flush_interval: Duration::from_secs(flush_interval_sec),
-                cache_ttl: Duration::from_secs(cache_ttl_sec)
+        Commands::FindBatch { name, queries, k, f, radius, max_results } => {
+            let (db, _) = wal::load(&cli.dir, &name, false)?;
+            let queries = parse_query_file(&queries)?;
+            if let Some((i, q)) = queries.iter().enumerate().find(|(_, q)| q.len() != db.dimension) { tracing::error!(query = i, expected = db.dimension, actual = q.len(), "dimension mismatch"); std::process::exit(1); }
+            let metric = Metric::from_code(&f).ok_or("unknown metric code")?;
+            check_radius(radius)?;
+            let mode = search_mode(Some(k), radius, max_results);
+            let results: Vec<Vec<(usize, f64)>> = queries.par_iter().map(|q| db.search(q, &metric, &mode, None)).collect();
+            for (qi, scored) in results.into_iter().enumerate() {
+                for (i, (idx, dist)) in scored.into_iter().enumerate() {
+                    let v = &db.vectors[idx];
+                    let src = v.metadata().iter().find(|m| m.key() == "source").map(|m| m.value().to_string()).unwrap_or_else(|| "".to_string());
+                    println!("q={}\t{}\tidx={}\tdist={:.6}\tsource={}\tvalues={:?}", qi, i, idx, dist, src, v.data());
+                }
+            }
+        }
+        Commands::Serve { addr, cache_max_mb, flush_interval_sec, cache_ttl_sec, write_mode, slow_query_ms, auth_config, tls_cert, tls_key, tls_client_ca, tls_reload_sec, max_k, max_batch, max_body_mb, query_timeout_ms, max_concurrent_searches, rate_limit, rate_burst, grpc_addr, event_buffer, follow, follow_key } => {
+            let auth = auth_config.as_deref().map(auth::AuthConfig::load).transpose()?.map(std::sync::Arc::new);
+            if auth.is_none() { tracing::warn!("no --auth-config given, all routes are unauthenticated"); }
+            let rate = (rate_limit > 0.0).then(|| (rate_limit, rate_burst.unwrap_or(rate_limit).max(1.0)));
+            let query_timeout = (query_timeout_ms > 0).then(|| Duration::from_millis(query_timeout_ms));
+            let limits = limits::Limits::new(max_k, max_batch, max_body_mb * 1024 * 1024, query_timeout, max_concurrent_searches, rate);
+            let state = server::AppState::new(cli.dir.clone(), write_mode, cache_max_mb * 1024 * 1024, Duration::from_secs(flush_interval_sec), Duration::from_secs(cache_ttl_sec), Duration::from_millis(slow_query_ms), limits)
+                .with_events(events::EventLog::open(&cli.dir, event_buffer)?);
+            let hooks = std::sync::Arc::new(webhooks::Webhooks::open(&cli.dir)?);
+            hooks.spawn(state.events())?;
+            let state = state.with_webhooks(hooks.clone());
+            let state = match follow {
+                Some(leader) => {
+                    let replication = std::sync::Arc::new(replica::Replication::new(&leader, follow_key.as_deref()).map_err(std::io::Error::other)?);
+                    replica::spawn(state.replica_writer(), replication.clone());
+                    tracing::info!(%leader, "following");
+                    state.with_follower(replication)
+                }
+                None => state,
             };
-            // background flush task
-            let state_clone = state.clone();
-            tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(state_clone.flush_interval).await;
-                    let mut map = match state_clone.dbs.lock() { Ok(g) => g, Err(_) => continue };
-                    let keys: Vec<String> = map.keys().cloned().collect();
-                    for k in keys {
-                        if let Some(entry) = map.get_mut(&k) {
-                            if entry.dirty {
-                                if entry.db.save_to_dir(&state_clone.dir).is_ok() {
-                                    entry.dirty = false;
-                                }
-                            }
-                        }
-                    }
-                    evict_if_needed(&mut map, state_clone.cache_max_bytes, state_clone.cache_ttl);
+            server::spawn_flush_loop(state.clone());
+            let app = server::router(state.clone(), auth.clone());
+            let tls = match tls_cert.zip(tls_key) {
+                Some((cert, key)) => {
+                    let files = tls::TlsFiles { cert, key, client_ca: tls_client_ca };
+                    let config = axum_server::tls_rustls::RustlsConfig::from_config(files.server_config()?);
+                    tls::spawn_reload(config.clone(), files.clone(), Duration::from_secs(tls_reload_sec));
+                    tracing::info!(mtls = files.client_ca.is_some(), "TLS enabled");
+                    Some(config)
+                }
+                None => None,
+            };
+            // both listeners stop accepting on SIGINT/SIGTERM and drain their in-flight requests
+            let (rest_handle, grpc_handle) = (axum_server::Handle::new(), axum_server::Handle::new());
+            let (h1, h2, events) = (rest_handle.clone(), grpc_handle.clone(), state.events().clone());
+            // change-feed streams never finish on their own, so they are ended before draining
+            tokio::spawn(async move { server::shutdown_signal().await; events.close(); h1.graceful_shutdown(None); h2.graceful_shutdown(None); });
+            let rest = std::net::TcpListener::bind(&addr)?;
+            tracing::info!(%addr, dir = %cli.dir, https = tls.is_some(), "listening");
+            match grpc_addr {
+                Some(grpc_addr) => {
+                    let grpc = std::net::TcpListener::bind(&grpc_addr)?;
+                    tracing::info!(addr = %grpc_addr, "gRPC listening");
+                    tokio::try_join!(
+                        serve_on(rest, app, tls.clone(), rest_handle),
+                        serve_on(grpc, grpc::router(state.clone(), auth), tls, grpc_handle),
+                    )?;
                 }
-            });
-            let app = Router::new()
-                .route("/create", post(create_db))
-                .route("/db/:name/insert", post(insert_vec))
-                .route("/db/:name/find", post(find_vec))
-                .route("/db/:name/info", get(info_db))
-                .with_state(state);
-            println!("listening on http://{}", addr);
-            let listener = tokio::net::TcpListener::bind(&addr).await?;
-            axum::serve(listener, app).await?;
+                None => serve_on(rest, app, tls, rest_handle).await?,
+            }
+            // no more requests are in flight: persist whatever the flush loop hasn't yet
+            tracing::info!("shutting down, flushing dirty databases");
+            let failed = tokio::task::spawn_blocking(move || state.flush_dirty()).await?;
+            hooks.finish().await;
+            if failed > 0 { tracing::error!(failed, "databases failed to flush on shutdown"); std::process::exit(1); }
+        }
+        Commands::Coordinate { addr, workers, shards, worker_key, auth_config, max_k, max_batch, max_body_mb } => {
+            let auth = auth_config.as_deref().map(auth::AuthConfig::load).transpose()?.map(std::sync::Arc::new);
+            if auth.is_none() { tracing::warn!("no --auth-config given, all routes are unauthenticated"); }
+            let limits = limits::Limits::new(max_k, max_batch, max_body_mb * 1024 * 1024, None, 0, None);
+            let cluster = std::sync::Arc::new(cluster::Cluster::open(&cli.dir, workers, shards, worker_key.as_deref(), limits)?);
+            let handle = axum_server::Handle::new();
+            let h = handle.clone();
+            tokio::spawn(async move { server::shutdown_signal().await; h.graceful_shutdown(None); });
+            let listener = std::net::TcpListener::bind(&addr)?;
+            tracing::info!(%addr, dir = %cli.dir, "coordinator listening");
+            serve_on(listener, cluster::router(cluster, auth), None, handle).await?;
         }
         Commands::ImportSqlite { sqlite, table, name, vec_cols, meta_cols, batch_size } => {
             let mut conn = Connection::open(sqlite)?;
@@ -383,26 +547,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
                     }
                     metas.push(MetadataEntry::new("created_at".to_string(), MetadataValue::DateTime(Utc::now())));
                 if db.dimension != values.len() { skipped += 1; continue; }
-                if let Err(e) = db.insert(Vector::new(values, metas)) { eprintln!("skip row due to insert error: {}", e); skipped += 1; continue; }
+                if let Err(e) = db.insert(Vector::new(values, metas)) { tracing::warn!(error = %e, "skip row due to insert error"); skipped += 1; continue; }
                     count += 1;
                 if db.vectors.len() >= batch_size {
+                    let _span = tracing::info_span!("import_batch", db = %name, shard = shard_index, rows = db.vectors.len()).entered();
                     let shard_path = format!("{}/{}_part_{}.bin", &cli.dir, &name, shard_index);
                     db.save_to_path(&shard_path).map_err(|e| format!("failed to save shard: {}", e)).unwrap();
+                    tracing::info!(path = %shard_path, "saved shard");
                     db.vectors.clear();
                     shard_index += 1;
                 }
-                    if count % 1000 == 0 { println!("progress: imported {} rows (skipped {})", count, skipped); }
+                    if count.is_multiple_of(1000) { tracing::info!(count, skipped, "import progress"); }
                 }
             // Save remaining shard
             if !db.vectors.is_empty() {
+                let _span = tracing::info_span!("import_batch", db = %name, shard = shard_index, rows = db.vectors.len()).entered();
                 let shard_path = format!("{}/{}_part_{}.bin", &cli.dir, &name, shard_index);
                 db.save_to_path(&shard_path).map_err(|e| format!("failed to save shard: {}", e)).unwrap();
+                tracing::info!(path = %shard_path, "saved shard");
             }
             println!("imported {} rows into '{}' (skipped {}), shards={}", count, name, skipped, shard_index + 1);
             }
             tx.commit()?;
             
         }
+        Commands::Push { name, to, key, batch_size } => {
+            let (db, _) = wal::load(&cli.dir, &name, false)?;
+            push_db(&db, to.trim_end_matches('/'), key.as_deref(), batch_size.max(1)).await?;
+            println!("pushed {} rows of '{}' to {}", db.vectors.len(), name, to);
+        }
+        Commands::Scan { name, cursor, limit, filter, no_values } => {
+            let (db, _) = wal::load(&cli.dir, &name, false)?;
+            let start = parse_cursor(cursor.as_deref())?;
+            if limit == Some(0) { return Err("--limit must be at least 1".into()); }
+            let filter: HashMap<String, String> = parse_meta(filter).into_iter().map(|m| (m.key().to_string(), m.value().to_string())).collect();
+            let (page, next) = scroll_db(&db, start, limit.unwrap_or(usize::MAX), (!filter.is_empty()).then_some(&filter));
+            for (id, v) in page {
+                let mut meta: Vec<String> = v.metadata().iter().map(|m| format!("{}={}", m.key(), m.value())).collect();
+                meta.sort();
+                if no_values { println!("id={}\tmeta={}", id, meta.join(",")); } else { println!("id={}\tmeta={}\tvalues={:?}", id, meta.join(","), v.data()); }
+            }
+            if let Some(c) = next { println!("next_cursor={}", c); }
+        }
+        Commands::List => {
+            for name in ver::list_dbs(&cli.dir)? {
+                match compute_db_info(&cli.dir, &name) {
+                    Ok(info) => println!("{}\tdimension={}\tcount={}", info.name, info.dimension, info.count),
+                    Err(e) => tracing::warn!(db = %name, error = %e, "cannot read database"),
+                }
+            }
+        }
+        Commands::Drop { name } => {
+            ver::drop_db(&cli.dir, &name)?;
+            println!("dropped db '{}'", name);
+        }
+        Commands::Rename { name, to } => {
+            ver::rename_db(&cli.dir, &name, &to)?;
+            println!("renamed db '{}' to '{}'", name, to);
+        }
+        Commands::Clone { name, to } => {
+            ver::clone_db(&cli.dir, &name, &to)?;
+            println!("cloned db '{}' to '{}'", name, to);
+        }
         Commands::Info { name } => {
             let info = compute_db_info(&cli.dir, &name)?;
             println!("name={} dimension={} count={}", info.name, info.dimension, info.count);
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// upper bounds in seconds; +Inf is implied
+const BUCKETS: [f64; 14] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default, Clone)]
+struct Histogram { buckets: [u64; BUCKETS.len()], count: u64, sum: f64 }
+
+impl Histogram {
+    fn observe(&mut self, d: Duration) {
+        let secs = d.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|b| secs <= *b) { self.buckets[i] += 1; }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (b, n) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += n;
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, b, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count);
+        let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count);
+    }
+}
+
+/// Server metrics, rendered in Prometheus text format by `GET /metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    pub evictions_ttl: AtomicU64,
+    pub evictions_size: AtomicU64,
+    pub eviction_flushes: AtomicU64,
+    pub eviction_flush_failures: AtomicU64,
+    pub writes_rejected: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub disk_loads: AtomicU64,
+    pub flush_failures: AtomicU64,
+    flush_duration: Mutex<Histogram>,
+    // keyed by (db, metric code)
+    query_latency: Mutex<BTreeMap<(String, String), Histogram>>,
+    // vectors inserted, keyed by db
+    inserts: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Point-in-time values read from the cache when rendering.
+pub struct CacheGauges { pub dbs: usize, pub dirty: usize, pub bytes: usize }
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(&str, &AtomicU64)]) {
+    header(out, name, "counter", help);
+    for (labels, v) in samples {
+        if labels.is_empty() { let _ = writeln!(out, "{} {}", name, v.load(Ordering::Relaxed)); }
+        else { let _ = writeln!(out, "{}{{{}}} {}", name, labels, v.load(Ordering::Relaxed)); }
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+impl Metrics {
+    pub fn observe_query(&self, db: &str, metric: &str, d: Duration) {
+        if let Ok(mut m) = self.query_latency.lock() {
+            m.entry((db.to_string(), metric.to_string())).or_default().observe(d);
+        }
+    }
+
+    pub fn add_inserts(&self, db: &str, n: usize) {
+        if n == 0 { return; }
+        if let Ok(mut m) = self.inserts.lock() { *m.entry(db.to_string()).or_default() += n as u64; }
+    }
+
+    pub fn observe_flush(&self, d: Duration) {
+        if let Ok(mut h) = self.flush_duration.lock() { h.observe(d); }
+    }
+
+    pub fn render(&self, cache: &CacheGauges) -> String {
+        let mut out = String::new();
+        header(&mut out, "vectra_query_duration_seconds", "histogram", "Search latency per database and metric.");
+        if let Ok(m) = self.query_latency.lock() {
+            for ((db, metric), h) in m.iter() {
+                h.render(&mut out, "vectra_query_duration_seconds", &format!("db=\"{}\",metric=\"{}\"", escape(db), escape(metric)));
+            }
+        }
+        header(&mut out, "vectra_inserted_vectors_total", "counter", "Vectors inserted per database.");
+        if let Ok(m) = self.inserts.lock() {
+            for (db, n) in m.iter() { let _ = writeln!(out, "vectra_inserted_vectors_total{{db=\"{}\"}} {}", escape(db), n); }
+        }
+        counter(&mut out, "vectra_cache_requests_total", "Cache lookups by outcome.",
+            &[("result=\"hit\"", &self.cache_hits), ("result=\"miss\"", &self.cache_misses)]);
+        counter(&mut out, "vectra_cache_disk_loads_total", "Databases loaded from disk into the cache.", &[("", &self.disk_loads)]);
+        counter(&mut out, "vectra_cache_evictions_total", "Databases evicted from the cache.",
+            &[("reason=\"ttl\"", &self.evictions_ttl), ("reason=\"size\"", &self.evictions_size)]);
+        counter(&mut out, "vectra_cache_eviction_flushes_total", "Dirty databases flushed so they could be evicted.", &[("", &self.eviction_flushes)]);
+        counter(&mut out, "vectra_cache_eviction_flush_failures_total", "Dirty eviction candidates kept because their flush failed.", &[("", &self.eviction_flush_failures)]);
+        counter(&mut out, "vectra_writes_rejected_total", "Writes rejected with 503 because the cache is full of unflushed data.", &[("", &self.writes_rejected)]);
+        header(&mut out, "vectra_flush_duration_seconds", "histogram", "Time to snapshot and write one database.");
+        if let Ok(h) = self.flush_duration.lock() { h.render(&mut out, "vectra_flush_duration_seconds", ""); }
+        counter(&mut out, "vectra_flush_failures_total", "Database flushes that failed.", &[("", &self.flush_failures)]);
+        gauge(&mut out, "vectra_cache_databases", "Databases currently cached.", cache.dbs);
+        gauge(&mut out, "vectra_cache_dirty_databases", "Cached databases with unflushed inserts.", cache.dirty);
+        gauge(&mut out, "vectra_cache_bytes", "Memory held by cached databases.", cache.bytes);
+        out
+    }
+}
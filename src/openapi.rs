@@ -0,0 +1,61 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+use crate::error::ErrorBody;
+
+/// OpenAPI 3 description of the REST API. Schemas come from the request and response types
+/// themselves, so the document can't drift from what the handlers accept.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Vectra", description = "Vector database REST API"),
+    components(schemas(ErrorBody)),
+    tags(
+        (name = "databases", description = "Create, list, rename, clone and drop databases"),
+        (name = "vectors", description = "Insert, search and read vectors"),
+        (name = "webhooks", description = "Per-database webhook registrations"),
+        (name = "replication", description = "What followers copy from the leader"),
+        (name = "admin", description = "Cache control, status and metrics"),
+        (name = "health", description = "Liveness and readiness probes"),
+    ),
+)]
+struct ApiDoc;
+
+pub fn spec() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.merge(crate::server::Paths::openapi());
+    Common.modify(&mut doc);
+    // the package has no license field, which would otherwise surface as an empty one
+    doc.info.license = None;
+    doc
+}
+
+// Parts every operation shares: the key schemes and the error body.
+struct Common;
+
+impl Modify for Common {
+    fn modify(&self, doc: &mut utoipa::openapi::OpenApi) {
+        let components = doc.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))));
+        // operations that set their own (the probes) keep it
+        doc.security = Some(vec![
+            SecurityRequirement::new("bearer", Vec::<String>::new()),
+            SecurityRequirement::new("api_key", Vec::<String>::new()),
+        ]);
+        let error = ResponseBuilder::new()
+            .description("Error; see the error codes table in the README")
+            .content("application/json", ContentBuilder::new().schema(Some(Ref::from_schema_name("ErrorBody"))).build())
+            .build();
+        for item in doc.paths.paths.values_mut() {
+            for op in [&mut item.get, &mut item.post, &mut item.delete].into_iter().flatten() {
+                op.responses.responses.entry("default".to_string()).or_insert_with(|| error.clone().into());
+            }
+        }
+    }
+}
+
+/// `/openapi.json` and the Swagger UI at `/docs`. Both are public, like the probes.
+pub fn docs<S: Clone + Send + Sync + 'static>() -> axum::Router<S> {
+    SwaggerUi::new("/docs").url("/openapi.json", spec()).into()
+}
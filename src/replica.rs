@@ -0,0 +1,326 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+use crate::error::ApiError;
+use crate::events::{Event, EventKind, Item};
+use crate::server::AppState;
+use crate::ver::{Database, Vector};
+
+// Reconnects after 1s, 2s, 4s, ... capped at MAX_BACKOFF.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// The leader sends a keep-alive every 15s, so this much silence means the connection is dead.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+// Snapshots carry a whole database, so they get longer.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(600);
+// Events read from the leader but not yet applied.
+const MAX_PENDING: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Copying snapshots of the leader's databases
+    Bootstrapping,
+    /// Applying the leader's change feed
+    Streaming,
+    /// Waiting to reconnect after an error
+    Reconnecting,
+}
+
+/// Where a follower is relative to its leader, reported by `GET /status`.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ReplicationStatus {
+    leader: String,
+    state: Phase,
+    /// Last leader event applied here
+    applied_seq: u64,
+    /// Latest leader event seen
+    leader_seq: u64,
+    /// Events seen but not yet applied
+    lag_events: u64,
+    /// How long after the leader made it the last applied change landed here
+    lag_ms: Option<i64>,
+    /// Last time anything, keep-alives included, arrived from the leader
+    last_contact: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    /// Full copies taken from the leader; more than one means the feed was lost and resynced
+    bootstraps: u64,
+}
+
+// Why streaming stopped: the leader no longer has the events we need, or something failed.
+enum Stop { Behind, Failed(String) }
+
+#[derive(Deserialize)]
+struct Listed { name: String }
+
+/// A follower's link to its leader. The follower copies every database the key can read,
+/// then applies the leader's change feed as it happens.
+pub struct Replication {
+    leader: String,
+    client: reqwest::Client,
+    status: Mutex<ReplicationStatus>,
+}
+
+impl Replication {
+    /// `key` is sent to the leader as `x-api-key` and needs read access to what should be copied.
+    pub fn new(leader: &str, key: Option<&str>) -> Result<Self, String> {
+        let url = reqwest::Url::parse(leader).map_err(|e| format!("invalid leader url '{}': {}", leader, e))?;
+        if !matches!(url.scheme(), "http" | "https") { return Err(format!("leader url must be http or https, not {}", url.scheme())); }
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(key) = key {
+            let mut v = reqwest::header::HeaderValue::from_str(key).map_err(|_| "the follow key is not a valid header value".to_string())?;
+            v.set_sensitive(true);
+            headers.insert("x-api-key", v);
+        }
+        let client = reqwest::Client::builder().default_headers(headers).connect_timeout(Duration::from_secs(10)).build().map_err(|e| e.to_string())?;
+        let leader = leader.trim_end_matches('/').to_string();
+        let status = ReplicationStatus { leader: leader.clone(), state: Phase::Bootstrapping, applied_seq: 0, leader_seq: 0, lag_events: 0, lag_ms: None, last_contact: None, last_error: None, bootstraps: 0 };
+        Ok(Replication { leader, client, status: Mutex::new(status) })
+    }
+
+    pub fn leader(&self) -> &str { &self.leader }
+
+    /// Whether the first full copy has finished; until then the follower isn't ready.
+    pub fn bootstrapped(&self) -> bool {
+        self.status.lock().map(|s| s.bootstraps > 0).unwrap_or(false)
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let mut s = self.status.lock().map(|s| s.clone()).unwrap_or_else(|e| e.into_inner().clone());
+        s.lag_events = s.leader_seq.saturating_sub(s.applied_seq);
+        s
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ReplicationStatus)) {
+        if let Ok(mut s) = self.status.lock() { f(&mut s); }
+    }
+
+    fn applied(&self) -> u64 {
+        self.status.lock().map(|s| s.applied_seq).unwrap_or(0)
+    }
+
+    // A 404 is `None`; the change feed has no timeout since it never finishes.
+    async fn get(&self, path: &str, timeout: Option<Duration>) -> Result<Option<reqwest::Response>, String> {
+        let mut req = self.client.get(format!("{}{}", self.leader, path));
+        if let Some(timeout) = timeout { req = req.timeout(timeout); }
+        let resp = req.send().await.map_err(|e| e.to_string())?;
+        self.update(|s| s.last_contact = Some(Utc::now()));
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            s if s.is_success() => Ok(Some(resp)),
+            s => Err(format!("leader answered {} for {}: {}", s, path, resp.text().await.unwrap_or_default())),
+        }
+    }
+
+    // Copies every database from the leader and drops local ones it no longer has. The
+    // sequence number is read before the list, so the feed resumes without a gap.
+    async fn bootstrap(&self, writer: &AppState) -> Result<(), String> {
+        self.update(|s| s.state = Phase::Bootstrapping);
+        let resp = self.get("/dbs", Some(REQUEST_TIMEOUT)).await?.ok_or("leader has no /dbs")?;
+        let seq = resp.headers().get("x-vectra-event-seq").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok())
+            .ok_or("leader did not send x-vectra-event-seq; is it a vectra server?")?;
+        let listed: Vec<Listed> = resp.json().await.map_err(|e| e.to_string())?;
+        for db in &listed {
+            self.copy(writer, &db.name).await?;
+        }
+        for name in writer.db_names().map_err(|e| e.to_string())? {
+            if !listed.iter().any(|l| l.name == name) { forget(writer, &name).await?; }
+        }
+        tracing::info!(leader = %self.leader, databases = listed.len(), seq, "copied the leader's databases");
+        self.update(|s| { s.applied_seq = seq; s.leader_seq = s.leader_seq.max(seq); s.lag_ms = Some(0); s.bootstraps += 1; });
+        Ok(())
+    }
+
+    // Replaces one database with the leader's copy, or drops it if the leader has none.
+    async fn copy(&self, writer: &AppState, name: &str) -> Result<(), String> {
+        let Some(resp) = self.get(&format!("/db/{}/snapshot", name), Some(SNAPSHOT_TIMEOUT)).await? else {
+            return forget(writer, name).await;
+        };
+        let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+        let db = Database::decode(&bytes).map_err(|e| format!("snapshot of '{}': {}", name, e))?;
+        if db.name != name { return Err(format!("asked for a snapshot of '{}', got '{}'", name, db.name)); }
+        writer.install(db).await.map_err(|e| e.to_string())
+    }
+
+    // Fetches vectors from the leader until the local copy has `upto` of them.
+    async fn catch_up(&self, writer: &AppState, name: &str, upto: usize) -> Result<(), String> {
+        loop {
+            let have = writer.count(name).await.map_err(|e| e.to_string())?;
+            if have >= upto { return Ok(()); }
+            let resp = self.get(&format!("/db/{}/tail?from={}", name, have), Some(REQUEST_TIMEOUT)).await?
+                .ok_or_else(|| format!("'{}' is gone from the leader", name))?;
+            let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+            let vectors: Vec<Vector<f64>> = bincode::deserialize(&bytes).map_err(|e| e.to_string())?;
+            if vectors.is_empty() { return Err(format!("leader has fewer than {} vectors in '{}'", upto, name)); }
+            writer.apply_vectors(name, have, vectors).await.map_err(|e| e.to_string())?;
+        }
+    }
+
+    async fn apply(&self, writer: &AppState, e: &Event) -> Result<(), String> {
+        let res = match &e.kind {
+            EventKind::Insert { first_id, count, .. } => return self.catch_up(writer, &e.db, first_id + count).await,
+            EventKind::Create { dimension } => writer.create(e.db.clone(), *dimension).await,
+            EventKind::Drop => writer.drop_db(&e.db).await,
+            EventKind::Rename { to } => writer.rename(&e.db, to.clone()).await,
+            EventKind::Clone { to } => writer.clone_db(&e.db, to.clone()).await,
+            EventKind::Flush { .. } => Ok(()),
+        };
+        match (&e.kind, res) {
+            // already done by the snapshot taken at bootstrap
+            (EventKind::Create { .. }, Err(ApiError::DbExists(_))) | (EventKind::Drop, Err(ApiError::DbNotFound(_))) => Ok(()),
+            (_, res) => res.map_err(|e| e.to_string()),
+        }
+    }
+
+    // Applies one event; when that fails (say the snapshot already reflected it), the
+    // databases it touched are copied from the leader again instead.
+    async fn apply_or_copy(&self, writer: &AppState, e: &Event) -> Result<(), String> {
+        let Err(err) = self.apply(writer, e).await else { return Ok(()) };
+        tracing::debug!(db = %e.db, seq = e.seq, error = %err, "could not apply event, copying the database instead");
+        self.copy(writer, &e.db).await?;
+        match &e.kind {
+            EventKind::Rename { to } | EventKind::Clone { to } => self.copy(writer, to).await,
+            _ => Ok(()),
+        }
+    }
+
+    // Follows the leader's feed from the last applied event until something breaks. Reading
+    // and applying run side by side so `lag_events` shows how far behind applying is.
+    async fn stream(&self, writer: &AppState) -> Stop {
+        let (tx, mut rx) = mpsc::channel(MAX_PENDING);
+        let read = self.read_feed(tx);
+        let apply = async {
+            while let Some(item) = rx.recv().await {
+                let e = match item {
+                    Item::Event(e) => e,
+                    Item::Reset(_) => return Stop::Behind,
+                };
+                if let Err(err) = self.apply_or_copy(writer, &e).await { return Stop::Failed(err); }
+                let lag = (Utc::now() - e.time).num_milliseconds().max(0);
+                self.update(|s| { s.applied_seq = e.seq; s.lag_ms = Some(lag); });
+            }
+            Stop::Failed("change feed ended".to_string())
+        };
+        tokio::pin!(read, apply);
+        // keep applying what was already read after the connection drops
+        tokio::select! {
+            stop = &mut apply => stop,
+            stop = &mut read => match apply.await { Stop::Failed(_) => stop, other => other },
+        }
+    }
+
+    async fn read_feed(&self, tx: mpsc::Sender<Item>) -> Stop {
+        let mut resp = match self.get(&format!("/events?since={}", self.applied()), None).await {
+            Ok(Some(resp)) => resp,
+            Ok(None) => return Stop::Failed("leader has no /events".to_string()),
+            Err(e) => return Stop::Failed(e),
+        };
+        self.update(|s| { s.state = Phase::Streaming; s.last_error = None; });
+        let mut buf: Vec<u8> = Vec::new();
+        let mut frame = Frame::default();
+        loop {
+            let chunk = match tokio::time::timeout(IDLE_TIMEOUT, resp.chunk()).await {
+                Err(_) => return Stop::Failed(format!("nothing from the leader for {}s", IDLE_TIMEOUT.as_secs())),
+                Ok(Err(e)) => return Stop::Failed(e.to_string()),
+                Ok(Ok(None)) => return Stop::Failed("leader closed the change feed".to_string()),
+                Ok(Ok(Some(chunk))) => chunk,
+            };
+            self.update(|s| s.last_contact = Some(Utc::now()));
+            buf.extend_from_slice(&chunk);
+            while let Some(nl) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=nl).collect();
+                let line = String::from_utf8_lossy(&line);
+                let item = match frame.line(line.trim_end_matches(['\n', '\r'])) {
+                    Ok(Some(item)) => item,
+                    Ok(None) => continue,
+                    Err(e) => return Stop::Failed(e),
+                };
+                if let Item::Event(e) = &item { self.update(|s| s.leader_seq = s.leader_seq.max(e.seq)); }
+                // the applier stopped, and says why
+                if tx.send(item).await.is_err() { return Stop::Failed(String::new()); }
+            }
+        }
+    }
+}
+
+async fn forget(writer: &AppState, name: &str) -> Result<(), String> {
+    match writer.drop_db(name).await {
+        Ok(()) | Err(ApiError::DbNotFound(_)) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct Reset { seq: u64 }
+
+// One server-sent event being assembled line by line.
+#[derive(Default)]
+struct Frame { event: String, data: String }
+
+impl Frame {
+    // Feeds one line; a blank line ends the event and yields it.
+    fn line(&mut self, line: &str) -> Result<Option<Item>, String> {
+        if line.is_empty() {
+            let Frame { event, data } = std::mem::take(self);
+            return match event.as_str() {
+                "" if data.is_empty() => Ok(None),
+                "reset" => serde_json::from_str::<Reset>(&data).map(|r| Some(Item::Reset(r.seq))).map_err(|e| format!("unreadable reset: {}", e)),
+                "error" => Err(format!("leader's change feed failed: {}", data)),
+                _ => serde_json::from_str::<Event>(&data).map(|e| Some(Item::Event(Arc::new(e)))).map_err(|e| format!("unreadable event: {}", e)),
+            };
+        }
+        // comments are keep-alives
+        if line.starts_with(':') { return Ok(None); }
+        let (field, value) = line.split_once(':').map_or((line, ""), |(f, v)| (f, v.strip_prefix(' ').unwrap_or(v)));
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if !self.data.is_empty() { self.data.push('\n'); }
+                self.data.push_str(value);
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+/// Keeps `writer`'s databases in step with the leader: a full copy first, then the change
+/// feed. Losing the feed reconnects from the last applied event; falling out of the leader's
+/// event buffer takes a fresh copy.
+pub fn spawn(writer: AppState, replication: Arc<Replication>) {
+    tokio::spawn(async move {
+        let mut backoff = FIRST_BACKOFF;
+        let mut copied = false;
+        loop {
+            if !copied {
+                if let Err(e) = replication.bootstrap(&writer).await {
+                    tracing::warn!(leader = %replication.leader, error = %e, "could not copy from the leader");
+                    replication.update(|s| { s.state = Phase::Reconnecting; s.last_error = Some(e); });
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+                copied = true;
+            }
+            let stop = replication.stream(&writer).await;
+            // it had connected, so this is a fresh failure rather than a leader still down
+            if replication.status().state == Phase::Streaming { backoff = FIRST_BACKOFF; }
+            match stop {
+                Stop::Behind => {
+                    tracing::warn!(leader = %replication.leader, "fell behind the leader's change feed, copying again");
+                    copied = false;
+                }
+                Stop::Failed(e) => {
+                    tracing::warn!(leader = %replication.leader, error = %e, "lost the leader's change feed");
+                    replication.update(|s| { s.state = Phase::Reconnecting; s.last_error = Some(e); });
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
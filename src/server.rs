@@ -0,0 +1,1410 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+use axum::{body::Body, extract::{DefaultBodyLimit, Request, State}, http::{header, HeaderMap, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{post, get, delete}, Extension, Json, Router};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+use crate::auth::{ApiKey, AuthConfig, Guard, Scope};
+use crate::codec::{self, Accept, AcceptVector, Decode, Format, Payload, Reply};
+use crate::error::{ApiError, ApiPath, ApiQuery};
+use crate::events::{EventKind, EventLog};
+use crate::limits::{Limits, RateGuard};
+use crate::webhooks::{self, Webhooks};
+use crate::replica::{Replication, ReplicationStatus};
+use crate::ver::{self, Vector, MetadataEntry, MetadataValue, Database, Metric, MemoryUsage, SearchMode};
+use crate::wal;
+use crate::metrics::{CacheGauges, Metrics};
+use crate::{check_radius, compute_db_info, db_info, parse_cursor, scroll_db, search_mode, InfoResp};
+
+/// When an insert is acknowledged relative to it reaching disk.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WriteMode {
+    /// Acknowledge once in memory; the background task flushes later
+    WriteBack,
+    /// Persist the whole DB before acknowledging
+    WriteThrough,
+    /// Acknowledge after the insert is appended and fsynced to a write-ahead log
+    Wal,
+}
+
+impl WriteMode {
+    // durability level reported in insert responses
+    fn durability(self) -> &'static str {
+        match self {
+            WriteMode::WriteBack => "memory",
+            WriteMode::WriteThrough => "disk",
+            WriteMode::Wal => "wal",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    dir: String,
+    write_mode: WriteMode,
+    // The map lock is only held to look up or swap entries; readers and writers of one
+    // database contend on that entry's own RwLock.
+    dbs: Arc<RwLock<HashMap<String, Arc<CacheEntry>>>>,
+    // Names with a create, drop, rename, clone or install in progress. They are taken and
+    // released under the map's write lock, so the file I/O in between runs without it.
+    busy: Arc<Mutex<HashSet<String>>>,
+    // bumped whenever a name is taken or released, so a load that raced one is redone
+    lifecycle: Arc<AtomicU64>,
+    cache_max_bytes: usize,
+    // set when eviction can't get under the byte budget because what's left is unflushed;
+    // writes are rejected with 503 until a later pass succeeds
+    cache_full: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    flush_interval: Duration,
+    cache_ttl: Duration,
+    // searches at least this slow are logged; zero disables the log
+    slow_query: Duration,
+    limits: Arc<Limits>,
+    events: Arc<EventLog>,
+    webhooks: Option<Arc<Webhooks>>,
+    // set on a follower, which refuses writes from clients
+    replica: Option<Arc<Replication>>,
+}
+
+// `bytes` mirrors `db.mem_bytes()` after every change so eviction can size the cache without
+// taking any database lock. `flushing` is held for a whole flush, so flushes of one DB never
+// overlap and a caller that finds a flush running waits for it to land. `retired` is set once
+// the DB has been dropped, renamed or replaced: whoever still holds the entry may read it, but
+// no insert or flush may touch its files again.
+struct CacheEntry { db: RwLock<Database>, bytes: AtomicUsize, last_access: Mutex<Instant>, dirty: AtomicBool, retired: AtomicBool, flushing: Mutex<()>, flush_error: Mutex<Option<String>>, last_flush: Mutex<Option<DateTime<Utc>>> }
+
+impl CacheEntry {
+    fn new(db: Database) -> Self {
+        let bytes = AtomicUsize::new(db.mem_bytes());
+        CacheEntry { db: RwLock::new(db), bytes, last_access: Mutex::new(Instant::now()), dirty: AtomicBool::new(false), retired: AtomicBool::new(false), flushing: Mutex::new(()), flush_error: Mutex::new(None), last_flush: Mutex::new(None) }
+    }
+
+    // Waits out a running flush, then marks the entry under its write lock, so every insert
+    // either landed before (and is in the next snapshot) or sees the mark and fails. The guard
+    // keeps flushes out while the caller works on the files.
+    fn retire(&self) -> io::Result<MutexGuard<'_, ()>> {
+        let flushing = self.flushing.lock().map_err(|_| io::Error::other("lock poisoned"))?;
+        let _db = self.db.write().map_err(|_| io::Error::other("lock poisoned"))?;
+        self.retired.store(true, Ordering::SeqCst);
+        Ok(flushing)
+    }
+
+    // Undoes `retire` when the operation that retired the entry failed before changing any file.
+    fn revive(&self) { self.retired.store(false, Ordering::SeqCst); }
+
+    // Call with the write lock held, before changing anything.
+    fn check_live(&self, name: &str) -> Result<(), ApiError> {
+        if self.retired.load(Ordering::SeqCst) { return Err(ApiError::Busy(name.to_string())); }
+        Ok(())
+    }
+
+    // call with the write lock still held after mutating the database
+    fn update_bytes(&self, db: &Database) {
+        self.bytes.store(db.mem_bytes(), Ordering::Relaxed);
+    }
+
+    fn bytes(&self) -> usize { self.bytes.load(Ordering::Relaxed) }
+
+    fn flush_error(&self) -> Option<String> {
+        self.flush_error.lock().ok().and_then(|e| e.clone())
+    }
+
+    fn last_flush(&self) -> Option<DateTime<Utc>> {
+        self.last_flush.lock().ok().and_then(|t| *t)
+    }
+
+    fn touch(&self) {
+        if let Ok(mut t) = self.last_access.lock() { *t = Instant::now(); }
+    }
+
+    fn last_access(&self) -> Instant {
+        self.last_access.lock().map(|t| *t).unwrap_or_else(|_| Instant::now())
+    }
+
+    fn is_dirty(&self) -> bool { self.dirty.load(Ordering::SeqCst) }
+
+    // Flush and remember the outcome so a failing DB stays visible until a later flush succeeds.
+    // Returns the name and vector count written, or None when there was nothing to write.
+    fn flush(&self, dir: &str, metrics: &Metrics) -> io::Result<Option<(String, usize)>> {
+        self.record_flush(metrics, || self.flush_inner(dir))
+    }
+
+    fn record_flush(&self, metrics: &Metrics, write: impl FnOnce() -> io::Result<Option<(String, usize)>>) -> io::Result<Option<(String, usize)>> {
+        let span = tracing::info_span!("flush", db = tracing::field::Empty, bytes = tracing::field::Empty);
+        let _guard = span.enter();
+        let started = Instant::now();
+        let res = write();
+        match &res {
+            Ok(Some(_)) => {
+                tracing::debug!(elapsed_ms = started.elapsed().as_millis() as u64, "flushed");
+                metrics.observe_flush(started.elapsed());
+                if let Ok(mut t) = self.last_flush.lock() { *t = Some(Utc::now()); }
+            }
+            Ok(None) => {}
+            Err(_) => { metrics.flush_failures.fetch_add(1, Ordering::Relaxed); }
+        }
+        if let Ok(mut last) = self.flush_error.lock() {
+            *last = res.as_ref().err().map(|e| e.to_string());
+        }
+        res
+    }
+
+    // Snapshot the database under its read lock, then write the snapshot without holding it.
+    // A write-through insert that finds nothing dirty here was taken by the flush it waited on,
+    // which has finished (or failed and marked the entry dirty again) by the time it gets the lock.
+    fn flush_inner(&self, dir: &str) -> io::Result<Option<(String, usize)>> {
+        let flushing = self.flushing.lock().map_err(|_| io::Error::other("lock poisoned"))?;
+        // the files now belong to nobody, or to a different database
+        if self.retired.load(Ordering::SeqCst) { return Ok(None); }
+        self.write_snapshot(&flushing, dir)
+    }
+
+    // The flush itself, for a caller holding `flushing`.
+    fn write_snapshot(&self, _flushing: &MutexGuard<'_, ()>, dir: &str) -> io::Result<Option<(String, usize)>> {
+        let (name, count, bytes) = {
+            let db = self.db.read().map_err(|_| io::Error::other("lock poisoned"))?;
+            // inserts take the write lock, so clearing the flag here cannot lose one
+            if !self.dirty.swap(false, Ordering::SeqCst) { return Ok(None); }
+            // WAL records up to this point are covered by the snapshot; later ones go to a fresh log
+            if let Err(e) = wal::rotate(dir, &db.name) {
+                self.dirty.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+            (db.name.clone(), db.vectors.len(), db.encode())
+        };
+        tracing::Span::current().record("db", name.as_str()).record("bytes", bytes.len());
+        if let Err(e) = Database::write_encoded(dir, &name, &bytes) {
+            self.dirty.store(true, Ordering::SeqCst);
+            return Err(e);
+        }
+        wal::finish(dir, &name)?;
+        Ok(Some((name, count)))
+    }
+}
+
+impl AppState {
+    pub fn new(dir: String, write_mode: WriteMode, cache_max_bytes: usize, flush_interval: Duration, cache_ttl: Duration, slow_query: Duration, limits: Limits) -> Self {
+        AppState { dir, write_mode, dbs: Arc::new(RwLock::new(HashMap::new())), busy: Arc::default(), lifecycle: Arc::default(), cache_max_bytes, cache_full: Arc::new(AtomicBool::new(false)), metrics: Arc::new(Metrics::default()), flush_interval, cache_ttl, slow_query, limits: Arc::new(limits), events: Arc::new(EventLog::memory(0)), webhooks: None, replica: None }
+    }
+
+    /// Publishes changes to `events` (the change feed) instead of a log nobody can resume from.
+    pub fn with_events(mut self, events: EventLog) -> Self {
+        self.events = Arc::new(events);
+        self
+    }
+
+    pub(crate) fn events(&self) -> &Arc<EventLog> { &self.events }
+
+    /// Enables the per-database webhook endpoints.
+    pub fn with_webhooks(mut self, webhooks: Arc<Webhooks>) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    pub(crate) fn webhooks(&self) -> Result<&Arc<Webhooks>, ApiError> {
+        self.webhooks.as_ref().ok_or_else(|| ApiError::Internal("webhooks are not enabled".to_string()))
+    }
+
+    /// Makes this server a read-only follower whose data comes from `replication`.
+    pub fn with_follower(mut self, replication: Arc<Replication>) -> Self {
+        self.replica = Some(replication);
+        self
+    }
+
+    /// The same state without the follower's write guard, for applying the leader's changes.
+    pub(crate) fn replica_writer(&self) -> AppState {
+        AppState { replica: None, ..self.clone() }
+    }
+
+    pub(crate) fn writable(&self) -> Result<(), ApiError> {
+        match &self.replica {
+            Some(r) => Err(ApiError::ReadOnly(r.leader().to_string())),
+            None => Ok(()),
+        }
+    }
+
+    // Flushes one entry, announcing a completed flush on the change feed.
+    fn flush_entry(&self, e: &CacheEntry) -> io::Result<bool> {
+        self.announce_flush(e.flush(&self.dir, &self.metrics)?)
+    }
+
+    // Flushes an entry whose `flushing` lock the caller already holds, retired or not.
+    fn flush_held(&self, e: &CacheEntry, flushing: &MutexGuard<'_, ()>) -> io::Result<bool> {
+        self.announce_flush(e.record_flush(&self.metrics, || e.write_snapshot(flushing, &self.dir))?)
+    }
+
+    fn announce_flush(&self, flushed: Option<(String, usize)>) -> io::Result<bool> {
+        match flushed {
+            Some((name, count)) => { self.events.publish(&name, EventKind::Flush { count }); Ok(true) }
+            None => Ok(false),
+        }
+    }
+
+    // Takes `names` for a lifecycle operation until the returned guard is dropped; a name
+    // another operation holds is `Busy`.
+    fn reserve(&self, names: &[&str]) -> Result<Reservation, ApiError> {
+        let _map = self.dbs.write()?;
+        let mut busy = self.busy.lock()?;
+        if let Some(name) = names.iter().find(|n| busy.contains(**n)) { return Err(ApiError::Busy(name.to_string())); }
+        busy.extend(names.iter().map(|n| n.to_string()));
+        self.lifecycle.fetch_add(1, Ordering::SeqCst);
+        Ok(Reservation { state: self.clone(), names: names.iter().map(|n| n.to_string()).collect() })
+    }
+
+    // Removes a retired entry from the map, unless something else has replaced it already.
+    fn forget(&self, name: &str, entry: &Arc<CacheEntry>) -> Result<(), ApiError> {
+        let mut map = self.dbs.write()?;
+        if map.get(name).is_some_and(|cur| Arc::ptr_eq(cur, entry)) { map.remove(name); }
+        Ok(())
+    }
+
+    // Every lookup by name starts here, so a name that could escape the data dir never reaches the disk.
+    fn cached(&self, name: &str) -> Result<Option<Arc<CacheEntry>>, ApiError> {
+        ver::validate_name(name)?;
+        let entry = self.dbs.read()?.get(name).cloned();
+        match &entry {
+            Some(e) => { e.touch(); self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed); }
+            None => { self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed); }
+        }
+        Ok(entry)
+    }
+
+    // Keeps whichever entry got into the map first if two requests loaded the same DB. A DB
+    // read from disk is only cached if no lifecycle operation started or ended since
+    // `loaded_at`; otherwise the files may have changed under the load, and None says to redo it.
+    fn insert_entry(&self, name: &str, db: Database, dirty: bool, loaded_at: Option<u64>) -> Result<Option<Arc<CacheEntry>>, ApiError> {
+        let mut map = self.dbs.write()?;
+        if self.busy.lock()?.contains(name) { return Err(ApiError::Busy(name.to_string())); }
+        if loaded_at.is_some_and(|g| g != self.lifecycle.load(Ordering::SeqCst)) && !map.contains_key(name) { return Ok(None); }
+        let entry = map.entry(name.to_string()).or_insert_with(|| Arc::new(CacheEntry::new(db))).clone();
+        if dirty { entry.dirty.store(true, Ordering::SeqCst); }
+        Ok(Some(entry))
+    }
+
+    // Look a DB up in the cache, loading it from disk on a blocking thread on a miss.
+    async fn entry(&self, name: &str) -> Result<Arc<CacheEntry>, ApiError> {
+        loop {
+            if let Some(e) = self.cached(name)? { return Ok(e); }
+            if self.busy.lock()?.contains(name) { return Err(ApiError::Busy(name.to_string())); }
+            let loaded_at = self.lifecycle.load(Ordering::SeqCst);
+            let (dir, n) = (self.dir.clone(), name.to_string());
+            let (db, replayed) = blocking(move || load_db(&dir, &n).map_err(|e| not_found_or_io(e, &n)))
+                .instrument(tracing::info_span!("load", db = %name))
+                .await?;
+            self.metrics.disk_loads.fetch_add(1, Ordering::Relaxed);
+            let Some(entry) = self.insert_entry(name, db, replayed, Some(loaded_at))? else { continue };
+            let state = self.clone();
+            blocking(move || state.evict_if_needed()).await?;
+            return Ok(entry);
+        }
+    }
+
+    // Like `entry`, but creates an empty DB of the given dimension if it doesn't exist yet and
+    // `create` is set; otherwise a missing DB stays `DbNotFound`.
+    async fn entry_or_create(&self, name: &str, dimension: usize, create: bool) -> Result<Arc<CacheEntry>, ApiError> {
+        match self.entry(name).await {
+            Err(ApiError::DbNotFound(_)) if create => {
+                self.insert_entry(name, Database::new(name.to_string(), dimension), false, None)?.ok_or_else(|| ApiError::Busy(name.to_string()))
+            }
+            Err(ApiError::DbNotFound(_)) => Err(not_creatable(name)),
+            other => other,
+        }
+    }
+
+    fn reject_if_cache_full(&self) -> Result<(), ApiError> {
+        if self.cache_full.load(Ordering::SeqCst) {
+            self.metrics.writes_rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(ApiError::CacheFull);
+        }
+        Ok(())
+    }
+
+    fn evict_if_needed(&self) -> Result<(), ApiError> {
+        // TTL eviction; dirty entries stay until the flush loop has persisted them
+        {
+            let mut map = self.dbs.write()?;
+            let now = Instant::now();
+            let before = map.len();
+            map.retain(|_, e| now.duration_since(e.last_access()) <= self.cache_ttl || e.is_dirty());
+            self.metrics.evictions_ttl.fetch_add((before - map.len()) as u64, Ordering::Relaxed);
+        }
+
+        // Byte-size based eviction: estimate bytes and evict clean & oldest first. Dirty victims
+        // are flushed before they are dropped, without holding the map lock during the I/O.
+        let mut entries: Vec<(String, Arc<CacheEntry>, Instant, bool, usize)> = self.dbs.read()?.iter()
+            .map(|(k, e)| (k.clone(), e.clone(), e.last_access(), e.is_dirty(), e.bytes()))
+            .collect();
+        let mut total_bytes: usize = entries.iter().map(|(_, _, _, _, sz)| sz).sum();
+        if total_bytes > self.cache_max_bytes {
+            entries.sort_by_key(|(_, _, last, dirty, _)| (*dirty, *last));
+            for (k, e, _, dirty, sz) in entries {
+                if total_bytes <= self.cache_max_bytes { break; }
+                if dirty {
+                    self.metrics.eviction_flushes.fetch_add(1, Ordering::Relaxed);
+                    if let Err(err) = self.flush_entry(&e) {
+                        tracing::warn!(db = %k, error = %err, "not evicting: flush failed");
+                        self.metrics.eviction_flush_failures.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                }
+                let mut map = self.dbs.write()?;
+                // an insert may have landed since the flush; keep the entry rather than lose it
+                if e.is_dirty() || !map.get(&k).is_some_and(|cur| Arc::ptr_eq(cur, &e)) { continue; }
+                map.remove(&k);
+                self.metrics.evictions_size.fetch_add(1, Ordering::Relaxed);
+                total_bytes = total_bytes.saturating_sub(sz);
+            }
+        }
+        self.cache_full.store(total_bytes > self.cache_max_bytes, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // Reasons the server should not receive traffic; empty when ready.
+    fn readiness(&self) -> Result<Vec<String>, ApiError> {
+        let mut problems = Vec::new();
+        if let Some(r) = &self.replica {
+            if !r.bootstrapped() { problems.push(format!("still copying data from the leader {}", r.leader())); }
+        }
+        // a name of its own, so concurrent probes don't remove each other's file
+        static PROBE_SEQ: AtomicU64 = AtomicU64::new(0);
+        let probe = std::path::Path::new(&self.dir).join(format!(".readyz.{}.{}", std::process::id(), PROBE_SEQ.fetch_add(1, Ordering::Relaxed)));
+        let writable = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&probe, b"ok"))
+            .and_then(|_| std::fs::remove_file(&probe));
+        if let Err(e) = writable { problems.push(format!("data dir '{}' is not writable: {}", self.dir, e)); }
+        let mut failed: Vec<(String, String)> = self.dbs.read()?.iter()
+            .filter_map(|(k, e)| e.flush_error().map(|err| (k.clone(), err)))
+            .collect();
+        failed.sort();
+        for (name, err) in failed { problems.push(format!("last flush of '{}' failed: {}", name, err)); }
+        Ok(problems)
+    }
+
+    // Record search latency, and log the search if it crossed the slow-query threshold.
+    fn record_query(&self, db: &str, metric: &str, k: Option<usize>, queries: usize, elapsed: Duration) {
+        self.metrics.observe_query(db, metric, elapsed);
+        if !self.slow_query.is_zero() && elapsed >= self.slow_query {
+            tracing::warn!(target: "vectra::slow_query", db, metric, k, queries, duration_ms = elapsed.as_millis() as u64, "slow query");
+        }
+    }
+
+    // Flush every dirty DB, logging failures. Returns the number of DBs that failed to flush.
+    pub fn flush_dirty(&self) -> usize {
+        let entries: Vec<(String, Arc<CacheEntry>)> = match self.dbs.read() {
+            Ok(map) => map.iter().map(|(k, e)| (k.clone(), e.clone())).collect(),
+            Err(_) => { tracing::error!("flush skipped: cache lock poisoned"); return 1; }
+        };
+        let mut failed = 0;
+        for (name, e) in entries {
+            if let Err(err) = self.flush_entry(&e) {
+                tracing::error!(db = %name, error = %err, "flush failed");
+                failed += 1;
+            }
+        }
+        if let Err(e) = self.evict_if_needed() { tracing::error!(error = %e, "eviction failed"); }
+        failed
+    }
+}
+
+// Load a DB and replay any write-ahead log left behind by a previous process, cutting off a
+// record torn by a crash so that new appends stay readable.
+fn load_db(dir: &str, name: &str) -> io::Result<(Database, bool)> {
+    let (db, replayed) = wal::load(dir, name, true)?;
+    Ok((db, replayed > 0))
+}
+
+fn not_found_or_io(e: io::Error, name: &str) -> ApiError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ApiError::DbNotFound(name.to_string()),
+        _ => ApiError::Io(e),
+    }
+}
+
+// Cooperative stop signal for a search running on a blocking thread. It fires when the deadline
+// passes or when the handler future is dropped because the client went away.
+// Names held by one lifecycle operation; released when dropped, which also happens if the
+// request that started it is cancelled, since the guard lives on the blocking thread.
+struct Reservation { state: AppState, names: Vec<String> }
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let Ok(_map) = self.state.dbs.write() else { return };
+        let Ok(mut busy) = self.state.busy.lock() else { return };
+        for n in &self.names { busy.remove(n); }
+        self.state.lifecycle.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+struct Cancel { deadline: Option<Instant>, abandoned: Arc<AtomicBool> }
+
+// Held by the handler; dropping it (normally or on disconnect) abandons the search.
+struct AbandonOnDrop(Arc<AtomicBool>);
+
+impl Drop for AbandonOnDrop {
+    fn drop(&mut self) { self.0.store(true, Ordering::Relaxed); }
+}
+
+impl Cancel {
+    fn new(timeout: Option<Duration>) -> (Self, AbandonOnDrop) {
+        let abandoned = Arc::new(AtomicBool::new(false));
+        (Cancel { deadline: timeout.map(|t| Instant::now() + t), abandoned: abandoned.clone() }, AbandonOnDrop(abandoned))
+    }
+
+    fn should_stop(&self) -> bool {
+        self.abandoned.load(Ordering::Relaxed) || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+// A search that stopped early (and was allowed to) is flagged with a header.
+fn search_response<T: Serialize>(format: Format, res: T, complete: bool) -> Response {
+    if complete { return Reply(format, res).into_response(); }
+    ([(header::HeaderName::from_static("x-search-incomplete"), "true")], Reply(format, res)).into_response()
+}
+
+// Run CPU-heavy search or disk I/O off the async workers, inside the caller's span.
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where F: FnOnce() -> Result<T, ApiError> + Send + 'static, T: Send + 'static {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f)).await.map_err(|e| ApiError::Internal(e.to_string()))?
+}
+
+pub fn spawn_flush_loop(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(state.flush_interval).await;
+            let s = state.clone();
+            let _ = tokio::task::spawn_blocking(move || s.flush_dirty()).await;
+        }
+    });
+}
+
+// Resolves on SIGINT or SIGTERM so the server can stop accepting and drain in-flight requests.
+pub async fn shutdown_signal() {
+    let ctrl_c = async { let _ = tokio::signal::ctrl_c().await; };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => { s.recv().await; }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! { _ = ctrl_c => {}, _ = terminate => {} }
+}
+
+/// The REST operations as OpenAPI paths; `openapi` adds the document-level parts.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    create_db, list_dbs, drop_db, rename_db, clone_db, info_db,
+    insert_vec, insert_batch, find_vec, find_batch, get_vector, scroll_vectors,
+    flush_db, evict_db, status, metrics, healthz, readyz, crate::events::subscribe,
+    webhooks::create_webhook, webhooks::list_webhooks, webhooks::delete_webhook, snapshot, tail_vectors,
+))]
+pub(crate) struct Paths;
+
+// Routes are grouped by the scope an API key needs for them; with `auth` unset every group is open.
+pub fn router(state: AppState, auth: Option<Arc<AuthConfig>>) -> Router {
+    // the rate limiter is the outer layer so requests the guard turns away are charged as well
+    let rate = RateGuard { limits: state.limits.clone(), auth: auth.clone() };
+    let protect = |routes: Router<AppState>, scope| routes
+        .route_layer(middleware::from_fn_with_state(Guard { auth: auth.clone(), scope }, crate::auth::require))
+        .route_layer(middleware::from_fn_with_state(rate.clone(), crate::limits::rate_limit));
+    let read = protect(Router::new()
+        .route("/db/:name/find", post(find_vec))
+        .route("/db/:name/find_batch", post(find_batch))
+        .route("/db/:name/info", get(info_db))
+        .route("/db/:name/vectors/:id", get(get_vector))
+        .route("/db/:name/scroll", post(scroll_vectors))
+        .route("/db/:name/snapshot", get(snapshot))
+        .route("/db/:name/tail", get(tail_vectors)), Scope::Read);
+    let write = protect(Router::new()
+        .route("/db/:name/insert", post(insert_vec))
+        .route("/db/:name/insert_batch", post(insert_batch)), Scope::Write);
+    let admin = protect(Router::new()
+        .route("/create", post(create_db))
+        .route("/db/:name", delete(drop_db))
+        .route("/db/:name/rename", post(rename_db))
+        .route("/db/:name/clone", post(clone_db))
+        .route("/db/:name/flush", post(flush_db))
+        .route("/db/:name/evict", post(evict_db))
+        .route("/db/:name/webhooks", post(webhooks::create_webhook).get(webhooks::list_webhooks))
+        .route("/db/:name/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/status", get(status))
+        .route("/metrics", get(metrics)), Scope::Admin);
+    let any = protect(Router::new()
+        .route("/dbs", get(list_dbs))
+        .route("/events", get(crate::events::subscribe)), Scope::Any);
+    // probes stay open so orchestrators don't need a key
+    let public = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .merge(crate::openapi::docs());
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .merge(admin)
+        .merge(any)
+        .merge(public)
+        .layer(DefaultBodyLimit::max(state.limits.max_body_bytes))
+        .layer(TraceLayer::new_for_http()
+            .make_span_with(DefaultMakeSpan::new().level(tracing::Level::INFO))
+            .on_response(DefaultOnResponse::new().level(tracing::Level::INFO)))
+        .with_state(state)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateReq { name: String, dimension: usize }
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct InsertReq { pub values: Vec<f64>, #[serde(default)] pub meta: HashMap<String, String> }
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct RowError { pub row: usize, pub code: &'static str, pub error: String }
+
+/// Outcome of a batch insert: an id per row (`None` where the row was rejected), the
+/// rejections, and the database size afterwards.
+pub(crate) struct BatchOutcome { pub ids: Vec<Option<usize>>, pub errors: Vec<RowError>, pub total: usize }
+
+// Search fields shared by `find` and `find_batch`.
+#[derive(Deserialize, ToSchema)]
+struct SearchOpts { k: Option<usize>, f: Option<String>, radius: Option<f64>, max_results: Option<usize>, filter: Option<HashMap<String, String>>, timeout_ms: Option<u64>, on_timeout: Option<OnTimeout> }
+
+#[derive(Deserialize, ToSchema)]
+struct FindReq { values: Vec<f64>, #[serde(flatten)] opts: SearchOpts }
+
+// `packed` marks a raw body whose queries still have to be split by the DB dimension.
+#[derive(Deserialize, ToSchema)]
+struct FindBatchReq { queries: Vec<Vec<f64>>, #[serde(flatten)] opts: SearchOpts, #[serde(skip)] packed: bool }
+
+/// What a search does when it runs out of time: fail with `504 timeout` (the default), or
+/// return what it has ranked so far with an `X-Search-Incomplete: true` header.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum OnTimeout { #[default] Error, Partial }
+
+/// Search options common to every front end. `timeout` falls back to `--query-timeout-ms`;
+/// with `partial` a timed-out search returns what it has instead of failing.
+pub(crate) struct SearchParams {
+    pub k: Option<usize>,
+    pub metric: Option<String>,
+    pub radius: Option<f64>,
+    pub max_results: Option<usize>,
+    pub filter: Option<HashMap<String, String>>,
+    pub timeout: Option<Duration>,
+    pub partial: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct FindItem { pub index: usize, pub distance: f64, pub values: Vec<f64>, pub metadata: HashMap<String, String> }
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetVectorQuery { with_values: Option<bool> }
+
+#[derive(Deserialize, ToSchema)]
+struct ScrollReq { cursor: Option<String>, limit: Option<usize>, filter: Option<HashMap<String, String>>, with_values: Option<bool> }
+
+#[derive(Serialize, ToSchema)]
+struct VectorItem { id: usize, #[serde(skip_serializing_if = "Option::is_none")] values: Option<Vec<f64>>, metadata: HashMap<String, String> }
+
+#[derive(Serialize, ToSchema)]
+struct ScrollResp { items: Vec<VectorItem>, next_cursor: Option<String> }
+
+#[derive(Deserialize, ToSchema)]
+struct TargetReq { to: String }
+
+#[derive(Serialize, ToSchema)]
+struct CacheStatus {
+    name: String,
+    bytes: usize,
+    dirty: bool,
+    last_access: DateTime<Utc>,
+    last_flush: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flush_error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct StatusResp {
+    durability: &'static str,
+    cache_bytes: usize,
+    cache_max_bytes: usize,
+    cache_full: bool,
+    /// Sequence number of the latest change event
+    event_seq: u64,
+    /// Present on a follower
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replication: Option<ReplicationStatus>,
+    dbs: Vec<CacheStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DbSummary { name: String, dimension: usize, count: usize, #[serde(skip_serializing_if = "Option::is_none")] flush_error: Option<String> }
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OkResp { pub ok: bool }
+
+#[derive(Serialize, ToSchema)]
+struct InsertResp { ok: bool, id: usize, total: usize, durability: &'static str }
+
+#[derive(Serialize, ToSchema)]
+struct InsertBatchResp { ok: bool, inserted: usize, ids: Vec<Option<usize>>, errors: Vec<RowError>, total: usize, durability: &'static str }
+
+#[derive(Serialize, ToSchema)]
+struct FlushResp { ok: bool, flushed: bool }
+
+#[derive(Serialize, ToSchema)]
+struct EvictResp { ok: bool, evicted: bool }
+
+#[derive(Serialize, ToSchema)]
+struct ReadyResp { ready: bool, problems: Vec<String> }
+
+// Operations shared by the REST handlers and the gRPC service. Callers have already
+// authorized the request; limits that depend on the request shape are checked here.
+impl AppState {
+    pub(crate) fn limits(&self) -> &Limits { &self.limits }
+
+    pub(crate) fn durability(&self) -> &'static str { self.write_mode.durability() }
+
+    pub(crate) async fn create(&self, name: String, dimension: usize) -> Result<(), ApiError> {
+        self.writable()?;
+        ver::validate_name(&name)?;
+        let (state, _name) = (self.clone(), self.reserve(&[&name])?);
+        blocking(move || {
+            let _name = _name;
+            if state.dbs.read()?.contains_key(&name) || !ver::db_files(&state.dir, &name).is_empty() { return Err(ApiError::DbExists(name)); }
+            let db = Database::new(name.clone(), dimension);
+            db.save_to_dir(&state.dir)?;
+            state.events.publish(&name, EventKind::Create { dimension });
+            state.dbs.write()?.insert(name, Arc::new(CacheEntry::new(db)));
+            Ok(())
+        }).await
+    }
+
+    // Returns the database size after the insert; the new vector's id is one less. A missing
+    // database is created on the fly only when `create` is set, i.e. the caller could create it.
+    pub(crate) async fn insert_one(&self, name: &str, req: InsertReq, create: bool) -> Result<usize, ApiError> {
+        self.writable()?;
+        self.reject_if_cache_full()?;
+        let entry = self.entry_or_create(name, req.values.len(), create).await?;
+        let (state, name, mode) = (self.clone(), name.to_string(), self.write_mode);
+        blocking(move || {
+            let total = {
+                let mut db = entry.db.write()?;
+                entry.check_live(&name)?;
+                if db.dimension != req.values.len() { return Err(ApiError::DimensionMismatch { expected: db.dimension, actual: req.values.len() }); }
+                let v = to_vector(req);
+                if mode == WriteMode::Wal { wal::append(&state.dir, &name, &[(db.vectors.len(), &v)])?; }
+                db.insert(v)?;
+                entry.dirty.store(true, Ordering::SeqCst);
+                entry.update_bytes(&db);
+                let total = db.vectors.len();
+                state.events.publish(&name, EventKind::Insert { first_id: total - 1, count: 1, total });
+                total
+            };
+            state.metrics.add_inserts(&name, 1);
+            if mode == WriteMode::WriteThrough { state.flush_entry(&entry)?; }
+            state.evict_if_needed()?;
+            Ok(total)
+        }).await
+    }
+
+    // Rows that failed to parse upstream are passed in as errors so they are reported by position.
+    pub(crate) async fn insert_rows(&self, name: &str, rows: Vec<Result<InsertReq, ApiError>>, create: bool) -> Result<BatchOutcome, ApiError> {
+        self.writable()?;
+        self.reject_if_cache_full()?;
+        self.limits.check_batch("rows", rows.len())?;
+        let first_dim = rows.iter().find_map(|r| r.as_ref().ok().map(|r| r.values.len()));
+        let entry = match first_dim {
+            Some(dim) => self.entry_or_create(name, dim, create).await?,
+            None => match self.entry(name).await {
+                Err(ApiError::DbNotFound(_)) if !create => return Err(not_creatable(name)),
+                other => other?,
+            },
+        };
+        let (state, name, mode) = (self.clone(), name.to_string(), self.write_mode);
+        let span = tracing::info_span!("insert_batch", db = %name, rows = rows.len());
+        blocking(move || {
+            // all rows are applied under one write lock so the flush task never persists half a batch
+            let mut db = entry.db.write()?;
+            entry.check_live(&name)?;
+            let mut ids: Vec<Option<usize>> = Vec::with_capacity(rows.len());
+            let mut errors = Vec::new();
+            let mut accepted = Vec::new();
+            for (row, r) in rows.into_iter().enumerate() {
+                match r {
+                    Ok(req) if req.values.len() == db.dimension => {
+                        ids.push(Some(db.vectors.len() + accepted.len()));
+                        accepted.push(to_vector(req));
+                    }
+                    Ok(req) => {
+                        let e = ApiError::DimensionMismatch { expected: db.dimension, actual: req.values.len() };
+                        ids.push(None); errors.push(RowError { row, code: e.code(), error: e.to_string() });
+                    }
+                    Err(e) => { ids.push(None); errors.push(RowError { row, code: e.code(), error: e.to_string() }); }
+                }
+            }
+            if mode == WriteMode::Wal && !accepted.is_empty() {
+                let base = db.vectors.len();
+                let records: Vec<(usize, &Vector<f64>)> = accepted.iter().enumerate().map(|(i, v)| (base + i, v)).collect();
+                wal::append(&state.dir, &name, &records)?;
+            }
+            let (any, count, first_id) = (!accepted.is_empty(), accepted.len(), db.vectors.len());
+            state.metrics.add_inserts(&name, count);
+            for v in accepted { db.insert(v)?; }
+            let total = db.vectors.len();
+            if any {
+                entry.dirty.store(true, Ordering::SeqCst);
+                entry.update_bytes(&db);
+                state.events.publish(&name, EventKind::Insert { first_id, count, total });
+            }
+            drop(db);
+            if any && mode == WriteMode::WriteThrough { state.flush_entry(&entry)?; }
+            state.evict_if_needed()?;
+            Ok(BatchOutcome { ids, errors, total })
+        }).instrument(span).await
+    }
+
+    // Runs every query with the same options and returns one result list per query, plus
+    // whether all of them finished. An unfinished search is an error unless `partial` is set.
+    pub(crate) async fn search(&self, name: &str, queries: Vec<Vec<f64>>, p: SearchParams) -> Result<(Vec<Vec<FindItem>>, bool), ApiError> {
+        let metric = parse_metric(p.metric.as_deref())?;
+        let timeout = p.timeout.or(self.limits.query_timeout);
+        let (cancel, _abandon) = Cancel::new(timeout);
+        let code = p.metric.unwrap_or_else(|| "eu".to_string());
+        let n = queries.len();
+        self.limits.check_batch("queries", n)?;
+        let mode = bounded_mode(&self.limits, p.k, p.radius, p.max_results)?;
+        let permit = self.limits.search_permit()?;
+        let entry = self.entry(name).await?;
+        let span = tracing::info_span!("search", db = %name, metric = %code, k = p.k, queries = n);
+        let started = Instant::now();
+        let filter = p.filter;
+        let (res, complete) = blocking(move || {
+            let _permit = permit;
+            let db = entry.db.read()?;
+            if let Some(q) = queries.iter().find(|q| q.len() != db.dimension) {
+                return Err(ApiError::DimensionMismatch { expected: db.dimension, actual: q.len() });
+            }
+            let db = &*db;
+            let results: Vec<(Vec<FindItem>, bool)> = queries.par_iter()
+                .map(|q| {
+                    let (scored, complete) = db.search_until(q, &metric, &mode, filter.as_ref(), &|| cancel.should_stop());
+                    (to_find_items(db, scored), complete)
+                })
+                .collect();
+            let complete = results.iter().all(|(_, c)| *c);
+            Ok((results.into_iter().map(|(items, _)| items).collect::<Vec<_>>(), complete))
+        }).instrument(span).await?;
+        self.record_query(name, &code, p.k, n, started.elapsed());
+        if !complete && !p.partial { return Err(ApiError::Timeout(timeout.unwrap_or_default().as_millis())); }
+        Ok((res, complete))
+    }
+
+    pub(crate) async fn dimension(&self, name: &str) -> Result<usize, ApiError> {
+        let entry = self.entry(name).await?;
+        let dimension = entry.db.read()?.dimension;
+        Ok(dimension)
+    }
+
+    // A loaded DB answers from memory, so rows not yet flushed are counted. Otherwise the files
+    // are scanned without pulling the DB into the cache, unless a WAL holds rows they lack.
+    pub(crate) async fn info(&self, name: &str) -> Result<InfoResp, ApiError> {
+        let entry = match self.cached(name)? {
+            Some(entry) => entry,
+            None if wal::exists(&self.dir, name) => self.entry(name).await?,
+            None => {
+                let (dir, name) = (self.dir.clone(), name.to_string());
+                return blocking(move || compute_db_info(&dir, &name).map_err(|e| not_found_or_io(e, &name))).await;
+            }
+        };
+        let name = name.to_string();
+        blocking(move || Ok(db_info(&name, &*entry.db.read()?))).await
+    }
+
+    pub(crate) async fn drop_db(&self, name: &str) -> Result<(), ApiError> {
+        self.writable()?;
+        ver::validate_name(name)?;
+        let (state, _name) = (self.clone(), self.reserve(&[name])?);
+        let name = name.to_string();
+        blocking(move || {
+            let _name = _name;
+            let cached = state.dbs.read()?.get(&name).cloned();
+            let _flushing = cached.as_ref().map(|e| e.retire()).transpose()?;
+            match ver::drop_db(&state.dir, &name) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound && cached.is_some() => {}
+                Err(e) => {
+                    if let Some(e) = &cached { e.revive(); }
+                    return Err(if e.kind() == io::ErrorKind::NotFound { ApiError::DbNotFound(name) } else { e.into() });
+                }
+            }
+            if let Some(e) = &cached { state.forget(&name, e)?; }
+            state.events.publish(&name, EventKind::Drop);
+            Ok(())
+        }).await
+    }
+
+    pub(crate) async fn rename(&self, name: &str, to: String) -> Result<(), ApiError> {
+        self.writable()?;
+        ver::validate_name(name)?;
+        ver::validate_name(&to)?;
+        let (state, _names) = (self.clone(), self.reserve(&[name, &to])?);
+        let name = name.to_string();
+        blocking(move || {
+            let _names = _names;
+            let (cached, taken) = {
+                let map = state.dbs.read()?;
+                (map.get(&name).cloned(), map.contains_key(&to))
+            };
+            if taken { return Err(ApiError::DbExists(to)); }
+            if let Some(entry) = &cached {
+                // persist unflushed inserts so the files carry the latest data; once retired, no
+                // insert can slip in after that flush and land in a file that is about to move
+                let flushing = entry.retire()?;
+                let moved = state.flush_held(entry, &flushing).and_then(|_| ver::rename_db(&state.dir, &name, &to));
+                if let Err(e) = moved { entry.revive(); return Err(e.into()); }
+                state.forget(&name, entry)?;
+            } else {
+                ver::rename_db(&state.dir, &name, &to)?;
+            }
+            state.events.publish(&name, EventKind::Rename { to });
+            Ok(())
+        }).await
+    }
+
+    pub(crate) async fn clone_db(&self, name: &str, to: String) -> Result<(), ApiError> {
+        self.writable()?;
+        ver::validate_name(name)?;
+        ver::validate_name(&to)?;
+        let (state, _names) = (self.clone(), self.reserve(&[name, &to])?);
+        let name = name.to_string();
+        blocking(move || {
+            let _names = _names;
+            let (cached, taken) = {
+                let map = state.dbs.read()?;
+                (map.get(&name).cloned(), map.contains_key(&to))
+            };
+            if taken { return Err(ApiError::DbExists(to)); }
+            // the source stays live; holding its flush lock keeps the files still while they are copied
+            let flushing = cached.as_ref().map(|e| e.flushing.lock()).transpose()?;
+            if let (Some(e), Some(f)) = (&cached, &flushing) { state.flush_held(e, f)?; }
+            ver::clone_db(&state.dir, &name, &to)?;
+            drop(flushing);
+            state.events.publish(&name, EventKind::Clone { to });
+            Ok(())
+        }).await
+    }
+
+    // Databases on disk or only in the cache.
+    pub(crate) fn db_names(&self) -> Result<Vec<String>, ApiError> {
+        let mut names = ver::list_dbs(&self.dir).unwrap_or_default();
+        for k in self.dbs.read()?.keys() {
+            if !names.contains(k) { names.push(k.clone()); }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub(crate) async fn count(&self, name: &str) -> Result<usize, ApiError> {
+        let entry = self.entry(name).await?;
+        let count = entry.db.read()?.vectors.len();
+        Ok(count)
+    }
+
+    /// Replaces a database wholesale with a copy taken from the leader.
+    pub(crate) async fn install(&self, db: Database) -> Result<(), ApiError> {
+        self.writable()?;
+        ver::validate_name(&db.name)?;
+        let (state, _name) = (self.clone(), self.reserve(&[&db.name])?);
+        blocking(move || {
+            let _name = _name;
+            let old = state.dbs.read()?.get(&db.name).cloned();
+            let _flushing = old.as_ref().map(|e| e.retire()).transpose()?;
+            let existed = old.is_some() || !ver::db_files(&state.dir, &db.name).is_empty();
+            // shards and logs from an older copy would be merged back in on the next load
+            let replaced = match ver::drop_db(&state.dir, &db.name) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => db.save_to_dir(&state.dir),
+            };
+            if let Err(e) = replaced {
+                // the old copy's files may be gone, so it must not be served again
+                if let Some(old) = &old { state.forget(&db.name, old)?; }
+                return Err(e.into());
+            }
+            if !existed { state.events.publish(&db.name, EventKind::Create { dimension: db.dimension }); }
+            state.dbs.write()?.insert(db.name.clone(), Arc::new(CacheEntry::new(db)));
+            Ok(())
+        }).await?;
+        let state = self.clone();
+        blocking(move || state.evict_if_needed()).await
+    }
+
+    /// Appends vectors copied from the leader, where `from` is the id of the first one.
+    /// Vectors the database already has are skipped, so replaying a range is harmless.
+    /// Returns the database size afterwards.
+    pub(crate) async fn apply_vectors(&self, name: &str, from: usize, vectors: Vec<Vector<f64>>) -> Result<usize, ApiError> {
+        self.writable()?;
+        let entry = self.entry(name).await?;
+        let (state, name, mode) = (self.clone(), name.to_string(), self.write_mode);
+        blocking(move || {
+            let mut db = entry.db.write()?;
+            entry.check_live(&name)?;
+            let have = db.vectors.len();
+            if from > have { return Err(ApiError::BadRequest(format!("'{}' has {} vectors, cannot append from id {}", name, have, from))); }
+            let fresh: Vec<Vector<f64>> = vectors.into_iter().skip(have - from).collect();
+            if fresh.is_empty() { return Ok(have); }
+            if mode == WriteMode::Wal {
+                let records: Vec<(usize, &Vector<f64>)> = fresh.iter().enumerate().map(|(i, v)| (have + i, v)).collect();
+                wal::append(&state.dir, &name, &records)?;
+            }
+            let count = fresh.len();
+            for v in fresh { db.insert(v)?; }
+            entry.dirty.store(true, Ordering::SeqCst);
+            entry.update_bytes(&db);
+            let total = db.vectors.len();
+            state.events.publish(&name, EventKind::Insert { first_id: have, count, total });
+            drop(db);
+            if mode == WriteMode::WriteThrough { state.flush_entry(&entry)?; }
+            state.evict_if_needed()?;
+            Ok(total)
+        }).await
+    }
+}
+
+#[utoipa::path(post, path = "/create", tag = "databases",
+    request_body(content((CreateReq = "application/json"), (CreateReq = "application/msgpack"))),
+    responses((status = 200, description = "Database created", body = OkResp)))]
+async fn create_db(State(state): State<AppState>, Accept(format): Accept, Payload(req): Payload<CreateReq>) -> Result<Reply<OkResp>, ApiError> {
+    state.create(req.name, req.dimension).await?;
+    Ok(Reply(format, OkResp { ok: true }))
+}
+
+#[utoipa::path(post, path = "/db/{name}/insert", tag = "vectors", params(("name" = String, Path, description = "Database name")),
+    request_body(description = "A raw f64 body is the vector; query parameters become metadata", content((InsertReq = "application/json"), (InsertReq = "application/msgpack"), (Vec<f64> = "application/octet-stream"))),
+    responses((status = 200, description = "Vector stored", body = InsertResp)))]
+async fn insert_vec(State(state): State<AppState>, ApiPath(name): ApiPath<String>, key: Option<Extension<Arc<ApiKey>>>, Accept(format): Accept, Payload(req): Payload<InsertReq>) -> Result<Reply<InsertResp>, ApiError> {
+    let total = state.insert_one(&name, req, may_create(key.as_deref())).await?;
+    Ok(Reply(format, InsertResp { ok: true, id: total - 1, total, durability: state.durability() }))
+}
+
+// Inserting into a missing database creates it, which is an admin operation when auth is on.
+pub(crate) fn may_create(key: Option<&Arc<ApiKey>>) -> bool {
+    key.is_none_or(|k| k.can_admin())
+}
+
+// What a key that may not create databases is told when it inserts into one that is missing.
+fn not_creatable(name: &str) -> ApiError {
+    ApiError::Forbidden(format!("database '{}' does not exist, and only an admin key may create it by inserting", name))
+}
+
+fn to_vector(req: InsertReq) -> Vector<f64> {
+    let mut meta = Vec::new();
+    for (k,v) in req.meta.into_iter() { meta.push(MetadataEntry::new(k, MetadataValue::String(v))); }
+    meta.push(MetadataEntry::new("created_at".to_string(), MetadataValue::DateTime(Utc::now())));
+    Vector::new(req.values, meta)
+}
+
+// Body is a JSON or MessagePack array of InsertReq, or NDJSON (one InsertReq per line, selected
+// by Content-Type: application/x-ndjson). NDJSON is parsed as chunks arrive so the raw body is
+// never buffered whole. Rows that fail to parse are reported and skipped.
+// `Body` bypasses `DefaultBodyLimit`, so the size and row limits are enforced here while reading.
+pub(crate) async fn read_insert_rows(format: Format, headers: &HeaderMap, body: Body, limits: &Limits) -> Result<Vec<Result<InsertReq, ApiError>>, ApiError> {
+    let ndjson = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok())
+        .map(|ct| ct.starts_with("application/x-ndjson") || ct.starts_with("application/jsonl"))
+        .unwrap_or(false);
+    let parse_row = |raw: &[u8]| serde_json::from_slice::<InsertReq>(raw).map_err(|e| ApiError::InvalidBody(e.to_string()));
+    if !ndjson {
+        let bytes = axum::body::to_bytes(body, limits.max_body_bytes).await.map_err(|_| body_too_large(limits))?;
+        // rows are decoded one by one from a generic value so a bad row doesn't fail the batch
+        let rows: Vec<serde_json::Value> = match format {
+            Format::MsgPack => rmp_serde::from_slice(&bytes).map_err(|e| ApiError::InvalidBody(format!("expected MessagePack array: {}", e)))?,
+            _ => serde_json::from_slice(&bytes).map_err(|e| ApiError::InvalidBody(format!("expected JSON array: {}", e)))?,
+        };
+        limits.check_batch("rows", rows.len())?;
+        return Ok(rows.into_iter().map(|v| serde_json::from_value::<InsertReq>(v).map_err(|e| ApiError::InvalidBody(e.to_string()))).collect());
+    }
+    let mut rows = Vec::new();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut read = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        read += chunk.len();
+        if read > limits.max_body_bytes { return Err(body_too_large(limits)); }
+        // only the new bytes are searched for newlines, and complete lines are dropped from the
+        // front once per chunk, so a long body is read in linear time
+        let mut start = 0;
+        let mut scan = buf.len();
+        buf.extend_from_slice(&chunk);
+        while let Some(pos) = buf[scan..].iter().position(|b| *b == b'\n') {
+            let line = &buf[start..scan + pos];
+            start = scan + pos + 1;
+            scan = start;
+            if line.iter().all(|b| b.is_ascii_whitespace()) { continue; }
+            rows.push(parse_row(line));
+            limits.check_batch("rows", rows.len())?;
+        }
+        buf.drain(..start);
+    }
+    if !buf.iter().all(|b| b.is_ascii_whitespace()) { rows.push(parse_row(&buf)); }
+    limits.check_batch("rows", rows.len())?;
+    Ok(rows)
+}
+
+// A raw batch is rows laid end to end, split by `?dimension=` or else the existing DB's
+// dimension. The other query parameters become metadata on every row.
+async fn raw_insert_rows(state: &AppState, name: &str, query: Vec<(String, String)>, body: Body) -> Result<Vec<Result<InsertReq, ApiError>>, ApiError> {
+    let bytes = axum::body::to_bytes(body, state.limits.max_body_bytes).await.map_err(|_| body_too_large(&state.limits))?;
+    let dimension = match codec::param::<usize>(&query, "dimension")? {
+        Some(d) => d,
+        None => state.dimension(name).await?,
+    };
+    let meta: HashMap<String, String> = query.into_iter().filter(|(k, _)| k != "dimension").collect();
+    let rows = unpack(codec::floats(&bytes)?, dimension)?;
+    Ok(rows.into_iter().map(|values| Ok(InsertReq { values, meta: meta.clone() })).collect())
+}
+
+fn body_too_large(limits: &Limits) -> ApiError {
+    ApiError::TooLarge(format!("request body exceeds the limit of {} bytes", limits.max_body_bytes))
+}
+
+// Bound the result set: `k` and `max_results` may not exceed `max_k`, and a radius search
+// without its own `max_results` is capped at `max_k`.
+pub(crate) fn bounded_mode(limits: &Limits, k: Option<usize>, radius: Option<f64>, max_results: Option<usize>) -> Result<SearchMode, ApiError> {
+    check_radius(radius).map_err(ApiError::BadRequest)?;
+    if let Some(k) = k { limits.check_k("k", k)?; }
+    if let Some(m) = max_results { limits.check_k("max_results", m)?; }
+    Ok(search_mode(k, radius, Some(max_results.unwrap_or(limits.max_k))))
+}
+
+#[utoipa::path(post, path = "/db/{name}/insert_batch", tag = "vectors", params(("name" = String, Path, description = "Database name")),
+    request_body(description = "A JSON or MessagePack array of rows, NDJSON with one row per line, or raw f64 rows laid end to end", content(
+        (Vec<InsertReq> = "application/json"), (InsertReq = "application/x-ndjson"), (Vec<InsertReq> = "application/msgpack"), (Vec<f64> = "application/octet-stream"))),
+    responses((status = 200, description = "Rows applied; rejected rows are listed in errors", body = InsertBatchResp)))]
+async fn insert_batch(State(state): State<AppState>, ApiPath(name): ApiPath<String>, key: Option<Extension<Arc<ApiKey>>>, Accept(format): Accept, req: Request) -> Result<Reply<InsertBatchResp>, ApiError> {
+    state.reject_if_cache_full()?;
+    let rows = match Format::of_request(req.headers()) {
+        Format::RawF64 => { let query = codec::query_pairs(req.uri())?; raw_insert_rows(&state, &name, query, req.into_body()).await? }
+        body_format => { let (parts, body) = req.into_parts(); read_insert_rows(body_format, &parts.headers, body, &state.limits).await? }
+    };
+    let BatchOutcome { ids, errors, total } = state.insert_rows(&name, rows, may_create(key.as_deref())).await?;
+    let inserted = ids.iter().filter(|id| id.is_some()).count();
+    Ok(Reply(format, InsertBatchResp { ok: errors.is_empty(), inserted, ids, errors, total, durability: state.durability() }))
+}
+
+impl SearchOpts {
+    fn into_params(self) -> SearchParams {
+        SearchParams { k: self.k, metric: self.f, radius: self.radius, max_results: self.max_results, filter: self.filter, timeout: self.timeout_ms.map(Duration::from_millis), partial: self.on_timeout == Some(OnTimeout::Partial) }
+    }
+
+    // Raw requests carry the options in the query string, with filters as `filter.<key>=<value>`.
+    fn from_query(query: &[(String, String)]) -> Result<Self, ApiError> {
+        let filter: HashMap<String, String> = query.iter()
+            .filter_map(|(k, v)| k.strip_prefix("filter.").map(|k| (k.to_string(), v.clone())))
+            .collect();
+        let on_timeout = match codec::param::<String>(query, "on_timeout")?.as_deref() {
+            None => None,
+            Some("error") => Some(OnTimeout::Error),
+            Some("partial") => Some(OnTimeout::Partial),
+            Some(other) => return Err(ApiError::BadRequest(format!("invalid query parameter on_timeout={}", other))),
+        };
+        Ok(SearchOpts {
+            k: codec::param(query, "k")?,
+            f: codec::param(query, "f")?,
+            radius: codec::param(query, "radius")?,
+            max_results: codec::param(query, "max_results")?,
+            filter: (!filter.is_empty()).then_some(filter),
+            timeout_ms: codec::param(query, "timeout_ms")?,
+            on_timeout,
+        })
+    }
+}
+
+impl Decode for CreateReq {}
+impl Decode for ScrollReq {}
+impl Decode for TargetReq {}
+
+// A raw insert is the vector; every query parameter becomes a metadata entry.
+impl Decode for InsertReq {
+    fn from_raw(values: Vec<f64>, query: &[(String, String)]) -> Result<Self, ApiError> {
+        Ok(InsertReq { values, meta: query.iter().cloned().collect() })
+    }
+}
+
+impl Decode for FindReq {
+    fn from_raw(values: Vec<f64>, query: &[(String, String)]) -> Result<Self, ApiError> {
+        Ok(FindReq { values, opts: SearchOpts::from_query(query)? })
+    }
+}
+
+impl Decode for FindBatchReq {
+    fn from_raw(values: Vec<f64>, query: &[(String, String)]) -> Result<Self, ApiError> {
+        Ok(FindBatchReq { queries: vec![values], opts: SearchOpts::from_query(query)?, packed: true })
+    }
+}
+
+// Split vectors laid end to end by the DB dimension.
+fn unpack(values: Vec<f64>, dimension: usize) -> Result<Vec<Vec<f64>>, ApiError> {
+    if values.is_empty() { return Ok(Vec::new()); }
+    if dimension == 0 || !values.len().is_multiple_of(dimension) {
+        return Err(ApiError::InvalidBody(format!("raw body holds {} values, not a multiple of the dimension {}", values.len(), dimension)));
+    }
+    Ok(values.chunks_exact(dimension).map(<[f64]>::to_vec).collect())
+}
+
+#[utoipa::path(post, path = "/db/{name}/find", tag = "vectors", params(("name" = String, Path, description = "Database name")),
+    request_body(description = "A raw f64 body is the query; the other fields go in the query string", content((FindReq = "application/json"), (FindReq = "application/msgpack"), (Vec<f64> = "application/octet-stream"))),
+    responses((status = 200, description = "Matches, nearest first", body = Vec<FindItem>, headers(("x-search-incomplete" = String, description = "Set to true when a partial search timed out")))))]
+async fn find_vec(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept, Payload(req): Payload<FindReq>) -> Result<Response, ApiError> {
+    let (mut res, complete) = state.search(&name, vec![req.values], req.opts.into_params()).await?;
+    Ok(search_response(format, res.pop().unwrap_or_default(), complete))
+}
+
+#[utoipa::path(post, path = "/db/{name}/find_batch", tag = "vectors", params(("name" = String, Path, description = "Database name")),
+    request_body(description = "A raw f64 body is the queries laid end to end", content((FindBatchReq = "application/json"), (FindBatchReq = "application/msgpack"), (Vec<f64> = "application/octet-stream"))),
+    responses((status = 200, description = "One match list per query", body = Vec<Vec<FindItem>>, headers(("x-search-incomplete" = String, description = "Set to true when a partial search timed out")))))]
+async fn find_batch(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept, Payload(mut req): Payload<FindBatchReq>) -> Result<Response, ApiError> {
+    if req.packed {
+        let dimension = state.dimension(&name).await?;
+        req.queries = unpack(req.queries.concat(), dimension)?;
+    }
+    let (res, complete) = state.search(&name, req.queries, req.opts.into_params()).await?;
+    Ok(search_response(format, res, complete))
+}
+
+pub(crate) fn parse_metric(code: Option<&str>) -> Result<Metric, ApiError> {
+    let code = code.unwrap_or("eu");
+    Metric::from_code(code).ok_or_else(|| ApiError::UnknownMetric(code.to_string()))
+}
+
+fn to_find_items(db: &Database, scored: Vec<(usize, f64)>) -> Vec<FindItem> {
+    let mut res = Vec::with_capacity(scored.len());
+    for (idx, dist) in scored {
+        let mut meta_map = HashMap::new();
+        for m in db.vectors[idx].metadata() { meta_map.insert(m.key().to_string(), m.value().to_string()); }
+        let values = db.vectors[idx].data().to_vec();
+        res.push(FindItem { index: idx, distance: dist, values, metadata: meta_map });
+    }
+    res
+}
+
+fn to_vector_item(id: usize, v: &Vector<f64>, with_values: bool) -> VectorItem {
+    let metadata = v.metadata().iter().map(|m| (m.key().to_string(), m.value().to_string())).collect();
+    VectorItem { id, values: with_values.then(|| v.data().to_vec()), metadata }
+}
+
+#[utoipa::path(get, path = "/db/{name}/vectors/{id}", tag = "vectors",
+    params(("name" = String, Path, description = "Database name"), ("id" = usize, Path, description = "Vector id"), GetVectorQuery),
+    responses((status = 200, description = "The vector", content((VectorItem = "application/json"), (VectorItem = "application/msgpack"), (Vec<f64> = "application/octet-stream")))))]
+async fn get_vector(State(state): State<AppState>, ApiPath((name, id)): ApiPath<(String, usize)>, ApiQuery(q): ApiQuery<GetVectorQuery>, AcceptVector(format): AcceptVector) -> Result<Response, ApiError> {
+    let entry = state.entry(&name).await?;
+    let item = blocking(move || {
+        let db = entry.db.read()?;
+        let v = db.vectors.get(id).ok_or(ApiError::VectorNotFound(id))?;
+        Ok(to_vector_item(id, v, q.with_values.unwrap_or(true) || format == Format::RawF64))
+    }).await?;
+    // a raw response is just the values; metadata needs JSON or MessagePack
+    match (format, &item.values) {
+        (Format::RawF64, Some(values)) => Ok(codec::raw_response(values)),
+        _ => Ok(Reply(format, item).into_response()),
+    }
+}
+
+#[utoipa::path(post, path = "/db/{name}/scroll", tag = "vectors", params(("name" = String, Path, description = "Database name")),
+    request_body(content((ScrollReq = "application/json"), (ScrollReq = "application/msgpack"))),
+    responses((status = 200, description = "One page of vectors", body = ScrollResp)))]
+async fn scroll_vectors(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept, Payload(req): Payload<ScrollReq>) -> Result<Reply<ScrollResp>, ApiError> {
+    let start = parse_cursor(req.cursor.as_deref())?;
+    let limit = req.limit.unwrap_or(100);
+    // an empty page would hand back the same cursor, and a client following it would never finish
+    if limit == 0 { return Err(ApiError::BadRequest("limit must be at least 1".into())); }
+    state.limits.check_batch("items per page", limit)?;
+    let entry = state.entry(&name).await?;
+    let resp = blocking(move || {
+        let db = entry.db.read()?;
+        let with_values = req.with_values.unwrap_or(true);
+        let (page, next_cursor) = scroll_db(&db, start, limit, req.filter.as_ref());
+        let items = page.into_iter().map(|(id, v)| to_vector_item(id, v, with_values)).collect();
+        Ok(ScrollResp { items, next_cursor })
+    }).await?;
+    Ok(Reply(format, resp))
+}
+
+#[utoipa::path(get, path = "/dbs", tag = "databases",
+    responses((status = 200, description = "Databases the caller's key can read", body = Vec<DbSummary>,
+        headers(("x-vectra-event-seq" = u64, description = "Latest change event when the list was taken")))))]
+async fn list_dbs(State(state): State<AppState>, key: Option<Extension<Arc<ApiKey>>>, Accept(format): Accept) -> Result<Response, ApiError> {
+    // read before listing, so a follower resuming the change feed from here misses nothing
+    let seq = state.events.head();
+    let out = blocking(move || {
+        let cached: HashMap<String, Arc<CacheEntry>> = state.dbs.read()?.clone();
+        let mut names = ver::list_dbs(&state.dir).unwrap_or_default();
+        names.extend(cached.keys().filter(|k| !names.contains(k)).cloned().collect::<Vec<_>>());
+        // a key only sees the databases it can read
+        if let Some(Extension(key)) = &key { names.retain(|n| key.can_read(n)); }
+        names.sort();
+        let mut out = Vec::with_capacity(names.len());
+        for name in names {
+            // cached entries may hold unflushed inserts, so prefer them over disk
+            let summary = match cached.get(&name) {
+                Some(e) => { let db = e.db.read()?; DbSummary { name, dimension: db.dimension, count: db.vectors.len(), flush_error: e.flush_error() } }
+                None => match compute_db_info(&state.dir, &name) {
+                    Ok(info) => DbSummary { name, dimension: info.dimension, count: info.count, flush_error: None },
+                    Err(_) => continue,
+                },
+            };
+            out.push(summary);
+        }
+        Ok(out)
+    }).await?;
+    Ok(([(header::HeaderName::from_static("x-vectra-event-seq"), seq.to_string())], Reply(format, out)).into_response())
+}
+
+#[utoipa::path(get, path = "/db/{name}/snapshot", tag = "replication", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "The whole database in the on-disk format, as followers bootstrap from it", body = Vec<u8>, content_type = "application/octet-stream")))]
+async fn snapshot(State(state): State<AppState>, ApiPath(name): ApiPath<String>) -> Result<Response, ApiError> {
+    let entry = state.entry(&name).await?;
+    let bytes = blocking(move || Ok(entry.db.read()?.encode())).await?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes).into_response())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TailQuery {
+    /// Id of the first vector to return
+    from: usize,
+    /// Most vectors to return; defaults to --max-batch
+    limit: Option<usize>,
+}
+
+#[utoipa::path(get, path = "/db/{name}/tail", tag = "replication", params(("name" = String, Path, description = "Database name"), TailQuery),
+    responses((status = 200, description = "Vectors from `from` on, bincode-encoded as in the write-ahead log, with the database size in `x-vectra-total`", body = Vec<u8>, content_type = "application/octet-stream")))]
+async fn tail_vectors(State(state): State<AppState>, ApiPath(name): ApiPath<String>, ApiQuery(q): ApiQuery<TailQuery>) -> Result<Response, ApiError> {
+    let limit = q.limit.unwrap_or(state.limits.max_batch);
+    if limit == 0 { return Err(ApiError::BadRequest("limit must be at least 1".into())); }
+    state.limits.check_batch("vectors", limit)?;
+    let entry = state.entry(&name).await?;
+    let (bytes, total) = blocking(move || {
+        let db = entry.db.read()?;
+        let from = q.from.min(db.vectors.len());
+        let to = from.saturating_add(limit).min(db.vectors.len());
+        let bytes = bincode::serialize(&db.vectors[from..to]).map_err(|e| ApiError::Internal(e.to_string()))?;
+        Ok((bytes, db.vectors.len()))
+    }).await?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream".to_string()), (header::HeaderName::from_static("x-vectra-total"), total.to_string())], bytes).into_response())
+}
+
+#[utoipa::path(delete, path = "/db/{name}", tag = "databases", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "Database dropped", body = OkResp)))]
+async fn drop_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>) -> Result<Json<OkResp>, ApiError> {
+    state.drop_db(&name).await?;
+    Ok(Json(OkResp { ok: true }))
+}
+
+#[utoipa::path(post, path = "/db/{name}/rename", tag = "databases", params(("name" = String, Path, description = "Database name")),
+    request_body(content((TargetReq = "application/json"), (TargetReq = "application/msgpack"))),
+    responses((status = 200, description = "Database renamed", body = OkResp)))]
+async fn rename_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Payload(req): Payload<TargetReq>) -> Result<Json<OkResp>, ApiError> {
+    state.rename(&name, req.to).await?;
+    Ok(Json(OkResp { ok: true }))
+}
+
+#[utoipa::path(post, path = "/db/{name}/clone", tag = "databases", params(("name" = String, Path, description = "Database name")),
+    request_body(content((TargetReq = "application/json"), (TargetReq = "application/msgpack"))),
+    responses((status = 200, description = "Database cloned", body = OkResp)))]
+async fn clone_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Payload(req): Payload<TargetReq>) -> Result<Json<OkResp>, ApiError> {
+    state.clone_db(&name, req.to).await?;
+    Ok(Json(OkResp { ok: true }))
+}
+
+#[utoipa::path(get, path = "/db/{name}/info", tag = "databases", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "Database info", body = InfoResp)))]
+async fn info_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept) -> Result<Reply<InfoResp>, ApiError> {
+    Ok(Reply(format, state.info(&name).await?))
+}
+
+#[utoipa::path(get, path = "/metrics", tag = "admin",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain")))]
+async fn metrics(State(state): State<AppState>) -> Result<([(header::HeaderName, &'static str); 1], String), ApiError> {
+    let gauges = {
+        let map = state.dbs.read()?;
+        CacheGauges { dbs: map.len(), dirty: map.values().filter(|e| e.is_dirty()).count(), bytes: map.values().map(|e| e.bytes()).sum() }
+    };
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render(&gauges)))
+}
+
+#[utoipa::path(get, path = "/healthz", tag = "health", security(()),
+    responses((status = 200, description = "The process is up", body = OkResp)))]
+pub(crate) async fn healthz() -> Json<OkResp> {
+    Json(OkResp { ok: true })
+}
+
+#[utoipa::path(get, path = "/readyz", tag = "health", security(()),
+    responses((status = 200, description = "Ready", body = ReadyResp), (status = 503, description = "Not ready", body = ReadyResp)))]
+async fn readyz(State(state): State<AppState>) -> Result<(StatusCode, Json<ReadyResp>), ApiError> {
+    let problems = blocking(move || state.readiness()).await?;
+    let status = if problems.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok((status, Json(ReadyResp { ready: problems.is_empty(), problems })))
+}
+
+#[utoipa::path(get, path = "/status", tag = "admin",
+    responses((status = 200, description = "Server status", body = StatusResp)))]
+async fn status(State(state): State<AppState>) -> Result<Json<StatusResp>, ApiError> {
+    let (now, wall) = (Instant::now(), Utc::now());
+    let mut dbs: Vec<CacheStatus> = state.dbs.read()?.iter().map(|(k, e)| {
+        let idle = chrono::Duration::from_std(now.duration_since(e.last_access())).unwrap_or_default();
+        CacheStatus { name: k.clone(), bytes: e.bytes(), dirty: e.is_dirty(), last_access: wall - idle, last_flush: e.last_flush(), flush_error: e.flush_error() }
+    }).collect();
+    dbs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(StatusResp {
+        durability: state.write_mode.durability(),
+        cache_bytes: dbs.iter().map(|d| d.bytes).sum(),
+        cache_max_bytes: state.cache_max_bytes,
+        cache_full: state.cache_full.load(Ordering::SeqCst),
+        event_seq: state.events.head(),
+        replication: state.replica.as_ref().map(|r| r.status()),
+        dbs,
+    }))
+}
+
+// A DB that isn't cached has nothing to flush or evict, but an unknown name is still a 404.
+fn cached_or_exists(state: &AppState, name: &str) -> Result<Option<Arc<CacheEntry>>, ApiError> {
+    ver::validate_name(name)?;
+    let entry = state.dbs.read()?.get(name).cloned();
+    if entry.is_none() && ver::db_files(&state.dir, name).is_empty() { return Err(ApiError::DbNotFound(name.to_string())); }
+    Ok(entry)
+}
+
+#[utoipa::path(post, path = "/db/{name}/flush", tag = "admin", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "Flushed to disk", body = FlushResp)))]
+async fn flush_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>) -> Result<Json<FlushResp>, ApiError> {
+    let flushed = blocking(move || match cached_or_exists(&state, &name)? {
+        Some(e) => Ok(state.flush_entry(&e)?),
+        None => Ok(false),
+    }).await?;
+    Ok(Json(FlushResp { ok: true, flushed }))
+}
+
+#[utoipa::path(post, path = "/db/{name}/evict", tag = "admin", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "Evicted from the cache", body = EvictResp)))]
+async fn evict_db(State(state): State<AppState>, ApiPath(name): ApiPath<String>) -> Result<Json<EvictResp>, ApiError> {
+    let evicted = blocking(move || {
+        let Some(e) = cached_or_exists(&state, &name)? else { return Ok(false) };
+        state.flush_entry(&e)?;
+        let mut map = state.dbs.write()?;
+        // an insert may have landed since the flush; keep the entry rather than lose it
+        if e.is_dirty() || !map.get(&name).is_some_and(|cur| Arc::ptr_eq(cur, &e)) { return Ok(false); }
+        map.remove(&name);
+        Ok(true)
+    }).await?;
+    Ok(Json(EvictResp { ok: true, evicted }))
+}
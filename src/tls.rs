@@ -0,0 +1,79 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+/// PEM files the TLS listener is built from. With `client_ca` set, clients must present a
+/// certificate signed by one of its CAs (mutual TLS).
+#[derive(Clone)]
+pub struct TlsFiles { pub cert: String, pub key: String, pub client_ca: Option<String> }
+
+fn invalid(path: &str, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e))
+}
+
+fn read_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path).map_err(|e| invalid(path, e))?
+        .collect::<Result<Vec<_>, _>>().map_err(|e| invalid(path, e))?;
+    if certs.is_empty() { return Err(invalid(path, "no certificates found")); }
+    Ok(certs)
+}
+
+impl TlsFiles {
+    pub fn server_config(&self) -> io::Result<Arc<ServerConfig>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let certs = read_certs(&self.cert)?;
+        let key = PrivateKeyDer::from_pem_file(&self.key).map_err(|e| invalid(&self.key, e))?;
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?;
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for c in read_certs(ca)? { roots.add(c).map_err(|e| invalid(ca, e))?; }
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()
+                    .map_err(|e| invalid(ca, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key).map_err(|e| invalid(&self.cert, e))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+
+    // Latest modification time across the files, used to notice a rotated certificate.
+    fn modified(&self) -> Option<SystemTime> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()].into_iter().flatten()
+            .filter_map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .max()
+    }
+}
+
+/// Polls the PEM files and swaps in a new certificate when they change. New connections use
+/// the reloaded config; a broken file is logged and the previous config stays in use.
+pub fn spawn_reload(config: RustlsConfig, files: TlsFiles, interval: Duration) {
+    tokio::spawn(async move {
+        let mut seen = files.modified();
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = files.modified();
+            if current == seen { continue; }
+            // a half-rotated pair fails here and is retried once the other file changes too
+            seen = current;
+            let f = files.clone();
+            match tokio::task::spawn_blocking(move || f.server_config()).await {
+                Ok(Ok(c)) => {
+                    config.reload_from_config(c);
+                    tracing::info!(cert = %files.cert, "reloaded TLS certificate");
+                }
+                Ok(Err(e)) => tracing::error!(error = %e, "TLS reload failed, keeping the previous certificate"),
+                Err(e) => tracing::error!(error = %e, "TLS reload task failed"),
+            }
+        }
+    });
+}
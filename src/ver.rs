@@ -1,12 +1,13 @@
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::fmt::Debug;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
-use bincode;
 
 // 定义一个枚举，表示元数据的不同类型
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum MetadataValue {
     Integer(i32),
@@ -17,7 +18,7 @@ pub enum MetadataValue {
 }
 
 // 定义一个结构体，用于保存元数据的 key 和 value
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetadataEntry {
     key: String,
     value: MetadataValue,
@@ -33,7 +34,7 @@ impl MetadataEntry {
 }
 
 // 定义一个向量结构体
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Vector<T> {
     data: Vec<T>,                          // 向量数据
     metadata: Vec<MetadataEntry>,           // 元数据，包含多个 key-value 对
@@ -61,6 +62,11 @@ where T: Debug + Serialize + for<'de> Deserialize<'de> {
         &self.metadata
     }
 
+    // 元数据等值过滤：filter 中每个 key 都必须存在且字符串形式相等
+    pub fn matches(&self, filter: &HashMap<String, String>) -> bool {
+        filter.iter().all(|(k, v)| self.metadata.iter().any(|m| m.key == *k && m.value.to_string() == *v))
+    }
+
     // **持久化：将 Vector<T> 保存到文件**
     #[allow(dead_code)]
     pub fn save_to_file(&self, path: &str) -> io::Result<()> {
@@ -81,6 +87,42 @@ where T: Debug + Serialize + for<'de> Deserialize<'de> {
     }
 }
 
+// 内存占用统计，用于缓存容量控制：heap_bytes 为堆上分配（按容量计），mem_bytes 再加上自身大小。
+// 缓存中的数据结构（包括以后加入的索引）都应实现该 trait。
+pub trait MemoryUsage {
+    fn heap_bytes(&self) -> usize;
+
+    fn mem_bytes(&self) -> usize where Self: Sized {
+        std::mem::size_of::<Self>() + self.heap_bytes()
+    }
+}
+
+impl MemoryUsage for MetadataValue {
+    fn heap_bytes(&self) -> usize {
+        match self {
+            MetadataValue::String(s) => s.capacity(),
+            _ => 0,
+        }
+    }
+}
+
+impl MemoryUsage for MetadataEntry {
+    fn heap_bytes(&self) -> usize { self.key.capacity() + self.value.heap_bytes() }
+}
+
+impl<T> MemoryUsage for Vector<T> {
+    fn heap_bytes(&self) -> usize {
+        self.data.capacity() * std::mem::size_of::<T>()
+            + self.metadata.capacity() * std::mem::size_of::<MetadataEntry>()
+            + self.metadata.iter().map(|m| m.heap_bytes()).sum::<usize>()
+    }
+}
+
+// Database 的占用在 insert 时增量维护，不需要每次遍历全部向量
+impl MemoryUsage for Database {
+    fn heap_bytes(&self) -> usize { self.heap_bytes }
+}
+
 impl std::fmt::Display for MetadataValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -93,31 +135,66 @@ impl std::fmt::Display for MetadataValue {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Database {
     pub name: String,
     pub dimension: usize,
     pub vectors: Vec<Vector<f64>>,
+    // 堆内存占用（不持久化）；直接修改 vectors 后需调用 recount
+    #[serde(skip)]
+    heap_bytes: usize,
 }
 
 impl Database {
     pub fn new(name: String, dimension: usize) -> Self {
-        Database { name, dimension, vectors: Vec::new() }
+        let mut db = Database { name, dimension, vectors: Vec::new(), heap_bytes: 0 };
+        db.recount();
+        db
     }
 
     pub fn insert(&mut self, vector: Vector<f64>) -> io::Result<()> {
         if vector_len(&vector) != self.dimension { return Err(io::Error::new(io::ErrorKind::InvalidInput, "dimension mismatch")); }
+        let cap_before = self.vectors.capacity();
+        let added = vector.heap_bytes();
         self.vectors.push(vector);
+        self.heap_bytes += added + (self.vectors.capacity() - cap_before) * std::mem::size_of::<Vector<f64>>();
         Ok(())
     }
 
+    // 全量重新计算内存占用
+    pub fn recount(&mut self) {
+        self.heap_bytes = self.name.capacity()
+            + self.vectors.capacity() * std::mem::size_of::<Vector<f64>>()
+            + self.vectors.iter().map(|v| v.heap_bytes()).sum::<usize>();
+    }
+
     pub fn save_to_dir(&self, dir: &str) -> io::Result<()> {
+        Database::write_encoded(dir, &self.name, &self.encode())
+    }
+
+    // 序列化为 bincode 字节，便于先在锁内快照、再在锁外落盘
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    // encode 的逆操作（如从 leader 拉取的快照）
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut db: Database = bincode::deserialize(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        db.recount();
+        Ok(db)
+    }
+
+    // 写入 dir/name.bin：先写临时文件再 rename，避免崩溃时留下半个文件
+    // 临时文件名带进程号和序号，并发写同一个库时不会互相覆盖
+    pub fn write_encoded(dir: &str, name: &str, bytes: &[u8]) -> io::Result<()> {
+        static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
         fs::create_dir_all(dir)?;
-        let path = format!("{}/{}.bin", dir, self.name);
-        let encoded = bincode::serialize(self).unwrap();
-        let mut file = File::create(path)?;
-        file.write_all(&encoded)?;
-        Ok(())
+        let path = format!("{}/{}.bin", dir, name);
+        let tmp = format!("{}.{}.{}.tmp", path, std::process::id(), TMP_SEQ.fetch_add(1, Ordering::Relaxed));
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)
     }
 
     pub fn save_to_path(&self, path: &str) -> io::Result<()> {
@@ -137,8 +214,9 @@ impl Database {
             let decoded: Database = bincode::deserialize(&buffer).unwrap();
             base = Some(decoded);
         }
-        // scan shards: name_part_*.bin
+        // scan shards: name_part_*.bin，按分片序号合并，保证每次加载的 id 顺序与导入顺序一致
         let mut merged = if let Some(db) = base { db } else { Database::new(name.to_string(), 0) };
+        let mut parts: Vec<(u64, std::path::PathBuf)> = Vec::new();
         if let Ok(entries) = fs::read_dir(dir) {
             for entry in entries.flatten() {
                 if let Ok(ft) = entry.file_type() {
@@ -146,25 +224,165 @@ impl Database {
                 }
                 let fname = entry.file_name();
                 let fname = fname.to_string_lossy();
-                if fname.starts_with(&format!("{}_part_", name)) && fname.ends_with(".bin") {
-                    if let Ok(mut f) = File::open(entry.path()) {
-                        let mut buf = Vec::new();
-                        f.read_to_end(&mut buf)?;
-                        let shard: Database = bincode::deserialize(&buf).unwrap();
-                        if merged.dimension == 0 { merged.dimension = shard.dimension; }
-                        if merged.dimension != shard.dimension { return Err(io::Error::new(io::ErrorKind::InvalidData, "dimension mismatch in shards")); }
-                        merged.vectors.extend(shard.vectors);
-                    }
-                }
+                if let Some(n) = part_number(&fname, name) { parts.push((n, entry.path())); }
+            }
+        }
+        parts.sort();
+        for (_, path) in parts {
+            if let Ok(mut f) = File::open(path) {
+                let mut buf = Vec::new();
+                f.read_to_end(&mut buf)?;
+                let shard: Database = bincode::deserialize(&buf).unwrap();
+                if merged.dimension == 0 { merged.dimension = shard.dimension; }
+                if merged.dimension != shard.dimension { return Err(io::Error::new(io::ErrorKind::InvalidData, "dimension mismatch in shards")); }
+                merged.vectors.extend(shard.vectors);
             }
         }
         if merged.dimension == 0 { return Err(io::Error::new(io::ErrorKind::NotFound, "database not found")); }
+        merged.recount();
         Ok(merged)
     }
 }
 
+// 库名只允许字母、数字、'-'、'_'，避免路径穿越；
+// 也不能以 _part_<数字> 结尾，否则与其他库的分片文件无法区分
+pub fn validate_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid database name: {}", name)));
+    }
+    if split_part(name).is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid database name: {} (names ending in _part_<n> are reserved for shards)", name)));
+    }
+    Ok(())
+}
+
+// 把 base_part_<数字> 拆成 (base, 数字)
+fn split_part(stem: &str) -> Option<(&str, &str)> {
+    let pos = stem.rfind("_part_")?;
+    let digits = &stem[pos + 6..];
+    (!digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())).then(|| (&stem[..pos], digits))
+}
+
+// fname 是否为库 name 的分片文件 name_part_<数字>.bin
+pub fn is_part_file(fname: &str, name: &str) -> bool { part_number(fname, name).is_some() }
+
+// 库 name 的分片文件的序号；序号过大无法解析的不算分片
+fn part_number(fname: &str, name: &str) -> Option<u64> {
+    let (base, digits) = fname.strip_suffix(".bin").and_then(split_part)?;
+    if base != name { return None; }
+    digits.parse().ok()
+}
+
+// 库在目录中的全部文件：主文件 name.bin、分片 name_part_*.bin 以及预写日志 name.wal*
+pub fn db_files(dir: &str, name: &str) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for suffix in [".bin", ".wal", ".wal.flushing"] {
+        let path = std::path::Path::new(dir).join(format!("{}{}", name, suffix));
+        if path.is_file() { files.push(path); }
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let fname = entry.file_name();
+            let fname = fname.to_string_lossy();
+            if is_part_file(&fname, name) { files.push(entry.path()); }
+        }
+    }
+    files
+}
+
+// 列出目录中所有库名（分片归并到同一个库名下）
+pub fn list_dbs(dir: &str) -> io::Result<Vec<String>> {
+    let mut names = std::collections::BTreeSet::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) { continue; }
+        let fname = entry.file_name();
+        let fname = fname.to_string_lossy();
+        // 只写过日志、尚未落盘的库还没有 .bin
+        let Some(stem) = [".bin", ".wal", ".wal.flushing"].iter().find_map(|s| fname.strip_suffix(s)) else { continue };
+        let name = split_part(stem).map_or(stem, |(base, _)| base);
+        names.insert(name.to_string());
+    }
+    Ok(names.into_iter().collect())
+}
+
+// 删除库的全部文件
+pub fn drop_db(dir: &str, name: &str) -> io::Result<()> {
+    validate_name(name)?;
+    let files = db_files(dir, name);
+    if files.is_empty() { return Err(io::Error::new(io::ErrorKind::NotFound, "database not found")); }
+    for f in files { fs::remove_file(f)?; }
+    Ok(())
+}
+
+// 复制或重命名库文件；主文件内保存了库名，需要重写
+fn transfer_db(dir: &str, from: &str, to: &str, keep_source: bool) -> io::Result<()> {
+    validate_name(from)?;
+    validate_name(to)?;
+    let files = db_files(dir, from);
+    if files.is_empty() { return Err(io::Error::new(io::ErrorKind::NotFound, "database not found")); }
+    if !db_files(dir, to).is_empty() { return Err(io::Error::new(io::ErrorKind::AlreadyExists, "exists")); }
+    for f in files {
+        let fname = f.file_name().unwrap().to_string_lossy().to_string();
+        let target = std::path::Path::new(dir).join(format!("{}{}", to, &fname[from.len()..]));
+        if fname == format!("{}.bin", from) {
+            let mut buffer = Vec::new();
+            File::open(&f)?.read_to_end(&mut buffer)?;
+            let mut db: Database = bincode::deserialize(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            db.name = to.to_string();
+            db.save_to_path(&target.to_string_lossy())?;
+            if !keep_source { fs::remove_file(&f)?; }
+        } else if keep_source {
+            fs::copy(&f, &target)?;
+        } else {
+            fs::rename(&f, &target)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn rename_db(dir: &str, from: &str, to: &str) -> io::Result<()> { transfer_db(dir, from, to, false) }
+
+pub fn clone_db(dir: &str, from: &str, to: &str) -> io::Result<()> { transfer_db(dir, from, to, true) }
+
 fn vector_len(v: &Vector<f64>) -> usize { v.data.len() }
 
+// 查询模式：固定返回 k 个近邻，或返回半径 r 内的全部向量（可选上限）
+pub enum SearchMode {
+    TopK(usize),
+    Radius { radius: f64, max_results: Option<usize> },
+}
+
+// 扫描多少条检查一次取消/超时
+const CANCEL_CHECK_EVERY: usize = 1024;
+
+impl Database {
+    // 精确扫描，按距离升序返回 (下标, 距离)；目前没有近似索引，结果召回率始终为 100%
+    pub fn search(&self, query: &[f64], metric: &Metric, mode: &SearchMode, filter: Option<&HashMap<String, String>>) -> Vec<(usize, f64)> {
+        self.search_until(query, metric, mode, filter, &|| false).0
+    }
+
+    // 同 search，但每扫描 CANCEL_CHECK_EVERY 条检查一次 stop；stop 返回 true 时提前结束，
+    // 只对已扫描部分排序返回，第二个返回值为 false 表示结果不完整
+    pub fn search_until(&self, query: &[f64], metric: &Metric, mode: &SearchMode, filter: Option<&HashMap<String, String>>, stop: &dyn Fn() -> bool) -> (Vec<(usize, f64)>, bool) {
+        let mut scored: Vec<(usize, f64)> = Vec::new();
+        let mut complete = true;
+        for (i, v) in self.vectors.iter().enumerate() {
+            if i % CANCEL_CHECK_EVERY == 0 && i > 0 && stop() { complete = false; break; }
+            if filter.is_some_and(|f| !v.matches(f)) { continue; }
+            let d = distance(v.data(), query, metric);
+            if let SearchMode::Radius { radius, .. } = mode { if d > *radius { continue; } }
+            scored.push((i, d));
+        }
+        scored.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        let cap = match mode {
+            SearchMode::TopK(k) => *k,
+            SearchMode::Radius { max_results, .. } => max_results.unwrap_or(usize::MAX),
+        };
+        scored.truncate(cap);
+        (scored, complete)
+    }
+}
+
 pub enum Metric {
     Euclidean,
     L1,
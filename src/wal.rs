@@ -0,0 +1,121 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::ver::{Database, Vector};
+
+// 预写日志（WAL）：插入先以 (id, vector) 记录追加到 name.wal 并 fsync。
+// flush 时把 name.wal 轮转为 name.wal.flushing，快照落盘成功后删除。
+// 记录带有插入时分配的 id，回放时跳过快照中已包含的部分，重复回放是幂等的。
+
+#[derive(Serialize)]
+struct RecordRef<'a> { id: u64, vector: &'a Vector<f64> }
+
+#[derive(Deserialize)]
+struct Record { id: u64, vector: Vector<f64> }
+
+fn wal_path(dir: &str, name: &str) -> String { format!("{}/{}.wal", dir, name) }
+
+fn flushing_path(dir: &str, name: &str) -> String { format!("{}/{}.wal.flushing", dir, name) }
+
+pub fn exists(dir: &str, name: &str) -> bool {
+    Path::new(&wal_path(dir, name)).exists() || Path::new(&flushing_path(dir, name)).exists()
+}
+
+// 追加一批记录，一次 write + fsync
+pub fn append(dir: &str, name: &str, records: &[(usize, &Vector<f64>)]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut buf = Vec::new();
+    for (id, vector) in records {
+        let bytes = bincode::serialize(&RecordRef { id: *id as u64, vector }).map_err(io::Error::other)?;
+        buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&bytes);
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(wal_path(dir, name))?;
+    file.write_all(&buf)?;
+    file.sync_data()
+}
+
+// 回放 name.wal.flushing 与 name.wal 中尚未进入快照的记录，返回回放条数。
+// 末尾不完整的记录（写入中途崩溃）会被忽略。只读，可在服务运行时由命令行调用。
+pub fn replay(dir: &str, name: &str, db: &mut Database) -> io::Result<usize> { replay_files(dir, name, db, false) }
+
+// 同 replay，但会把日志末尾不完整的记录截掉，否则之后追加的记录会接在残缺记录后面，
+// 下次回放时读不到。只能由唯一的写者（服务进程加载库时）调用。
+pub fn recover(dir: &str, name: &str, db: &mut Database) -> io::Result<usize> { replay_files(dir, name, db, true) }
+
+fn replay_files(dir: &str, name: &str, db: &mut Database, truncate_torn: bool) -> io::Result<usize> {
+    let mut applied = 0;
+    for path in [flushing_path(dir, name), wal_path(dir, name)] {
+        let mut buf = Vec::new();
+        match File::open(&path) {
+            Ok(mut f) => { f.read_to_end(&mut buf)?; }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        let mut pos = 0;
+        while pos + 4 <= buf.len() {
+            let len = u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize;
+            if pos + 4 + len > buf.len() { break; }
+            let Ok(rec) = bincode::deserialize::<Record>(&buf[pos + 4..pos + 4 + len]) else { break };
+            pos += 4 + len;
+            if (rec.id as usize) < db.vectors.len() { continue; }
+            if db.dimension == 0 { db.dimension = rec.vector.data().len(); }
+            db.insert(rec.vector)?;
+            applied += 1;
+        }
+        if truncate_torn && pos < buf.len() {
+            let f = OpenOptions::new().write(true).open(&path)?;
+            f.set_len(pos as u64)?;
+            f.sync_data()?;
+        }
+    }
+    Ok(applied)
+}
+
+// 读取库文件并回放日志，返回库与回放条数；只写过日志、从未落盘的库由日志重建。
+// repair 见 recover。
+pub fn load(dir: &str, name: &str, repair: bool) -> io::Result<(Database, usize)> {
+    let mut db = match Database::load_from_dir(dir, name) {
+        Ok(db) => db,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && exists(dir, name) => Database::new(name.to_string(), 0),
+        Err(e) => return Err(e),
+    };
+    let replayed = if repair { recover(dir, name, &mut db)? } else { replay(dir, name, &mut db)? };
+    if db.dimension == 0 { return Err(io::Error::new(io::ErrorKind::NotFound, "database not found")); }
+    Ok((db, replayed))
+}
+
+// 调用方需保证期间没有并发 append（持有该库的读锁即可，append 需要写锁）
+pub fn rotate(dir: &str, name: &str) -> io::Result<()> {
+    let wal = wal_path(dir, name);
+    if !Path::new(&wal).exists() { return Ok(()); }
+    let flushing = flushing_path(dir, name);
+    if Path::new(&flushing).exists() {
+        // 上次快照失败留下的日志还在，把新日志接在后面
+        let bytes = fs::read(&wal)?;
+        let mut f = OpenOptions::new().append(true).open(&flushing)?;
+        f.write_all(&bytes)?;
+        f.sync_data()?;
+        fs::remove_file(wal)
+    } else {
+        fs::rename(wal, flushing)
+    }
+}
+
+// 快照已落盘，轮转出去的日志可以删除
+pub fn finish(dir: &str, name: &str) -> io::Result<()> {
+    match fs::remove_file(flushing_path(dir, name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// 快照已包含日志中的全部记录（命令行写入后），两个日志文件都可以删除
+pub fn discard(dir: &str, name: &str) -> io::Result<()> {
+    finish(dir, name)?;
+    match fs::remove_file(wal_path(dir, name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
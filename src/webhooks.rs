@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use ring::{hmac, rand::{SecureRandom, SystemRandom}};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use crate::codec::{Accept, Payload, Reply};
+use crate::error::{ApiError, ApiPath};
+use crate::events::{Event, EventKind, EventLog, Item, EVENT_TYPES};
+use crate::server::{blocking, AppState, OkResp};
+
+// Events are gathered for up to BATCH_WINDOW (or BATCH_MAX events) and posted together.
+const BATCH_MAX: usize = 100;
+const BATCH_WINDOW: Duration = Duration::from_secs(1);
+// A failed batch is retried after 1s, 2s, 4s, ... capped at MAX_BACKOFF, then dropped.
+const MAX_ATTEMPTS: u32 = 10;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// Events waiting per hook while it is slow or down; beyond this new events are dropped.
+const MAX_QUEUED: usize = 10_000;
+const DEFAULT_EVENTS: [&str; 3] = ["insert", "drop", "flush"];
+// How long shutdown waits for the last events to be delivered.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// A webhook as stored in `<dir>/webhooks.json`.
+#[derive(Clone, Serialize, Deserialize)]
+struct Registration { id: String, db: String, url: String, secret: String, events: Vec<String>, created_at: DateTime<Utc> }
+
+// Delivery counters, reported by `GET /db/{name}/webhooks`.
+#[derive(Default)]
+struct Stats { delivered: u64, failed: u64, dropped: u64, last_delivery: Option<DateTime<Utc>>, last_error: Option<String> }
+
+// `deleted` is set when the hook is removed through the API, so its task stops retrying.
+struct Hook { reg: Registration, queue: mpsc::Sender<Arc<Event>>, stats: Arc<Mutex<Stats>>, deleted: Arc<AtomicBool> }
+
+/// Per-database webhooks. Each one has its own queue and delivery task, so a slow endpoint
+/// only holds up its own events.
+pub struct Webhooks {
+    path: PathBuf,
+    hooks: Mutex<HashMap<String, Hook>>,
+    client: reqwest::Client,
+    // registration lists are numbered as they are taken, and the file only moves forward
+    version: AtomicU64,
+    written: Mutex<u64>,
+    // delivery tasks, and the dispatcher once it runs
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    dispatcher: Mutex<Option<JoinHandle<()>>>,
+    flushed: watch::Sender<bool>,
+}
+
+impl Webhooks {
+    /// Loads the registrations in `<dir>/webhooks.json` and starts their delivery tasks.
+    pub fn open(dir: &str) -> io::Result<Self> {
+        let path = PathBuf::from(dir).join("webhooks.json");
+        let regs: Vec<Registration> = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().map_err(io::Error::other)?;
+        let webhooks = Webhooks {
+            path, hooks: Mutex::new(HashMap::new()), client, version: AtomicU64::new(0), written: Mutex::new(0),
+            tasks: Mutex::new(Vec::new()), dispatcher: Mutex::new(None), flushed: watch::channel(false).0,
+        };
+        {
+            let mut hooks = webhooks.hooks.lock().map_err(|_| io::Error::other("lock poisoned"))?;
+            for reg in regs { hooks.insert(reg.id.clone(), webhooks.start(reg, Arc::default())); }
+        }
+        Ok(webhooks)
+    }
+
+    fn start(&self, reg: Registration, stats: Arc<Mutex<Stats>>) -> Hook {
+        let (queue, rx) = mpsc::channel(MAX_QUEUED);
+        let deleted = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(deliver(self.client.clone(), reg.clone(), rx, stats.clone(), deleted.clone()));
+        if let Ok(mut tasks) = self.tasks.lock() {
+            tasks.retain(|t| !t.is_finished());
+            tasks.push(task);
+        }
+        Hook { reg, queue, stats, deleted }
+    }
+
+    // The registrations as they are now, numbered while the caller holds the hooks lock.
+    fn snapshot(&self, hooks: &HashMap<String, Hook>) -> (u64, Vec<Registration>) {
+        let mut regs: Vec<Registration> = hooks.values().map(|h| h.reg.clone()).collect();
+        regs.sort_by_key(|r| r.created_at);
+        (self.version.fetch_add(1, Ordering::SeqCst) + 1, regs)
+    }
+
+    // Rewrites the registration file unless a newer snapshot is already there. It holds the
+    // secrets, so only the owner may read it; a temporary file and rename keep it whole if we crash.
+    fn save(&self, (version, regs): (u64, Vec<Registration>)) -> io::Result<()> {
+        let mut written = self.written.lock().map_err(|_| io::Error::other("lock poisoned"))?;
+        if *written >= version { return Ok(()); }
+        let tmp = self.path.with_extension("json.tmp");
+        // a leftover file would keep its old permissions
+        let _ = fs::remove_file(&tmp);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(&regs).map_err(io::Error::other)?)?;
+        file.sync_all()?;
+        fs::rename(tmp, &self.path)?;
+        *written = version;
+        Ok(())
+    }
+
+    /// Routes events from the change feed to the hooks that want them. The feed closes when
+    /// shutdown begins; what is published after that, up to the final flush, is routed by `finish`.
+    pub fn spawn(self: &Arc<Self>, log: &Arc<EventLog>) -> Result<(), ApiError> {
+        let mut feed = log.follow(None)?;
+        let mut flushed = self.flushed.subscribe();
+        let webhooks = self.clone();
+        let dispatcher = tokio::spawn(async move {
+            while let Some(item) = feed.next().await { webhooks.dispatch(item).await; }
+            let _ = flushed.wait_for(|done| *done).await;
+            while let Some(item) = feed.try_next() { webhooks.dispatch(item).await; }
+            // closing every queue lets each delivery task send what it has and stop
+            if let Ok(mut hooks) = webhooks.hooks.lock() { hooks.clear(); }
+        });
+        *self.dispatcher.lock()? = Some(dispatcher);
+        Ok(())
+    }
+
+    async fn dispatch(self: &Arc<Self>, item: Item) {
+        let event = match item {
+            Item::Event(e) => e,
+            Item::Reset(seq) => { tracing::warn!(seq, "webhook dispatch fell behind; some events were not delivered"); return; }
+        };
+        let snapshot = {
+            let Ok(mut hooks) = self.hooks.lock() else { return };
+            for hook in hooks.values().filter(|h| h.reg.db == event.db && h.reg.events.iter().any(|t| t == event.kind.name())) {
+                if hook.queue.try_send(event.clone()).is_err() {
+                    if let Ok(mut s) = hook.stats.lock() { s.dropped += 1; }
+                }
+            }
+            // only after the drop or rename event itself was queued for the old name
+            let changed = match &event.kind {
+                EventKind::Drop => self.forget_db(&mut hooks, &event.db),
+                EventKind::Rename { to } => self.move_db(&mut hooks, &event.db, to),
+                _ => false,
+            };
+            if !changed { return; }
+            self.snapshot(&hooks)
+        };
+        let webhooks = self.clone();
+        if let Err(e) = blocking(move || Ok(webhooks.save(snapshot)?)).await {
+            tracing::warn!(db = %event.db, error = %e, "could not save webhooks");
+        }
+    }
+
+    /// Called once the final flush on shutdown is done: routes the events published since the
+    /// feed closed, then waits a while for every hook to deliver what it has queued.
+    pub async fn finish(&self) {
+        self.flushed.send_replace(true);
+        let dispatcher = self.dispatcher.lock().ok().and_then(|mut d| d.take());
+        let done = async {
+            if let Some(dispatcher) = dispatcher { let _ = dispatcher.await; }
+            let tasks = self.tasks.lock().map(|mut t| std::mem::take(&mut *t)).unwrap_or_default();
+            futures_util::future::join_all(tasks).await;
+        };
+        if tokio::time::timeout(SHUTDOWN_GRACE, done).await.is_err() {
+            tracing::warn!("gave up waiting for webhook deliveries");
+        }
+    }
+
+    // A dropped database takes its hooks with it. Their tasks still deliver what is queued,
+    // the drop event included, and then stop.
+    fn forget_db(&self, hooks: &mut HashMap<String, Hook>, db: &str) -> bool {
+        let before = hooks.len();
+        hooks.retain(|_, h| h.reg.db != db);
+        hooks.len() != before
+    }
+
+    // Hooks follow a renamed database. Each gets a new task under the new name, so batches never
+    // mix the two; the old task finishes what it already has queued.
+    fn move_db(&self, hooks: &mut HashMap<String, Hook>, from: &str, to: &str) -> bool {
+        let ids: Vec<String> = hooks.values().filter(|h| h.reg.db == from).map(|h| h.reg.id.clone()).collect();
+        for id in &ids {
+            let Some(old) = hooks.remove(id) else { continue };
+            let reg = Registration { db: to.to_string(), ..old.reg };
+            hooks.insert(id.clone(), self.start(reg, old.stats));
+        }
+        !ids.is_empty()
+    }
+
+    fn register(&self, db: String, req: CreateWebhookReq) -> Result<WebhookInfo, ApiError> {
+        let url = reqwest::Url::parse(&req.url).map_err(|e| ApiError::BadRequest(format!("invalid url '{}': {}", req.url, e)))?;
+        if !matches!(url.scheme(), "http" | "https") { return Err(ApiError::BadRequest(format!("webhook urls must be http or https, not {}", url.scheme()))); }
+        let events = match req.events {
+            Some(events) if events.is_empty() => return Err(ApiError::BadRequest("events must not be empty".to_string())),
+            Some(events) => events,
+            None => DEFAULT_EVENTS.iter().map(|t| t.to_string()).collect(),
+        };
+        if let Some(t) = events.iter().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+            return Err(ApiError::BadRequest(format!("unknown event type '{}'; expected one of {}", t, EVENT_TYPES.join(", "))));
+        }
+        let secret = match req.secret {
+            Some(s) if s.is_empty() => return Err(ApiError::BadRequest("secret must not be empty".to_string())),
+            Some(s) => s,
+            None => random_hex(24)?,
+        };
+        let reg = Registration { id: format!("wh_{}", random_hex(8)?), db, url: url.to_string(), secret, events, created_at: Utc::now() };
+        let snapshot = {
+            let mut hooks = self.hooks.lock()?;
+            hooks.insert(reg.id.clone(), self.start(reg.clone(), Arc::default()));
+            self.snapshot(&hooks)
+        };
+        if let Err(e) = self.save(snapshot) {
+            self.hooks.lock()?.remove(&reg.id);
+            return Err(e.into());
+        }
+        let mut info = WebhookInfo::new(&reg, &Stats::default());
+        // the only time the secret is shown
+        info.secret = Some(reg.secret);
+        Ok(info)
+    }
+
+    fn list(&self, db: &str) -> Result<Vec<WebhookInfo>, ApiError> {
+        let hooks = self.hooks.lock()?;
+        let mut out: Vec<WebhookInfo> = hooks.values()
+            .filter(|h| h.reg.db == db)
+            .map(|h| WebhookInfo::new(&h.reg, &h.stats.lock().unwrap_or_else(|e| e.into_inner())))
+            .collect();
+        out.sort_by_key(|w| w.created_at);
+        Ok(out)
+    }
+
+    // Dropping the hook closes its queue, which stops its delivery task.
+    fn remove(&self, db: &str, id: &str) -> Result<(), ApiError> {
+        let (hook, snapshot) = {
+            let mut hooks = self.hooks.lock()?;
+            if hooks.get(id).is_none_or(|h| h.reg.db != db) { return Err(ApiError::WebhookNotFound(id.to_string())); }
+            let hook = hooks.remove(id);
+            (hook, self.snapshot(&hooks))
+        };
+        if let Err(e) = self.save(snapshot) {
+            if let Some(hook) = hook { self.hooks.lock()?.insert(id.to_string(), hook); }
+            return Err(e.into());
+        }
+        if let Some(hook) = hook { hook.deleted.store(true, Ordering::Relaxed); }
+        Ok(())
+    }
+}
+
+fn random_hex(bytes: usize) -> Result<String, ApiError> {
+    let mut buf = vec![0u8; bytes];
+    SystemRandom::new().fill(&mut buf).map_err(|_| ApiError::Internal("no randomness available".to_string()))?;
+    Ok(hex(&buf))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Serialize)]
+struct Batch<'a> { webhook: &'a str, db: &'a str, events: Vec<&'a Event> }
+
+// Collects a batch, posts it with retries, and repeats until the hook's queue closes and drains.
+async fn deliver(client: reqwest::Client, reg: Registration, mut rx: mpsc::Receiver<Arc<Event>>, stats: Arc<Mutex<Stats>>, deleted: Arc<AtomicBool>) {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, reg.secret.as_bytes());
+    while let Some(first) = rx.recv().await {
+        if deleted.load(Ordering::Relaxed) { break; }
+        let mut events = vec![first];
+        let window = Instant::now() + BATCH_WINDOW;
+        while events.len() < BATCH_MAX {
+            match tokio::time::timeout_at(window, rx.recv()).await {
+                Ok(Some(e)) => events.push(e),
+                _ => break,
+            }
+        }
+        let Ok(body) = serde_json::to_vec(&Batch { webhook: &reg.id, db: &reg.db, events: events.iter().map(|e| &**e).collect() }) else { continue };
+        let mut backoff = FIRST_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            match post(&client, &reg, &key, &body, attempt).await {
+                Ok(()) => {
+                    if let Ok(mut s) = stats.lock() { s.delivered += events.len() as u64; s.last_delivery = Some(Utc::now()); s.last_error = None; }
+                    break;
+                }
+                Err(err) => {
+                    tracing::warn!(webhook = %reg.id, db = %reg.db, attempt, error = %err, "webhook delivery failed");
+                    let last = attempt == MAX_ATTEMPTS;
+                    if let Ok(mut s) = stats.lock() {
+                        s.last_error = Some(err);
+                        if last { s.failed += events.len() as u64; }
+                    }
+                    // deleted while retrying: nobody wants these any more
+                    if last || deleted.load(Ordering::Relaxed) { break; }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+// Signs `<timestamp>.<body>` so a captured request can't be replayed later with a fresh timestamp.
+async fn post(client: &reqwest::Client, reg: &Registration, key: &hmac::Key, body: &[u8], attempt: u32) -> Result<(), String> {
+    let timestamp = Utc::now().timestamp().to_string();
+    let mut signed = Vec::with_capacity(timestamp.len() + 1 + body.len());
+    signed.extend_from_slice(timestamp.as_bytes());
+    signed.push(b'.');
+    signed.extend_from_slice(body);
+    let signature = hex(hmac::sign(key, &signed).as_ref());
+    let resp = client.post(&reg.url)
+        .header("content-type", "application/json")
+        .header("x-vectra-webhook", &reg.id)
+        .header("x-vectra-timestamp", &timestamp)
+        .header("x-vectra-signature", format!("sha256={}", signature))
+        .header("x-vectra-attempt", attempt.to_string())
+        .body(body.to_vec())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if resp.status().is_success() { Ok(()) } else { Err(format!("endpoint answered {}", resp.status())) }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateWebhookReq {
+    url: String,
+    /// Event types to deliver; defaults to insert, drop and flush
+    events: Option<Vec<String>>,
+    /// HMAC key; a random one is generated when absent
+    secret: Option<String>,
+}
+
+impl crate::codec::Decode for CreateWebhookReq {}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct WebhookInfo {
+    id: String,
+    db: String,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+    /// Only returned when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    /// Events delivered since the server started
+    delivered: u64,
+    /// Events given up on after every retry failed
+    failed: u64,
+    /// Events discarded because too many were already queued
+    dropped: u64,
+    last_delivery: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+}
+
+impl WebhookInfo {
+    fn new(reg: &Registration, s: &Stats) -> Self {
+        WebhookInfo {
+            id: reg.id.clone(), db: reg.db.clone(), url: reg.url.clone(), events: reg.events.clone(), created_at: reg.created_at, secret: None,
+            delivered: s.delivered, failed: s.failed, dropped: s.dropped, last_delivery: s.last_delivery, last_error: s.last_error.clone(),
+        }
+    }
+}
+
+#[utoipa::path(post, path = "/db/{name}/webhooks", tag = "webhooks", params(("name" = String, Path, description = "Database name")),
+    request_body(content((CreateWebhookReq = "application/json"), (CreateWebhookReq = "application/msgpack"))),
+    responses((status = 200, description = "Webhook registered; the response carries its secret", body = WebhookInfo)))]
+pub(crate) async fn create_webhook(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept, Payload(req): Payload<CreateWebhookReq>) -> Result<Reply<WebhookInfo>, ApiError> {
+    state.writable()?;
+    // registering for a database that doesn't exist is a 404, like everything else under /db
+    state.dimension(&name).await?;
+    let webhooks = state.webhooks()?.clone();
+    Ok(Reply(format, blocking(move || webhooks.register(name, req)).await?))
+}
+
+#[utoipa::path(get, path = "/db/{name}/webhooks", tag = "webhooks", params(("name" = String, Path, description = "Database name")),
+    responses((status = 200, description = "The database's webhooks and their delivery counters", body = Vec<WebhookInfo>)))]
+pub(crate) async fn list_webhooks(State(state): State<AppState>, ApiPath(name): ApiPath<String>, Accept(format): Accept) -> Result<Reply<Vec<WebhookInfo>>, ApiError> {
+    Ok(Reply(format, state.webhooks()?.list(&name)?))
+}
+
+#[utoipa::path(delete, path = "/db/{name}/webhooks/{id}", tag = "webhooks",
+    params(("name" = String, Path, description = "Database name"), ("id" = String, Path, description = "Webhook id")),
+    responses((status = 200, description = "Webhook removed; queued events are discarded", body = OkResp)))]
+pub(crate) async fn delete_webhook(State(state): State<AppState>, ApiPath((name, id)): ApiPath<(String, String)>) -> Result<Json<OkResp>, ApiError> {
+    let webhooks = state.webhooks()?.clone();
+    blocking(move || webhooks.remove(&name, &id)).await?;
+    Ok(Json(OkResp { ok: true }))
+}
@@ -0,0 +1,117 @@
+//! API keys: missing and unknown keys, read/write/admin scopes, per-database lists, and who may
+//! create a database by inserting into it.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const ADMIN: &str = "admin-secret-key";
+const READER: &str = "reader-key";
+const WRITER: &str = "writer-key";
+
+fn start(dir: &TempDir) -> Server {
+    let auth = dir.join("auth.json");
+    let keys = json!({"keys": [
+        {"name": "admin", "key": ADMIN, "admin": true},
+        {"name": "reader", "key": READER, "read": ["docs"]},
+        {"name": "writer", "key": WRITER, "write": ["docs", "fresh"]},
+    ]});
+    std::fs::write(&auth, keys.to_string()).unwrap();
+    let server = Server::start(&dir.join("data"), &["serve", "--auth-config", &auth]);
+    server.wait_listening();
+    server
+}
+
+async fn call(req: reqwest::RequestBuilder, key: Option<&str>) -> (u16, Value) {
+    let req = match key { Some(k) => req.bearer_auth(k), None => req };
+    let resp = req.send().await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+fn code(reply: &(u16, Value)) -> (u16, Option<&str>) { (reply.0, reply.1["error"]["code"].as_str()) }
+
+#[tokio::test]
+async fn missing_and_unknown_keys_are_unauthorized() {
+    let dir = TempDir::new("auth-keys");
+    let server = start(&dir);
+    let http = reqwest::Client::new();
+    let info = || http.get(server.url("/db/docs/info"));
+
+    assert_eq!(code(&call(info(), None).await), (401, Some("unauthorized")));
+    // wrong, a prefix of a real key, a real key with more after it, and an empty bearer token
+    for key in ["nope", &ADMIN[..5], &format!("{}x", ADMIN), ""] {
+        assert_eq!(code(&call(info(), Some(key)).await), (401, Some("unauthorized")), "key {:?}", key);
+    }
+    // X-Api-Key works as well as a bearer token
+    let resp = http.post(server.url("/create")).header("x-api-key", ADMIN).json(&json!({"name": "docs", "dimension": 2})).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // health and the API description stay open
+    for path in ["/healthz", "/readyz", "/openapi.json"] {
+        assert_eq!(http.get(server.url(path)).send().await.unwrap().status(), 200, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn scopes_and_database_lists_are_enforced() {
+    let dir = TempDir::new("auth-scopes");
+    let server = start(&dir);
+    let http = reqwest::Client::new();
+    for name in ["docs", "other"] {
+        let created = call(http.post(server.url("/create")).json(&json!({"name": name, "dimension": 2})), Some(ADMIN)).await;
+        assert_eq!(created.0, 200);
+    }
+    let insert = |db: &str| http.post(server.url(&format!("/db/{}/insert", db))).json(&json!({"values": [1.0, 2.0]}));
+    let info = |db: &str| http.get(server.url(&format!("/db/{}/info", db)));
+
+    // the reader reads docs and nothing else, and writes nowhere
+    assert_eq!(call(info("docs"), Some(READER)).await.0, 200);
+    assert_eq!(code(&call(info("other"), Some(READER)).await), (403, Some("forbidden")));
+    assert_eq!(code(&call(insert("docs"), Some(READER)).await), (403, Some("forbidden")));
+
+    // the writer writes docs, and write implies read
+    assert_eq!(call(insert("docs"), Some(WRITER)).await.0, 200);
+    assert_eq!(call(info("docs"), Some(WRITER)).await.1["count"], 1);
+    assert_eq!(code(&call(insert("other"), Some(WRITER)).await), (403, Some("forbidden")));
+
+    // only admin keys manage databases or see server-wide state
+    let admin_only = [
+        ("create", http.post(server.url("/create")).json(&json!({"name": "x", "dimension": 2}))),
+        ("drop", http.delete(server.url("/db/docs"))),
+        ("rename", http.post(server.url("/db/docs/rename")).json(&json!({"to": "y"}))),
+        ("flush", http.post(server.url("/db/docs/flush"))),
+        ("status", http.get(server.url("/status"))),
+        ("metrics", http.get(server.url("/metrics"))),
+    ];
+    for (what, req) in admin_only {
+        assert_eq!(code(&call(req, Some(WRITER)).await), (403, Some("forbidden")), "{}", what);
+    }
+
+    // /dbs takes any key but only lists what that key can read
+    let (_, listed) = call(http.get(server.url("/dbs")), Some(READER)).await;
+    let names: Vec<&str> = listed.as_array().unwrap().iter().map(|d| d["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["docs"]);
+    let (_, listed) = call(http.get(server.url("/dbs")), Some(ADMIN)).await;
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn only_admin_keys_create_databases_by_inserting() {
+    let dir = TempDir::new("auth-create");
+    let server = start(&dir);
+    let http = reqwest::Client::new();
+    let insert = |db: &str| http.post(server.url(&format!("/db/{}/insert", db))).json(&json!({"values": [1.0, 2.0]}));
+    let batch = || http.post(server.url("/db/fresh/insert_batch")).json(&json!([{"values": [1.0, 2.0]}]));
+
+    // the writer may write `fresh` once it exists, but not bring it into existence
+    assert_eq!(code(&call(insert("fresh"), Some(WRITER)).await), (403, Some("forbidden")));
+    assert_eq!(code(&call(batch(), Some(WRITER)).await), (403, Some("forbidden")));
+    assert_eq!(code(&call(http.get(server.url("/db/fresh/info")), Some(ADMIN)).await), (404, Some("db_not_found")));
+
+    assert_eq!(call(insert("fresh"), Some(ADMIN)).await.0, 200);
+    assert_eq!(call(insert("fresh"), Some(WRITER)).await.0, 200);
+    assert_eq!(call(batch(), Some(WRITER)).await.0, 200);
+    assert_eq!(call(http.get(server.url("/db/fresh/info")), Some(WRITER)).await.1["count"], 3);
+}
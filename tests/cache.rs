@@ -0,0 +1,127 @@
+//! The cache under `--cache-max-mb`: dirty databases are flushed before they are evicted,
+//! writes are turned away with 503 while the cache is full of data that can't be flushed, and
+//! the bytes it accounts for follow what the data actually holds.
+
+mod common;
+
+use std::fs;
+use std::time::Duration;
+use common::{eventually, Server, TempDir};
+use serde_json::{json, Value};
+
+const DIM: usize = 64;
+
+// 1000 rows of 64 floats: about half a megabyte once cached.
+fn rows(seed: usize) -> Value {
+    json!((0..1000).map(|i| json!({"values": vec![(seed * 1000 + i) as f64; DIM]})).collect::<Vec<_>>())
+}
+
+async fn insert(http: &reqwest::Client, server: &Server, db: &str) -> u16 {
+    http.post(server.url(&format!("/db/{}/insert_batch", db))).json(&rows(db.len())).send().await.unwrap().status().as_u16()
+}
+
+async fn get(http: &reqwest::Client, server: &Server, path: &str) -> Value {
+    http.get(server.url(path)).send().await.unwrap().json().await.unwrap()
+}
+
+async fn cached(http: &reqwest::Client, server: &Server) -> Vec<String> {
+    get(http, server, "/status").await["dbs"].as_array().unwrap().iter().map(|d| d["name"].as_str().unwrap().to_string()).collect()
+}
+
+// The value of one sample in the `/metrics` text, e.g. `vectra_cache_evictions_total{reason="size"}`.
+async fn metric(http: &reqwest::Client, server: &Server, sample: &str) -> f64 {
+    let text = http.get(server.url("/metrics")).send().await.unwrap().text().await.unwrap();
+    text.lines().find_map(|l| l.strip_prefix(sample)?.strip_prefix(' ')?.parse().ok()).unwrap_or_else(|| panic!("no {} in\n{}", sample, text))
+}
+
+#[tokio::test]
+async fn a_dirty_database_is_flushed_before_it_is_evicted() {
+    let dir = TempDir::new("cache-evict");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve", "--cache-max-mb", "1", "--flush-interval-sec", "3600"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    assert_eq!(insert(&http, &server, "a").await, 200);
+    assert!(!dir.path().join("data/a.bin").exists(), "nothing flushes `a` on its own");
+    // `b` pushes the cache over its budget; `a` is older, so it goes, but only once it is on disk
+    assert_eq!(insert(&http, &server, "b").await, 200);
+    assert_eq!(cached(&http, &server).await, ["b"]);
+    assert!(dir.path().join("data/a.bin").exists());
+    assert_eq!(metric(&http, &server, "vectra_cache_eviction_flushes_total").await, 1.0);
+    assert_eq!(metric(&http, &server, "vectra_cache_evictions_total{reason=\"size\"}").await, 1.0);
+
+    // nothing was lost: `a` comes back from disk whole, even after a crash
+    assert_eq!(get(&http, &server, "/db/a/info").await["count"], 1000);
+    drop(server);
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    assert_eq!(get(&http, &server, "/db/a/info").await["count"], 1000);
+}
+
+#[tokio::test]
+async fn writes_are_refused_while_unflushable_data_fills_the_cache() {
+    let dir = TempDir::new("cache-full");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve", "--cache-max-mb", "1", "--flush-interval-sec", "1"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    // with a plain file where the data dir should be, nothing can be flushed or evicted
+    let _ = fs::remove_dir_all(&data);
+    fs::write(&data, b"not a directory").unwrap();
+    assert_eq!(insert(&http, &server, "a").await, 200);
+    assert_eq!(insert(&http, &server, "b").await, 200);
+    let resp = http.post(server.url("/db/a/insert")).json(&json!({"values": vec![0.0; DIM]})).send().await.unwrap();
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.json::<Value>().await.unwrap()["error"]["code"], "cache_full");
+    assert!(get(&http, &server, "/status").await["cache_full"].as_bool().unwrap());
+    assert!(metric(&http, &server, "vectra_cache_eviction_flush_failures_total").await >= 1.0);
+    assert!(metric(&http, &server, "vectra_writes_rejected_total").await >= 1.0);
+    // reads still work
+    assert_eq!(get(&http, &server, "/db/a/info").await["count"], 1000);
+
+    // once the disk is back the flush loop catches up, and writes are taken again
+    fs::remove_file(&data).unwrap();
+    fs::create_dir_all(&data).unwrap();
+    assert!(eventually(Duration::from_secs(15), async || !get(&http, &server, "/status").await["cache_full"].as_bool().unwrap()).await);
+    let resp = http.post(server.url("/db/a/insert")).json(&json!({"values": vec![0.0; DIM]})).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(get(&http, &server, "/db/a/info").await["count"], 1001);
+}
+
+async fn bytes(http: &reqwest::Client, server: &Server, db: &str) -> f64 {
+    let status = get(http, server, "/status").await;
+    status["dbs"].as_array().unwrap().iter().find(|d| d["name"] == db).unwrap()["bytes"].as_f64().unwrap()
+}
+
+#[tokio::test]
+async fn cached_bytes_follow_vectors_and_metadata() {
+    let dir = TempDir::new("cache-bytes");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    // 64 000 floats are 512 000 bytes; bookkeeping and spare capacity add some, not a multiple
+    assert_eq!(insert(&http, &server, "a").await, 200);
+    let plain = bytes(&http, &server, "a").await;
+    assert!((512_000.0..1_024_000.0).contains(&plain), "{}", plain);
+
+    // string contents count, not a flat guess per value
+    let long: Vec<Value> = (0..100).map(|i| json!({"values": vec![i as f64; DIM], "meta": {"text": "x".repeat(10_000)}})).collect();
+    http.post(server.url("/db/a/insert_batch")).json(&long).send().await.unwrap();
+    let with_text = bytes(&http, &server, "a").await;
+    assert!(with_text - plain >= 100.0 * (DIM * 8 + 10_000) as f64, "{} -> {}", plain, with_text);
+
+    // the total is reported the same way by /status and /metrics
+    let status = get(&http, &server, "/status").await;
+    assert_eq!(status["cache_bytes"].as_f64().unwrap(), with_text);
+    assert_eq!(metric(&http, &server, "vectra_cache_bytes").await, with_text);
+
+    // counting as rows arrive agrees with counting a freshly loaded copy
+    http.post(server.url("/db/a/evict")).send().await.unwrap();
+    assert!(cached(&http, &server).await.is_empty());
+    http.post(server.url("/db/a/find")).json(&json!({"values": vec![0.0; DIM], "k": 1})).send().await.unwrap();
+    let reloaded = bytes(&http, &server, "a").await;
+    assert!((reloaded - with_text).abs() / with_text < 0.25, "{} after reload vs {}", reloaded, with_text);
+}
@@ -0,0 +1,177 @@
+//! A coordinator in front of three workers: insert routing, reads by cluster id, merged
+//! searches matching a single node, errors decided at the coordinator or passed through, and
+//! spreading a database imported into `_part_<n>` files over the workers.
+
+mod common;
+
+use std::collections::BTreeSet;
+use common::{run, Server, TempDir};
+use serde_json::{json, Value};
+
+const ROWS: usize = 60;
+
+struct Cluster { _workers: Vec<Server>, coordinator: Server }
+
+fn start_cluster(dir: &TempDir) -> Cluster {
+    let workers: Vec<Server> = (0..3).map(|i| Server::start(&dir.join(&format!("w{}", i)), &["serve"])).collect();
+    for w in &workers { w.wait_listening(); }
+    let coordinator = start_coordinator(dir, &workers);
+    Cluster { _workers: workers, coordinator }
+}
+
+fn start_coordinator(dir: &TempDir, workers: &[Server]) -> Server {
+    let urls: Vec<String> = workers.iter().map(|w| w.url("")).collect();
+    let coordinator = Server::start(&dir.join("coord"), &["coordinate", "--worker", &urls.join(","), "--max-k", "50"]);
+    coordinator.wait_listening();
+    coordinator
+}
+
+fn row(i: usize) -> Value {
+    let x = i as f64;
+    json!({"values": [x.sin(), x.cos(), (x * 0.37).sin()], "meta": {"n": i.to_string()}})
+}
+
+async fn call(http: &reqwest::Client, req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = http.execute(req.build().unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+async fn ok(http: &reqwest::Client, req: reqwest::RequestBuilder) -> Value {
+    let (status, body) = call(http, req).await;
+    assert_eq!(status, 200, "{}", body);
+    body
+}
+
+// Creates `docs` and inserts the same rows, so ids line up between a cluster and a single node.
+async fn load(http: &reqwest::Client, server: &Server) {
+    ok(http, http.post(server.url("/create")).json(&json!({"name": "docs", "dimension": 3}))).await;
+    let rows: Vec<Value> = (0..ROWS).map(row).collect();
+    let resp = ok(http, http.post(server.url("/db/docs/insert_batch")).json(&rows)).await;
+    assert_eq!(resp["ids"], json!((0..ROWS).collect::<Vec<_>>()));
+}
+
+fn ranked(results: &Value) -> Vec<(u64, String)> {
+    results.as_array().unwrap().iter().map(|r| (r["index"].as_u64().unwrap(), format!("{:.9}", r["distance"].as_f64().unwrap()))).collect()
+}
+
+#[tokio::test]
+async fn sharded_database_matches_a_single_node() {
+    let dir = TempDir::new("cluster");
+    let cluster = start_cluster(&dir);
+    let single = Server::start(&dir.join("single"), &["serve"]);
+    single.wait_listening();
+    let http = reqwest::Client::new();
+    let coord = &cluster.coordinator;
+    load(&http, coord).await;
+    load(&http, &single).await;
+
+    // every row went to exactly one shard, and every shard got some
+    let info = ok(&http, http.get(coord.url("/db/docs/info"))).await;
+    assert_eq!(info["count"], ROWS);
+    let mut seen = BTreeSet::new();
+    for shard in info["shards"].as_array().unwrap() {
+        let (worker, db) = (shard["worker"].as_str().unwrap(), shard["db"].as_str().unwrap());
+        let page = ok(&http, http.post(format!("{}/db/{}/scroll", worker, db)).json(&json!({"limit": 1000, "with_values": false}))).await;
+        let ids: Vec<u64> = page["items"].as_array().unwrap().iter().map(|i| i["metadata"]["_id"].as_str().unwrap().parse().unwrap()).collect();
+        assert!(!ids.is_empty(), "shard {} got no rows", db);
+        assert_eq!(ids.len(), shard["count"].as_u64().unwrap() as usize);
+        for id in ids { assert!(seen.insert(id), "id {} stored twice", id); }
+    }
+    assert_eq!(seen, (0..ROWS as u64).collect());
+
+    // reads by cluster id find the row wherever it lives
+    for id in [0, 17, 42, ROWS - 1] {
+        let got = ok(&http, http.get(coord.url(&format!("/db/docs/vectors/{}", id)))).await;
+        assert_eq!(got["id"], id);
+        assert_eq!(got["values"], row(id)["values"]);
+        assert_eq!(got["metadata"]["n"], id.to_string());
+        assert!(got["metadata"].get("_id").is_none());
+    }
+    let (status, body) = call(&http, http.get(coord.url(&format!("/db/docs/vectors/{}", ROWS + 5)))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (404, Some("vector_not_found")));
+
+    // merged searches rank like one node holding everything
+    for query in [json!({"values": [0.2, 0.9, -0.3], "k": 7}), json!({"values": [-1.0, 0.0, 0.5], "k": 5, "f": "cs"}), json!({"values": [0.0, 1.0, 0.0], "radius": 0.8})] {
+        let sharded = ok(&http, http.post(coord.url("/db/docs/find")).json(&query)).await;
+        let whole = ok(&http, http.post(single.url("/db/docs/find")).json(&query)).await;
+        assert!(!sharded.as_array().unwrap().is_empty());
+        assert_eq!(ranked(&sharded), ranked(&whole), "query {}", query);
+    }
+    let batch = json!({"queries": [[0.5, 0.5, 0.5], [1.0, -1.0, 0.0]], "k": 4});
+    let sharded = ok(&http, http.post(coord.url("/db/docs/find_batch")).json(&batch)).await;
+    let whole = ok(&http, http.post(single.url("/db/docs/find_batch")).json(&batch)).await;
+    for q in 0..2 { assert_eq!(ranked(&sharded[q]), ranked(&whole[q])); }
+}
+
+#[tokio::test]
+async fn bad_searches_are_rejected_like_serve_does() {
+    let dir = TempDir::new("cluster-errors");
+    let cluster = start_cluster(&dir);
+    let http = reqwest::Client::new();
+    let coord = &cluster.coordinator;
+    load(&http, coord).await;
+    let find = |body: Value| http.post(coord.url("/db/docs/find")).json(&body);
+
+    // decided at the coordinator
+    let (status, body) = call(&http, find(json!({"values": [0.0, 0.0, 0.0], "k": 51}))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (413, Some("too_large")));
+    let (status, body) = call(&http, find(json!({"values": [0.0, 0.0, 0.0], "radius": -1.0}))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (400, Some("bad_request")));
+
+    // decided by the workers and passed through rather than turned into a 502
+    let (status, body) = call(&http, find(json!({"values": [0.0, 0.0, 0.0], "on_timeout": "sometimes"}))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (422, Some("invalid_body")), "{}", body);
+}
+
+#[tokio::test]
+async fn concurrent_creates_of_one_name_make_one_database() {
+    let dir = TempDir::new("cluster-create");
+    let cluster = start_cluster(&dir);
+    let http = reqwest::Client::new();
+    let coord = &cluster.coordinator;
+    let creates = (0..8).map(|_| call(&http, http.post(coord.url("/create")).json(&json!({"name": "race", "dimension": 2}))));
+    let statuses: Vec<u16> = futures_util::future::join_all(creates).await.into_iter().map(|(s, _)| s).collect();
+    assert_eq!(statuses.iter().filter(|s| **s == 200).count(), 1, "{:?}", statuses);
+    assert!(statuses.iter().all(|s| *s == 200 || *s == 409), "{:?}", statuses);
+    let info = ok(&http, http.get(coord.url("/db/race/info"))).await;
+    assert_eq!(info["shards"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn an_imported_database_is_pushed_across_the_workers() {
+    let dir = TempDir::new("cluster-push");
+    let Cluster { _workers: workers, coordinator } = start_cluster(&dir);
+    let http = reqwest::Client::new();
+
+    // 30 rows in five `_part_<n>.bin` files of one local database
+    let (sqlite, local) = (dir.join("rows.sqlite"), dir.join("local"));
+    let conn = rusqlite::Connection::open(&sqlite).unwrap();
+    conn.execute("CREATE TABLE t (a REAL, b REAL, c REAL, tag TEXT)", []).unwrap();
+    for i in 0..30 {
+        let v = &row(i)["values"];
+        conn.execute("INSERT INTO t VALUES (?1, ?2, ?3, ?4)", rusqlite::params![v[0].as_f64(), v[1].as_f64(), v[2].as_f64(), format!("r{}", i)]).unwrap();
+    }
+    drop(conn);
+    run(&local, &["import-sqlite", "--sqlite", &sqlite, "--table", "t", "--name", "docs", "--vec-cols", "a,b,c", "--meta-cols", "tag=tag", "--batch-size", "6"]);
+    assert!(std::path::Path::new(&local).join("docs_part_4.bin").exists());
+
+    run(&local, &["push", "docs", "--to", &coordinator.url(""), "--batch-size", "7"]);
+    let info = ok(&http, http.get(coordinator.url("/db/docs/info"))).await;
+    assert_eq!(info["count"], 30);
+    assert!(info["shards"].as_array().unwrap().iter().all(|s| s["count"].as_u64().unwrap() > 0), "{}", info);
+
+    // ids follow the local order, and reads by id still work once a restarted coordinator has
+    // forgotten where everything went
+    drop(coordinator);
+    let coordinator = start_coordinator(&dir, &workers);
+    for id in [0, 13, 29] {
+        let got = ok(&http, http.get(coordinator.url(&format!("/db/docs/vectors/{}", id)))).await;
+        // JSON on the way over may cost the last bit
+        let (got_values, want) = (got["values"].as_array().unwrap(), row(id)["values"].clone());
+        assert!(got_values.iter().zip(want.as_array().unwrap()).all(|(g, w)| (g.as_f64().unwrap() - w.as_f64().unwrap()).abs() < 1e-12), "{} vs {}", got["values"], want);
+        assert_eq!(got["metadata"]["tag"], format!("r{}", id));
+    }
+    let (status, _) = call(&http, http.get(coordinator.url("/db/docs/vectors/30"))).await;
+    assert_eq!(status, 404);
+}
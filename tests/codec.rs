@@ -0,0 +1,87 @@
+//! Response negotiation: `Accept` ranked by `q` against what each endpoint can produce, with
+//! `406` decided before the request changes anything.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const RAW: &str = "application/octet-stream";
+const MSGPACK: &str = "application/msgpack";
+
+fn content_type(resp: &reqwest::Response) -> String {
+    resp.headers().get("content-type").map(|v| v.to_str().unwrap().to_string()).unwrap_or_default()
+}
+
+async fn count(http: &reqwest::Client, server: &Server) -> u64 {
+    let info: Value = http.get(server.url("/db/t/info")).send().await.unwrap().json().await.unwrap();
+    info["count"].as_u64().unwrap()
+}
+
+#[tokio::test]
+async fn accept_picks_the_best_format_the_endpoint_can_produce() {
+    let dir = TempDir::new("codec");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    http.post(server.url("/create")).json(&json!({"name": "t", "dimension": 2})).send().await.unwrap();
+    http.post(server.url("/db/t/insert")).json(&json!({"values": [1.0, 2.0]})).send().await.unwrap();
+    let find = |accept: &str| http.post(server.url("/db/t/find")).header("accept", accept).json(&json!({"values": [0.0, 0.0], "k": 1}));
+
+    // the highest q wins, whatever the header order
+    let resp = find("application/json;q=0.4, application/msgpack;q=0.9").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(content_type(&resp), MSGPACK);
+    let hits: Vec<Value> = rmp_serde::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(hits[0]["index"], 0);
+    let resp = find("application/msgpack;q=0.2, application/json").send().await.unwrap();
+    assert_eq!(content_type(&resp), "application/json");
+
+    // a preferred type the endpoint can't produce falls back to the next acceptable one
+    let resp = find(&format!("{}, application/json;q=0.5", RAW)).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(content_type(&resp), "application/json");
+    let resp = find(&format!("{}, */*;q=0.1", RAW)).send().await.unwrap();
+    assert_eq!(content_type(&resp), "application/json");
+    // q=0 keeps a wildcard from choosing that type
+    let resp = find("application/json;q=0, */*").send().await.unwrap();
+    assert_eq!(content_type(&resp), MSGPACK);
+
+    // nothing acceptable is a 406
+    for accept in [RAW, "text/html", "application/json;q=0, application/msgpack;q=0"] {
+        let resp = find(accept).send().await.unwrap();
+        assert_eq!(resp.status(), 406, "{}", accept);
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "not_acceptable");
+    }
+
+    // a single vector can come back as raw f64
+    let resp = http.get(server.url("/db/t/vectors/0")).header("accept", format!("{}, application/json;q=0.5", RAW)).send().await.unwrap();
+    assert_eq!(content_type(&resp), RAW);
+    let bytes = resp.bytes().await.unwrap();
+    assert_eq!(bytes.chunks(8).map(|c| f64::from_le_bytes(c.try_into().unwrap())).collect::<Vec<_>>(), [1.0, 2.0]);
+}
+
+#[tokio::test]
+async fn an_unacceptable_write_changes_nothing() {
+    let dir = TempDir::new("codec-406");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    let resp = http.post(server.url("/create")).header("accept", RAW).json(&json!({"name": "t", "dimension": 2})).send().await.unwrap();
+    assert_eq!(resp.status(), 406);
+    assert_eq!(http.get(server.url("/db/t/info")).send().await.unwrap().status(), 404, "the create must not have happened");
+
+    http.post(server.url("/create")).json(&json!({"name": "t", "dimension": 2})).send().await.unwrap();
+    let resp = http.post(server.url("/db/t/insert")).header("accept", RAW).json(&json!({"values": [1.0, 2.0]})).send().await.unwrap();
+    assert_eq!(resp.status(), 406);
+    let resp = http.post(server.url("/db/t/insert_batch")).header("accept", RAW).json(&json!([{"values": [1.0, 2.0]}])).send().await.unwrap();
+    assert_eq!(resp.status(), 406);
+    assert_eq!(count(&http, &server).await, 0);
+
+    // no Accept header at all is JSON
+    let resp = http.post(server.url("/db/t/insert")).json(&json!({"values": [1.0, 2.0]})).send().await.unwrap();
+    assert_eq!(content_type(&resp), "application/json");
+    assert_eq!(count(&http, &server).await, 1);
+}
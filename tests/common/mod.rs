@@ -0,0 +1,112 @@
+//! Helpers shared by the integration tests: scratch directories, free ports and `Vectra`
+//! processes that are killed when the test drops them.
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A directory under the system temp dir, removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(tag: &str) -> Self {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!("vectra-{}-{}-{}", tag, std::process::id(), SEQ.fetch_add(1, Ordering::Relaxed)));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path { &self.0 }
+
+    pub fn join(&self, name: &str) -> String { self.0.join(name).to_string_lossy().into_owned() }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
+
+/// A port nothing was listening on a moment ago.
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Runs a one-shot `Vectra --dir <dir> <args…>` command and returns its stdout, failing the
+/// test if it exits unsuccessfully.
+pub fn run(dir: &str, args: &[&str]) -> String {
+    let out = Command::new(env!("CARGO_BIN_EXE_Vectra")).arg("--dir").arg(dir).args(args).env("RUST_LOG", "warn").output().unwrap();
+    assert!(out.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// A running `Vectra` subcommand.
+pub struct Server { child: Child, pub addr: String }
+
+impl Server {
+    /// Starts `Vectra --dir <dir> <args…> --addr 127.0.0.1:<free port>`.
+    pub fn start(dir: &str, args: &[&str]) -> Self {
+        Server::start_at(dir, args, &format!("127.0.0.1:{}", free_port()))
+    }
+
+    /// Like `start`, on a given address, e.g. to restart a server where its clients expect it.
+    pub fn start_at(dir: &str, args: &[&str], addr: &str) -> Self {
+        Server::spawn(dir, args, addr, "warn", Stdio::null())
+    }
+
+    /// Like `start`, with `RUST_LOG=<filter>` and the diagnostic log written to the file `log`.
+    pub fn start_logging(dir: &str, args: &[&str], filter: &str, log: &str) -> Self {
+        let log = std::fs::File::create(log).unwrap();
+        Server::spawn(dir, args, &format!("127.0.0.1:{}", free_port()), filter, Stdio::from(log))
+    }
+
+    fn spawn(dir: &str, args: &[&str], addr: &str, filter: &str, stderr: Stdio) -> Self {
+        let addr = addr.to_string();
+        let child = Command::new(env!("CARGO_BIN_EXE_Vectra"))
+            .arg("--dir").arg(dir)
+            .args(args)
+            .arg("--addr").arg(&addr)
+            .env("RUST_LOG", filter)
+            .stdout(Stdio::null())
+            .stderr(stderr)
+            .spawn()
+            .unwrap();
+        Server { child, addr }
+    }
+
+    pub fn url(&self, path: &str) -> String { format!("http://{}{}", self.addr, path) }
+
+    /// Sends SIGTERM, as an orchestrator would, and waits for a graceful shutdown to finish.
+    pub fn terminate(mut self) -> std::process::ExitStatus {
+        let sent = Command::new("kill").arg("-TERM").arg(self.child.id().to_string()).status().unwrap();
+        assert!(sent.success());
+        self.child.wait().unwrap()
+    }
+
+    /// Waits until the listener accepts connections.
+    pub fn wait_listening(&self) {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while std::net::TcpStream::connect(&self.addr).is_err() {
+            assert!(Instant::now() < deadline, "server on {} did not start", self.addr);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Polls `check` until it returns true or `timeout` passes.
+pub async fn eventually(timeout: Duration, mut check: impl AsyncFnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check().await { return true; }
+        if Instant::now() >= deadline { return false; }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -0,0 +1,110 @@
+//! Drops and renames racing inserts, in every write mode: an insert either lands before the
+//! database goes away and moves with it, or fails; nothing is written to a file afterwards.
+
+mod common;
+
+use std::time::Duration;
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const MODES: [&str; 3] = ["write-back", "write-through", "wal"];
+const ADMIN: &str = "admin-key";
+// may insert into `t` but not create it, so a late insert can't bring a dropped `t` back
+const WRITER: &str = "writer-key";
+
+fn start(dir: &TempDir, mode: &str) -> Server {
+    let auth = dir.join("auth.json");
+    let keys = json!({"keys": [
+        {"name": "admin", "key": ADMIN, "admin": true},
+        {"name": "writer", "key": WRITER, "read": ["*"], "write": ["t"]},
+    ]});
+    std::fs::write(&auth, keys.to_string()).unwrap();
+    let server = Server::start(&dir.join("data"), &["serve", "--write-mode", mode, "--auth-config", &auth, "--flush-interval-sec", "1"]);
+    server.wait_listening();
+    server
+}
+
+async fn admin(req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = req.bearer_auth(ADMIN).send().await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+// Inserts into `t` from several tasks until `rounds` attempts each are done, returning how
+// many were acknowledged.
+fn insert_in_background(http: &reqwest::Client, server: &Server, rounds: usize) -> Vec<tokio::task::JoinHandle<usize>> {
+    (0..4).map(|w| {
+        let (http, url) = (http.clone(), server.url("/db/t/insert"));
+        tokio::spawn(async move {
+            let mut acked = 0;
+            for i in 0..rounds {
+                let resp = http.post(&url).bearer_auth(WRITER).json(&json!({"values": [w as f64, i as f64]})).send().await.unwrap();
+                match resp.status().as_u16() {
+                    200 => acked += 1,
+                    403 | 409 => {}
+                    s => panic!("insert answered {}: {}", s, resp.text().await.unwrap()),
+                }
+            }
+            acked
+        })
+    }).collect()
+}
+
+async fn acknowledged(tasks: Vec<tokio::task::JoinHandle<usize>>) -> usize {
+    futures_util::future::join_all(tasks).await.into_iter().map(|r| r.unwrap()).sum()
+}
+
+#[tokio::test]
+async fn a_dropped_database_stays_dropped_under_concurrent_inserts() {
+    for mode in MODES {
+        let dir = TempDir::new("drop-race");
+        let server = start(&dir, mode);
+        let http = reqwest::Client::new();
+        let (status, body) = admin(http.post(server.url("/create")).json(&json!({"name": "t", "dimension": 2}))).await;
+        assert_eq!(status, 200, "{}", body);
+
+        let tasks = insert_in_background(&http, &server, 150);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (status, body) = admin(http.delete(server.url("/db/t"))).await;
+        assert_eq!(status, 200, "{}: {}", mode, body);
+        acknowledged(tasks).await;
+
+        // let the periodic flush run at least once more
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path().join("data")).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|f| f.starts_with("t.") || f.starts_with("t_part_"))
+            .collect();
+        assert!(leftovers.is_empty(), "{}: files written after the drop: {:?}", mode, leftovers);
+        let (status, body) = admin(http.get(server.url("/db/t/info"))).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (404, Some("db_not_found")), "{}", mode);
+    }
+}
+
+#[tokio::test]
+async fn a_renamed_database_keeps_every_acknowledged_insert() {
+    for mode in MODES {
+        let dir = TempDir::new("rename-race");
+        let server = start(&dir, mode);
+        let http = reqwest::Client::new();
+        admin(http.post(server.url("/create")).json(&json!({"name": "t", "dimension": 2}))).await;
+
+        let tasks = insert_in_background(&http, &server, 100);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (status, body) = admin(http.post(server.url("/db/t/rename")).json(&json!({"to": "u"}))).await;
+        assert_eq!(status, 200, "{}: {}", mode, body);
+        let acked = acknowledged(tasks).await;
+        assert!(acked > 0, "{}: no insert landed before the rename", mode);
+
+        let (_, info) = admin(http.get(server.url("/db/u/info"))).await;
+        assert_eq!(info["count"], acked, "{}", mode);
+        // and the same after a restart, read back from the files alone
+        admin(http.post(server.url("/db/u/flush"))).await;
+        drop(server);
+        let server = start(&dir, mode);
+        let (_, info) = admin(http.get(server.url("/db/u/info"))).await;
+        assert_eq!(info["count"], acked, "{} after a restart", mode);
+        let (status, _) = admin(http.get(server.url("/db/t/info"))).await;
+        assert_eq!(status, 404, "{}", mode);
+    }
+}
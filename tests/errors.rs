@@ -0,0 +1,53 @@
+//! Every error is a JSON `{error:{code,message}}` body with the status its code maps to,
+//! including rejections from path, query and body extractors.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn errors_have_a_stable_code_and_status() {
+    let dir = TempDir::new("errors");
+    let server = Server::start(&dir.join("data"), &["serve", "--max-k", "10"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    let create = |name: &str| http.post(server.url("/create")).json(&json!({"name": name, "dimension": 2}));
+    assert_eq!(create("t").send().await.unwrap().status(), 200);
+    let get = |path: &str| http.get(server.url(path));
+    let post = |path: &str, body: Value| http.post(server.url(path)).json(&body);
+    let find = |body: Value| post("/db/t/find", body);
+
+    let cases = [
+        ("name outside --dir", get("/db/..%2Fx/info"), 400, "bad_request"),
+        ("malformed JSON", http.post(server.url("/db/t/find")).header("content-type", "application/json").body("{\"values\": [1,"), 400, "bad_request"),
+        ("unknown metric", find(json!({"values": [1.0, 0.0], "f": "xx"})), 400, "unknown_metric"),
+        ("bad cursor", post("/db/t/scroll", json!({"cursor": "not hex"})), 400, "invalid_cursor"),
+        ("path that isn't an id", get("/db/t/vectors/abc"), 400, "bad_request"),
+        ("query that isn't a bool", get("/db/t/vectors/0?with_values=maybe"), 400, "bad_request"),
+        ("unknown db", get("/db/nope/info"), 404, "db_not_found"),
+        ("unknown vector", get("/db/t/vectors/0"), 404, "vector_not_found"),
+        ("unknown webhook", http.delete(server.url("/db/t/webhooks/wh_nope")), 404, "webhook_not_found"),
+        ("raw floats where only vectors are raw", get("/db/t/info").header("accept", "application/octet-stream"), 406, "not_acceptable"),
+        ("existing db", create("t"), 409, "db_exists"),
+        ("k over the limit", find(json!({"values": [1.0, 0.0], "k": 11})), 413, "too_large"),
+        ("raw floats for a create", http.post(server.url("/create")).header("content-type", "application/octet-stream").body(vec![0u8; 16]), 415, "unsupported_media_type"),
+        ("wrong shape", post("/db/t/insert", json!({"values": "1,2"})), 422, "invalid_body"),
+        ("missing field", find(json!({"k": 1})), 422, "invalid_body"),
+        ("wrong dimension", post("/db/t/insert", json!({"values": [1.0, 2.0, 3.0]})), 422, "dimension_mismatch"),
+    ];
+    for (what, req, status, code) in cases {
+        let resp = req.send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), status, "{}", what);
+        assert_eq!(resp.headers()["content-type"], "application/json", "{}", what);
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["code"], code, "{}: {}", what, body);
+        assert!(!body["error"]["message"].as_str().unwrap().is_empty(), "{}", what);
+    }
+
+    // the message names what was wrong
+    let body: Value = get("/db/nope/info").send().await.unwrap().json().await.unwrap();
+    assert_eq!(body, json!({"error": {"code": "db_not_found", "message": "database not found: nope"}}));
+    let body: Value = post("/db/t/insert", json!({"values": [1.0, 2.0, 3.0]})).send().await.unwrap().json().await.unwrap();
+    assert_eq!(body["error"]["message"], "dimension mismatch: db=2, input=3");
+}
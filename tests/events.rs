@@ -0,0 +1,129 @@
+//! The `/events` change feed: sequence order, resuming from `Last-Event-ID` or `since`, the
+//! reset event for a client older than the buffer, and sequence numbers across a restart.
+
+mod common;
+
+use std::time::Duration;
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+// One SSE event: its id, its name and its JSON data.
+#[derive(Debug)]
+struct Sse { id: u64, event: String, data: Value }
+
+struct Stream { resp: reqwest::Response, buf: String }
+
+impl Stream {
+    async fn open(req: reqwest::RequestBuilder) -> Self {
+        let resp = req.send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        Stream { resp, buf: String::new() }
+    }
+
+    // The next event, skipping keep-alive comments.
+    async fn next(&mut self) -> Sse {
+        loop {
+            if let Some(end) = self.buf.find("\n\n") {
+                let block: String = self.buf.drain(..end + 2).collect();
+                let field = |name: &str| block.lines().find_map(|l| l.strip_prefix(name)).map(|v| v.trim_start().to_string());
+                let Some(id) = field("id:") else { continue };
+                return Sse { id: id.parse().unwrap(), event: field("event:").unwrap(), data: serde_json::from_str(&field("data:").unwrap()).unwrap() };
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(10), self.resp.chunk()).await.expect("no event within 10s").unwrap().expect("stream ended");
+            self.buf.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    async fn take(&mut self, n: usize) -> Vec<Sse> {
+        let mut out = Vec::new();
+        for _ in 0..n { out.push(self.next().await); }
+        out
+    }
+}
+
+async fn post(http: &reqwest::Client, server: &Server, path: &str, body: Value) {
+    let resp = http.post(server.url(path)).json(&body).send().await.unwrap();
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+}
+
+async fn head(http: &reqwest::Client, server: &Server) -> u64 {
+    let status: Value = http.get(server.url("/status")).send().await.unwrap().json().await.unwrap();
+    status["event_seq"].as_u64().unwrap()
+}
+
+fn ids(events: &[Sse]) -> Vec<u64> { events.iter().map(|e| e.id).collect() }
+
+#[tokio::test]
+async fn events_arrive_in_order_and_resume_after_last_event_id() {
+    let dir = TempDir::new("events-resume");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    let mut live = Stream::open(http.get(server.url("/events"))).await;
+    post(&http, &server, "/create", json!({"name": "t", "dimension": 2})).await;
+    for i in 0..3 { post(&http, &server, "/db/t/insert", json!({"values": [i as f64, 0.0]})).await; }
+    let seen = live.take(4).await;
+    let first = seen[0].id;
+    assert_eq!(ids(&seen), (first..first + 4).collect::<Vec<_>>());
+    assert_eq!(seen.iter().map(|e| e.event.as_str()).collect::<Vec<_>>(), ["create", "insert", "insert", "insert"]);
+    for e in &seen {
+        assert_eq!((e.data["seq"].as_u64(), e.data["type"].as_str(), e.data["db"].as_str()), (Some(e.id), Some(e.event.as_str()), Some("t")));
+    }
+    assert_eq!((seen[3].data["first_id"].as_u64(), seen[3].data["total"].as_u64()), (Some(2), Some(3)));
+    drop(live);
+
+    // missed while disconnected: replayed after the last id seen, then the stream goes live.
+    // Last-Event-ID, as EventSource sends it, wins over `since`
+    post(&http, &server, "/db/t/insert", json!({"values": [9.0, 0.0]})).await;
+    let mut resumed = Stream::open(http.get(server.url("/events?since=0")).header("last-event-id", seen[1].id.to_string())).await;
+    post(&http, &server, "/db/t/flush", json!({})).await;
+    let replayed = resumed.take(4).await;
+    assert_eq!(ids(&replayed), (first + 2..first + 6).collect::<Vec<_>>());
+    assert_eq!(replayed[3].event, "flush");
+
+    // filters apply to the replay too
+    let mut inserts = Stream::open(http.get(server.url(&format!("/events?since={}&types=insert", first)))).await;
+    assert_eq!(ids(&inserts.take(4).await), [first + 1, first + 2, first + 3, first + 4]);
+
+    // a restarted server never hands out a number again
+    drop((resumed, inserts, server));
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let mut live = Stream::open(http.get(server.url("/events"))).await;
+    post(&http, &server, "/db/t/insert", json!({"values": [1.0, 1.0]})).await;
+    assert!(live.next().await.id > first + 5);
+}
+
+#[tokio::test]
+async fn a_client_older_than_the_buffer_is_told_to_reset() {
+    let dir = TempDir::new("events-reset");
+    let server = Server::start(&dir.join("data"), &["serve", "--event-buffer", "3"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    post(&http, &server, "/create", json!({"name": "t", "dimension": 2})).await;
+    for i in 0..5 { post(&http, &server, "/db/t/insert", json!({"values": [i as f64, 0.0]})).await; }
+    let last = head(&http, &server).await;
+
+    // only the last three are kept: someone who saw up to last-5 missed last-4 and last-3
+    let mut behind = Stream::open(http.get(server.url(&format!("/events?since={}", last - 5)))).await;
+    let got = behind.take(4).await;
+    assert_eq!((got[0].event.as_str(), got[0].id, &got[0].data), ("reset", last - 3, &json!({"seq": last - 3})));
+    assert_eq!(ids(&got[1..]), [last - 2, last - 1, last]);
+
+    // one that saw last-3 missed nothing that is gone
+    let mut current = Stream::open(http.get(server.url(&format!("/events?since={}", last - 3)))).await;
+    let got = current.take(3).await;
+    assert!(got.iter().all(|e| e.event == "insert"));
+    assert_eq!(ids(&got), [last - 2, last - 1, last]);
+
+    // and a sequence this server never issued means the client's state is from somewhere else
+    let mut ahead = Stream::open(http.get(server.url(&format!("/events?since={}", last + 100)))).await;
+    let got = ahead.next().await;
+    assert_eq!((got.event.as_str(), got.id), ("reset", last - 3));
+
+    let resp = http.get(server.url("/events")).header("last-event-id", "abc").send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+}
@@ -0,0 +1,117 @@
+//! The gRPC API through a generated tonic client: every RPC once, and errors arriving as the
+//! status code the REST status maps to, with the REST code in `vectra-error-code`.
+
+mod common;
+
+use std::time::{Duration, Instant};
+use common::{free_port, Server, TempDir};
+use serde_json::json;
+use tonic::{transport::Channel, Code, Request, Status};
+
+pub mod pb {
+    tonic::include_proto!("vectra.v1");
+}
+
+use pb::vectra_client::VectraClient;
+
+const ADMIN: &str = "admin-key";
+const READER: &str = "reader-key";
+
+struct Grpc { _server: Server, client: VectraClient<Channel> }
+
+async fn start(dir: &TempDir) -> Grpc {
+    let auth = dir.join("auth.json");
+    let keys = json!({"keys": [
+        {"name": "admin", "key": ADMIN, "admin": true},
+        {"name": "reader", "key": READER, "read": ["*"]},
+    ]});
+    std::fs::write(&auth, keys.to_string()).unwrap();
+    let grpc_addr = format!("127.0.0.1:{}", free_port());
+    let server = Server::start(&dir.join("data"), &["serve", "--auth-config", &auth, "--grpc-addr", &grpc_addr, "--max-k", "5"]);
+    server.wait_listening();
+    let endpoint = tonic::transport::Endpoint::from_shared(format!("http://{}", grpc_addr)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let channel = loop {
+        match endpoint.connect().await {
+            Ok(channel) => break channel,
+            Err(e) => assert!(Instant::now() < deadline, "gRPC listener did not start: {}", e),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    Grpc { _server: server, client: VectraClient::new(channel) }
+}
+
+fn with_key<T>(msg: T, key: &str) -> Request<T> {
+    let mut req = Request::new(msg);
+    req.metadata_mut().insert("authorization", format!("Bearer {}", key).parse().unwrap());
+    req
+}
+
+fn row(values: Vec<f64>, n: usize) -> pb::Row {
+    pb::Row { values, meta: [("n".to_string(), n.to_string())].into() }
+}
+
+fn assert_status<T: std::fmt::Debug>(res: Result<T, Status>, code: Code, rest_code: &str) {
+    let status = res.expect_err("the call should fail");
+    assert_eq!(status.code(), code, "{}", status.message());
+    assert_eq!(status.metadata().get("vectra-error-code").unwrap().to_str().unwrap(), rest_code);
+}
+
+#[tokio::test]
+async fn every_rpc_round_trips() {
+    let dir = TempDir::new("grpc");
+    let Grpc { _server, mut client } = start(&dir).await;
+
+    client.create_db(with_key(pb::CreateDbRequest { name: "t".into(), dimension: 2 }, ADMIN)).await.unwrap();
+    let one = client.insert(with_key(pb::InsertRequest { db: "t".into(), values: vec![0.0, 0.0], meta: Default::default() }, ADMIN)).await.unwrap().into_inner();
+    assert_eq!((one.id, one.total, one.durability.as_str()), (0, 1, "memory"));
+
+    // a bad row is reported by position and the rest go in
+    let batch = pb::InsertBatchRequest { db: "t".into(), rows: vec![row(vec![1.0, 0.0], 1), row(vec![1.0], 2), row(vec![2.0, 0.0], 3)] };
+    let batch = client.insert_batch(with_key(batch, ADMIN)).await.unwrap().into_inner();
+    assert_eq!((batch.inserted, batch.ids, batch.total), (2, vec![1, -1, 2], 3));
+    assert_eq!((batch.errors[0].row, batch.errors[0].code.as_str()), (1, "dimension_mismatch"));
+
+    // only the first streamed message has to name the database
+    let rows: Vec<pb::InsertRequest> = (3..6).map(|i| pb::InsertRequest { db: if i == 3 { "t".into() } else { String::new() }, values: vec![i as f64, 0.0], meta: Default::default() }).collect();
+    let streamed = client.insert_stream(with_key(futures_util::stream::iter(rows), ADMIN)).await.unwrap().into_inner();
+    assert_eq!((streamed.ids, streamed.total), (vec![3, 4, 5], 6));
+
+    let info = client.info(with_key(pb::DbRef { name: "t".into() }, READER)).await.unwrap().into_inner();
+    assert_eq!((info.name.as_str(), info.dimension, info.count), ("t", 2, 6));
+    assert_eq!(info.metadata_schema["n"].types, ["String"]);
+
+    let options = pb::SearchOptions { k: Some(2), ..Default::default() };
+    let found = client.find(with_key(pb::FindRequest { db: "t".into(), values: vec![2.9, 0.0], options: Some(options.clone()) }, READER)).await.unwrap().into_inner();
+    assert_eq!(found.matches.iter().map(|m| m.index).collect::<Vec<_>>(), [3, 2]);
+    assert!(!found.incomplete);
+    let found = client.find_batch(with_key(pb::FindBatchRequest { db: "t".into(), queries: vec![0.1, 0.0, 5.0, 0.0], options: Some(options) }, READER)).await.unwrap().into_inner();
+    let firsts: Vec<u64> = found.results.iter().map(|r| r.matches[0].index).collect();
+    assert_eq!(firsts, [0, 5]);
+
+    client.drop_db(with_key(pb::DbRef { name: "t".into() }, ADMIN)).await.unwrap();
+    assert_status(client.info(with_key(pb::DbRef { name: "t".into() }, ADMIN)).await, Code::NotFound, "db_not_found");
+}
+
+#[tokio::test]
+async fn errors_map_to_grpc_status_codes() {
+    let dir = TempDir::new("grpc-errors");
+    let Grpc { _server, mut client } = start(&dir).await;
+    let create = || pb::CreateDbRequest { name: "t".into(), dimension: 2 };
+    client.create_db(with_key(create(), ADMIN)).await.unwrap();
+    let find = |values: Vec<f64>, options: pb::SearchOptions| pb::FindRequest { db: "t".into(), values, options: Some(options) };
+
+    assert_status(client.info(Request::new(pb::DbRef { name: "t".into() })).await, Code::Unauthenticated, "unauthorized");
+    assert_status(client.info(with_key(pb::DbRef { name: "t".into() }, "wrong")).await, Code::Unauthenticated, "unauthorized");
+    assert_status(client.create_db(with_key(create(), READER)).await, Code::PermissionDenied, "forbidden");
+    assert_status(client.create_db(with_key(create(), ADMIN)).await, Code::AlreadyExists, "db_exists");
+    assert_status(client.info(with_key(pb::DbRef { name: "nope".into() }, ADMIN)).await, Code::NotFound, "db_not_found");
+    assert_status(client.info(with_key(pb::DbRef { name: "../x".into() }, ADMIN)).await, Code::InvalidArgument, "bad_request");
+    assert_status(client.find(with_key(find(vec![1.0], Default::default()), ADMIN)).await, Code::InvalidArgument, "dimension_mismatch");
+    let unknown = pb::SearchOptions { metric: "xx".into(), ..Default::default() };
+    assert_status(client.find(with_key(find(vec![1.0, 0.0], unknown), ADMIN)).await, Code::InvalidArgument, "unknown_metric");
+    let too_many = pb::SearchOptions { k: Some(6), ..Default::default() };
+    assert_status(client.find(with_key(find(vec![1.0, 0.0], too_many), ADMIN)).await, Code::ResourceExhausted, "too_large");
+    let queries = pb::FindBatchRequest { db: "t".into(), queries: vec![1.0, 0.0, 1.0], options: None };
+    assert_status(client.find_batch(with_key(queries, ADMIN)).await, Code::InvalidArgument, "bad_request");
+}
@@ -0,0 +1,72 @@
+//! Liveness and readiness probes: `/readyz` under concurrent probes, and the problems it reports
+//! while `--dir` can't be written and until a failed flush succeeds again.
+
+mod common;
+
+use std::fs;
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+async fn ready(http: &reqwest::Client, server: &Server) -> (u16, Value) {
+    let resp = http.get(server.url("/readyz")).send().await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn concurrent_probes_all_see_a_writable_dir() {
+    let dir = TempDir::new("readyz-race");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    assert_eq!(http.get(server.url("/healthz")).send().await.unwrap().json::<Value>().await.unwrap(), json!({"ok": true}));
+
+    for _ in 0..5 {
+        let probes = (0..32).map(|_| ready(&http, &server));
+        for (status, body) in futures_util::future::join_all(probes).await {
+            assert_eq!((status, &body), (200, &json!({"ready": true, "problems": []})));
+        }
+    }
+    // and the probes clean up after themselves
+    let left: Vec<_> = fs::read_dir(dir.path().join("data")).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert!(left.is_empty(), "{:?}", left);
+}
+
+#[tokio::test]
+async fn an_unwritable_dir_and_a_failed_flush_make_the_server_unready() {
+    let dir = TempDir::new("readyz-fail");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve", "--flush-interval-sec", "3600"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    let resp = http.post(server.url("/db/t/insert")).json(&json!({"values": [1.0, 2.0]})).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(ready(&http, &server).await.0, 200);
+
+    // a plain file where the data dir should be: neither the probe nor a flush can write there
+    let moved = dir.join("moved");
+    fs::rename(&data, &moved).unwrap();
+    fs::write(&data, b"not a directory").unwrap();
+    let flushed = http.post(server.url("/db/t/flush")).send().await.unwrap();
+    assert!(!flushed.status().is_success(), "{}", flushed.status());
+
+    let (status, body) = ready(&http, &server).await;
+    assert_eq!((status, body["ready"].as_bool()), (503, Some(false)));
+    let problems: Vec<&str> = body["problems"].as_array().unwrap().iter().map(|p| p.as_str().unwrap()).collect();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].contains("is not writable"), "{:?}", problems);
+    assert!(problems[1].starts_with("last flush of 't' failed"), "{:?}", problems);
+    let status: Value = http.get(server.url("/status")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(status["dbs"][0]["dirty"], true);
+    assert!(status["dbs"][0]["flush_error"].is_string(), "{}", status);
+
+    // the dir alone coming back isn't enough; the DB has to flush successfully again
+    fs::remove_file(&data).unwrap();
+    fs::rename(&moved, &data).unwrap();
+    let (status, body) = ready(&http, &server).await;
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(http.post(server.url("/db/t/flush")).send().await.unwrap().status(), 200);
+    assert_eq!(ready(&http, &server).await.0, 200);
+    let status: Value = http.get(server.url("/status")).send().await.unwrap().json().await.unwrap();
+    assert_eq!((status["dbs"][0]["dirty"].as_bool(), status["dbs"][0]["last_flush"].is_string()), (Some(false), true));
+}
@@ -0,0 +1,102 @@
+//! Bulk inserts: NDJSON bodies arriving in arbitrary chunks, JSON arrays, and bad rows reported
+//! by position while the good ones go in.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// Posts `chunks` as one chunked body, each piece its own HTTP chunk, so the server sees lines
+// split wherever the pieces split them.
+async fn post_chunked(server: &Server, path: &str, content_type: &str, chunks: &[&[u8]]) -> (u16, Value) {
+    let mut conn = tokio::net::TcpStream::connect(&server.addr).await.unwrap();
+    let head = format!("POST {} HTTP/1.1\r\nhost: {}\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n", path, server.addr, content_type);
+    conn.write_all(head.as_bytes()).await.unwrap();
+    for chunk in chunks {
+        conn.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await.unwrap();
+        conn.write_all(chunk).await.unwrap();
+        conn.write_all(b"\r\n").await.unwrap();
+        conn.flush().await.unwrap();
+    }
+    conn.write_all(b"0\r\n\r\n").await.unwrap();
+    let mut resp = Vec::new();
+    conn.read_to_end(&mut resp).await.unwrap();
+    let resp = String::from_utf8(resp).unwrap();
+    let status = resp[9..12].parse().unwrap();
+    let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+    // the response may itself be chunked: the JSON is whatever lies between the braces
+    let json = &body[body.find('{').unwrap()..=body.rfind('}').unwrap()];
+    (status, serde_json::from_str(json).unwrap())
+}
+
+fn codes(resp: &Value) -> Vec<(u64, String)> {
+    resp["errors"].as_array().unwrap().iter().map(|e| (e["row"].as_u64().unwrap(), e["code"].as_str().unwrap().to_string())).collect()
+}
+
+#[tokio::test]
+async fn ndjson_is_read_line_by_line_across_chunks() {
+    let dir = TempDir::new("insert-ndjson");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    http.post(server.url("/create")).json(&json!({"name": "t", "dimension": 2})).send().await.unwrap();
+
+    let body = concat!(
+        "{\"values\":[0,1],\"meta\":{\"name\":\"zéro\"}}\n",
+        "\n",
+        "{\"values\":[1,1]}\r\n",
+        "{\"values\":[2,1],\n",
+        "{\"values\":[3]}\n",
+        "   \n",
+        "{\"values\":[4,1],\"meta\":{\"name\":\"four\"}}",
+    ).as_bytes();
+    // split inside a line, inside the two-byte é, and right after a newline
+    let cut = [5, 34, 41, 50, 60, 61, 100];
+    let mut pieces: Vec<&[u8]> = Vec::new();
+    let mut from = 0;
+    for to in cut.into_iter().chain([body.len()]) { pieces.push(&body[from..to]); from = to; }
+
+    let (status, resp) = post_chunked(&server, "/db/t/insert_batch", "application/x-ndjson", &pieces).await;
+    assert_eq!(status, 200, "{}", resp);
+    // blank lines don't count as rows; the unterminated last line does
+    assert_eq!(resp["ids"], json!([0, 1, null, null, 2]));
+    assert_eq!((resp["inserted"].as_u64(), resp["ok"].as_bool(), resp["total"].as_u64()), (Some(3), Some(false), Some(3)));
+    assert_eq!(codes(&resp), [(2, "invalid_body".to_string()), (3, "dimension_mismatch".to_string())]);
+
+    let first: Value = http.get(server.url("/db/t/vectors/0")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(first["metadata"]["name"], "zéro");
+    let last: Value = http.get(server.url("/db/t/vectors/2")).send().await.unwrap().json().await.unwrap();
+    assert_eq!((&last["values"], &last["metadata"]["name"]), (&json!([4.0, 1.0]), &json!("four")));
+
+    // application/jsonl is read the same way
+    let (status, resp) = post_chunked(&server, "/db/t/insert_batch", "application/jsonl", &[b"{\"values\":[5,1]}\n{\"val", b"ues\":[6,1]}\n"]).await;
+    assert_eq!((status, &resp["ids"]), (200, &json!([3, 4])));
+}
+
+#[tokio::test]
+async fn a_json_array_reports_bad_rows_and_commits_the_rest() {
+    let dir = TempDir::new("insert-array");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve", "--write-mode", "write-through"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    // a new database takes the dimension of the first good row
+    let rows = json!([{"values": "nope"}, {"values": [1.0, 2.0, 3.0], "meta": {"source": "s1"}}, {"values": [4.0, 5.0]}, {"values": [6.0, 7.0, 8.0]}, {"meta": {}}]);
+    let resp: Value = http.post(server.url("/db/t/insert_batch")).json(&rows).send().await.unwrap().json().await.unwrap();
+    assert_eq!(resp["ids"], json!([null, 0, null, 1, null]));
+    assert_eq!(codes(&resp), [(0, "invalid_body".to_string()), (2, "dimension_mismatch".to_string()), (4, "invalid_body".to_string())]);
+    assert_eq!((resp["inserted"].as_u64(), resp["durability"].as_str()), (Some(2), Some("disk")));
+
+    // not an array at all fails as a whole
+    let resp = http.post(server.url("/db/t/insert_batch")).json(&json!({"values": [1.0, 2.0, 3.0]})).send().await.unwrap();
+    assert_eq!(resp.status(), 422);
+
+    // what was acknowledged is on disk together
+    drop(server);
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let info: Value = http.get(server.url("/db/t/info")).send().await.unwrap().json().await.unwrap();
+    assert_eq!((info["dimension"].as_u64(), info["count"].as_u64()), (Some(3), Some(2)));
+}
@@ -0,0 +1,87 @@
+//! Database lifecycle over REST: list, drop, rename and clone, and which files belong to which
+//! database.
+
+mod common;
+
+use common::{run, Server, TempDir};
+use serde_json::{json, Value};
+
+async fn call(http: &reqwest::Client, req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = http.execute(req.build().unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+async fn ok(http: &reqwest::Client, req: reqwest::RequestBuilder) -> Value {
+    let (status, body) = call(http, req).await;
+    assert_eq!(status, 200, "{}", body);
+    body
+}
+
+// Imports `rows` two-dimensional rows as `name`, two rows per `_part_<n>.bin` shard file.
+fn import(dir: &TempDir, data: &str, name: &str, rows: usize) {
+    let sqlite = dir.join(&format!("{}.sqlite", name));
+    let conn = rusqlite::Connection::open(&sqlite).unwrap();
+    conn.execute("CREATE TABLE t (x REAL, y REAL)", []).unwrap();
+    for i in 0..rows { conn.execute("INSERT INTO t VALUES (?1, ?2)", [i as f64, 1.0]).unwrap(); }
+    drop(conn);
+    run(data, &["import-sqlite", "--sqlite", &sqlite, "--table", "t", "--name", name, "--vec-cols", "x,y", "--batch-size", "2"]);
+}
+
+fn files(data: &str) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(data).unwrap().flatten().map(|e| e.file_name().to_string_lossy().into_owned()).filter(|f| f.ends_with(".bin")).collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn shard_files_belong_only_to_their_own_database() {
+    let dir = TempDir::new("lifecycle");
+    let data = dir.join("data");
+    import(&dir, &data, "docs", 5);
+    assert_eq!(files(&data), ["docs_part_0.bin", "docs_part_1.bin", "docs_part_2.bin"]);
+
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    // a database whose name merely contains _part_ is a database of its own
+    ok(&http, http.post(server.url("/create")).json(&json!({"name": "docs_part_old", "dimension": 2}))).await;
+    for i in 0..3 { ok(&http, http.post(server.url("/db/docs_part_old/insert")).json(&json!({"values": [i as f64, 9.0]}))).await; }
+    ok(&http, http.post(server.url("/db/docs_part_old/flush"))).await;
+    assert!(files(&data).contains(&"docs_part_old.bin".to_string()));
+
+    let count = async |name: &str| ok(&http, http.get(server.url(&format!("/db/{}/info", name)))).await["count"].as_u64().unwrap();
+    assert_eq!(count("docs").await, 5);
+    assert_eq!(count("docs_part_old").await, 3);
+    let listed: Vec<String> = ok(&http, http.get(server.url("/dbs"))).await.as_array().unwrap().iter().map(|d| d["name"].as_str().unwrap().to_string()).collect();
+    assert_eq!(listed, ["docs", "docs_part_old"]);
+
+    // a rename moves the shards and nothing else
+    ok(&http, http.post(server.url("/db/docs/rename")).json(&json!({"to": "papers"}))).await;
+    assert_eq!(files(&data), ["docs_part_old.bin", "papers_part_0.bin", "papers_part_1.bin", "papers_part_2.bin"]);
+    assert_eq!(count("papers").await, 5);
+    assert_eq!(count("docs_part_old").await, 3);
+
+    // and a drop removes only the dropped database's files
+    ok(&http, http.delete(server.url("/db/papers"))).await;
+    assert_eq!(files(&data), ["docs_part_old.bin"]);
+    assert_eq!(count("docs_part_old").await, 3);
+
+    // names that look like a shard of another database are refused
+    let (status, body) = call(&http, http.post(server.url("/create")).json(&json!({"name": "docs_part_7", "dimension": 2}))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (400, Some("bad_request")), "{}", body);
+}
+
+#[tokio::test]
+async fn flush_and_evict_validate_the_name() {
+    let dir = TempDir::new("lifecycle-names");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    for action in ["flush", "evict"] {
+        let (status, body) = call(&http, http.post(server.url(&format!("/db/..%2Fnope/{}", action)))).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (400, Some("bad_request")), "{}: {}", action, body);
+        let (status, body) = call(&http, http.post(server.url(&format!("/db/nope/{}", action)))).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (404, Some("db_not_found")), "{}: {}", action, body);
+    }
+}
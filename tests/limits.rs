@@ -0,0 +1,85 @@
+//! Request limits: `429` with `Retry-After` once a client's bucket is empty, one bucket per key
+//! (and per address for callers without a valid key), and `413` for oversized bodies, batches
+//! and `k`.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const ADMIN: &str = "admin-key";
+const OTHER: &str = "other-key";
+
+async fn call(req: reqwest::RequestBuilder) -> (u16, Option<String>, Value) {
+    let resp = req.send().await.unwrap();
+    let status = resp.status().as_u16();
+    let retry_after = resp.headers().get("retry-after").map(|v| v.to_str().unwrap().to_string());
+    (status, retry_after, resp.json().await.unwrap_or(Value::Null))
+}
+
+fn code(body: &Value) -> Option<&str> { body["error"]["code"].as_str() }
+
+#[tokio::test]
+async fn each_key_has_its_own_bucket_and_is_told_when_to_retry() {
+    let dir = TempDir::new("limits-rate");
+    let auth = dir.join("auth.json");
+    std::fs::write(&auth, json!({"keys": [
+        {"name": "admin", "key": ADMIN, "admin": true},
+        {"name": "other", "key": OTHER, "read": ["*"]},
+    ]}).to_string()).unwrap();
+    // three requests at once, then one every ten seconds
+    let server = Server::start(&dir.join("data"), &["serve", "--auth-config", &auth, "--rate-limit", "0.1", "--rate-burst", "3"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    let dbs = |key: &str| http.get(server.url("/dbs")).bearer_auth(key);
+
+    for _ in 0..3 { assert_eq!(call(dbs(ADMIN)).await.0, 200); }
+    let (status, retry_after, body) = call(dbs(ADMIN)).await;
+    assert_eq!((status, code(&body)), (429, Some("rate_limited")));
+    let retry_after: u64 = retry_after.expect("a 429 says when to retry").parse().unwrap();
+    assert!((1..=10).contains(&retry_after), "Retry-After: {}", retry_after);
+
+    // another key is unaffected
+    for _ in 0..3 { assert_eq!(call(dbs(OTHER)).await.0, 200); }
+    assert_eq!(call(dbs(OTHER)).await.0, 429);
+
+    // a wrong key is charged to the address, so guessing keys is limited too
+    for _ in 0..3 { assert_eq!(call(dbs("guess")).await.0, 401); }
+    let (status, _, body) = call(dbs("guess")).await;
+    assert_eq!((status, code(&body)), (429, Some("rate_limited")));
+
+    // probes are never limited
+    for _ in 0..5 { assert_eq!(call(http.get(server.url("/healthz"))).await.0, 200); }
+}
+
+#[tokio::test]
+async fn oversized_requests_are_too_large() {
+    let dir = TempDir::new("limits-size");
+    let server = Server::start(&dir.join("data"), &["serve", "--max-k", "5", "--max-batch", "3", "--max-body-mb", "1"]);
+    server.wait_listening();
+    // the server answers an oversized body without reading it and closes the connection, so
+    // none is kept for the next request
+    let http = reqwest::Client::builder().pool_max_idle_per_host(0).build().unwrap();
+    let post = |path: &str, body: Value| http.post(server.url(path)).json(&body);
+    let rows: Vec<Value> = (0..3).map(|i| json!({"values": [i as f64, 0.0]})).collect();
+    assert_eq!(call(post("/db/t/insert_batch", json!(rows))).await.0, 200);
+
+    let too_large = [
+        ("k", post("/db/t/find", json!({"values": [0.0, 0.0], "k": 6}))),
+        ("radius max_results", post("/db/t/find", json!({"values": [0.0, 0.0], "radius": 1.0, "max_results": 6}))),
+        ("find_batch k", post("/db/t/find_batch", json!({"queries": [[0.0, 0.0]], "k": 6}))),
+        ("insert rows", post("/db/t/insert_batch", json!([rows.clone(), vec![json!({"values": [9.0, 0.0]})]].concat()))),
+        ("queries", post("/db/t/find_batch", json!({"queries": [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [3.0, 0.0]], "k": 1}))),
+        ("scroll page", post("/db/t/scroll", json!({"limit": 4}))),
+        // well over a megabyte of JSON
+        ("body", post("/db/t/insert", json!({"values": vec![0.123456789f64; 200_000]}))),
+    ];
+    for (what, req) in too_large {
+        let (status, _, body) = call(req).await;
+        assert_eq!((status, code(&body)), (413, Some("too_large")), "{}: {}", what, body);
+    }
+    // at the limits themselves everything is fine
+    assert_eq!(call(post("/db/t/find", json!({"values": [0.0, 0.0], "k": 5}))).await.0, 200);
+    assert_eq!(call(post("/db/t/scroll", json!({"limit": 3}))).await.0, 200);
+    assert_eq!(call(http.get(server.url("/db/t/info"))).await.2["count"], 3);
+}
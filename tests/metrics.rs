@@ -0,0 +1,74 @@
+//! `GET /metrics`: Prometheus text with query latency per database and metric, inserts,
+//! cache lookups and loads, flushes and the cache gauges, each moving with what the server did.
+
+mod common;
+
+use std::collections::HashMap;
+use common::{Server, TempDir};
+use serde_json::json;
+
+// Every sample in the exposition, keyed by name and labels as written.
+fn samples(text: &str) -> HashMap<String, f64> {
+    text.lines()
+        .filter(|l| !l.starts_with('#') && !l.is_empty())
+        .map(|l| {
+            let (key, value) = l.rsplit_once(' ').unwrap();
+            (key.to_string(), value.parse().unwrap())
+        })
+        .collect()
+}
+
+async fn scrape(http: &reqwest::Client, server: &Server) -> HashMap<String, f64> {
+    let resp = http.get(server.url("/metrics")).send().await.unwrap();
+    assert_eq!(resp.headers()["content-type"], "text/plain; version=0.0.4");
+    let text = resp.text().await.unwrap();
+    // every family is introduced by its HELP and TYPE lines
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        let family = line.split(['{', ' ']).next().unwrap();
+        let family = family.trim_end_matches("_bucket").trim_end_matches("_sum").trim_end_matches("_count");
+        assert!(text.contains(&format!("# TYPE {} ", family)), "no TYPE for {}", line);
+    }
+    samples(&text)
+}
+
+#[tokio::test]
+async fn metrics_follow_what_the_server_did() {
+    let dir = TempDir::new("metrics");
+    let server = Server::start(&dir.join("data"), &["serve", "--flush-interval-sec", "3600"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    let post = |path: &str, body: serde_json::Value| http.post(server.url(path)).json(&body).send();
+
+    post("/create", json!({"name": "t", "dimension": 2})).await.unwrap();
+    for i in 0..5 { post("/db/t/insert", json!({"values": [i as f64, 1.0]})).await.unwrap(); }
+    post("/db/t/insert_batch", json!([{"values": [1.0, 0.0]}, {"values": [2.0, 0.0]}, {"values": [3.0]}])).await.unwrap();
+    for _ in 0..3 { post("/db/t/find", json!({"values": [0.0, 0.0], "k": 2})).await.unwrap(); }
+    post("/db/t/find_batch", json!({"queries": [[0.0, 1.0], [1.0, 0.0]], "f": "cs"})).await.unwrap();
+
+    let m = scrape(&http, &server).await;
+    assert_eq!(m["vectra_inserted_vectors_total{db=\"t\"}"], 7.0, "the bad row doesn't count");
+    assert_eq!(m["vectra_query_duration_seconds_count{db=\"t\",metric=\"eu\"}"], 3.0);
+    assert_eq!(m["vectra_query_duration_seconds_count{db=\"t\",metric=\"cs\"}"], 1.0);
+    assert_eq!(m["vectra_query_duration_seconds_bucket{db=\"t\",metric=\"eu\",le=\"+Inf\"}"], 3.0);
+    // buckets are cumulative
+    let buckets: Vec<f64> = ["0.0005", "0.005", "0.05", "0.5", "5", "10"].iter().map(|le| m[&format!("vectra_query_duration_seconds_bucket{{db=\"t\",metric=\"eu\",le=\"{}\"}}", le)]).collect();
+    assert!(buckets.windows(2).all(|w| w[0] <= w[1]), "{:?}", buckets);
+    assert!(m["vectra_query_duration_seconds_sum{db=\"t\",metric=\"eu\"}"] > 0.0);
+    assert_eq!((m["vectra_cache_databases"], m["vectra_cache_dirty_databases"]), (1.0, 1.0));
+    assert!(m["vectra_cache_bytes"] > 0.0);
+    assert!(m["vectra_cache_requests_total{result=\"hit\"}"] >= 9.0);
+    assert_eq!(m["vectra_flush_duration_seconds_count"], 0.0);
+
+    // a flush, an eviction, and a miss that loads the database from disk again
+    http.post(server.url("/db/t/evict")).send().await.unwrap();
+    let misses = m["vectra_cache_requests_total{result=\"miss\"}"];
+    post("/db/t/find", json!({"values": [0.0, 0.0]})).await.unwrap();
+
+    let m = scrape(&http, &server).await;
+    assert_eq!(m["vectra_flush_duration_seconds_count"], 1.0);
+    assert_eq!(m["vectra_flush_failures_total"], 0.0);
+    assert_eq!(m["vectra_cache_requests_total{result=\"miss\"}"], misses + 1.0);
+    assert_eq!(m["vectra_cache_disk_loads_total"], 1.0);
+    assert_eq!(m["vectra_cache_dirty_databases"], 0.0);
+    assert_eq!(m["vectra_query_duration_seconds_count{db=\"t\",metric=\"eu\"}"], 4.0);
+}
@@ -0,0 +1,104 @@
+//! The API description: `/openapi.json` lists every route the server answers, with schemas that
+//! match real responses, and it and the `/docs` UI stay open when keys are required.
+
+mod common;
+
+use std::collections::BTreeSet;
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const ADMIN: &str = "admin-key";
+
+fn start(dir: &TempDir) -> Server {
+    let auth = dir.join("auth.json");
+    std::fs::write(&auth, json!({"keys": [{"name": "admin", "key": ADMIN, "admin": true}]}).to_string()).unwrap();
+    let server = Server::start(&dir.join("data"), &["serve", "--auth-config", &auth]);
+    server.wait_listening();
+    server
+}
+
+// The keys of a real response object fit the schema: all required ones present, none unknown.
+fn fits(spec: &Value, schema: &str, object: &Value) {
+    let schema = &spec["components"]["schemas"][schema];
+    let known: BTreeSet<&str> = schema["properties"].as_object().unwrap().keys().map(|k| k.as_str()).collect();
+    let got: BTreeSet<&str> = object.as_object().unwrap().keys().map(|k| k.as_str()).collect();
+    assert!(got.is_subset(&known), "{:?} not all in {:?}", got, known);
+    for required in schema["required"].as_array().unwrap() {
+        assert!(got.contains(required.as_str().unwrap()), "{} missing from {}", required, object);
+    }
+}
+
+#[tokio::test]
+async fn the_spec_describes_the_routes_and_their_bodies() {
+    let dir = TempDir::new("openapi");
+    let server = start(&dir);
+    let http = reqwest::Client::new();
+
+    // no key needed for the description
+    let resp = http.get(server.url("/openapi.json")).send().await.unwrap();
+    assert_eq!((resp.status().as_u16(), resp.headers()["content-type"].to_str().unwrap()), (200, "application/json"));
+    let spec: Value = resp.json().await.unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."), "{}", spec["openapi"]);
+    assert_eq!(spec["info"]["title"], "Vectra");
+
+    let paths: BTreeSet<&str> = spec["paths"].as_object().unwrap().keys().map(|k| k.as_str()).collect();
+    for (path, method) in [
+        ("/create", "post"), ("/dbs", "get"), ("/db/{name}", "delete"), ("/db/{name}/rename", "post"), ("/db/{name}/clone", "post"),
+        ("/db/{name}/info", "get"), ("/db/{name}/insert", "post"), ("/db/{name}/insert_batch", "post"), ("/db/{name}/find", "post"),
+        ("/db/{name}/find_batch", "post"), ("/db/{name}/vectors/{id}", "get"), ("/db/{name}/scroll", "post"), ("/db/{name}/flush", "post"),
+        ("/db/{name}/evict", "post"), ("/db/{name}/snapshot", "get"), ("/db/{name}/tail", "get"), ("/db/{name}/webhooks", "post"),
+        ("/db/{name}/webhooks", "get"), ("/db/{name}/webhooks/{id}", "delete"), ("/status", "get"), ("/metrics", "get"),
+        ("/events", "get"), ("/healthz", "get"), ("/readyz", "get"),
+    ] {
+        let op = &spec["paths"][path][method];
+        assert!(op.is_object(), "{} {} not described; have {:?}", method, path, paths);
+        assert_eq!(op["responses"]["default"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/ErrorBody", "{} {}", method, path);
+    }
+    // only routes that exist: the docs themselves aren't operations
+    assert_eq!(paths.len(), 23, "{:?}", paths);
+
+    // request bodies reference the types the handlers decode
+    for (path, schema) in [("/create", "CreateReq"), ("/db/{name}/insert", "InsertReq"), ("/db/{name}/find", "FindReq")] {
+        let body = &spec["paths"][path]["post"]["requestBody"]["content"]["application/json"]["schema"]["$ref"];
+        assert_eq!(body, &format!("#/components/schemas/{}", schema), "{}", path);
+    }
+    let find = &spec["paths"]["/db/{name}/find"]["post"]["responses"]["200"];
+    assert_eq!(find["content"]["application/json"]["schema"]["items"]["$ref"], "#/components/schemas/FindItem");
+    assert!(find["headers"]["x-search-incomplete"].is_object());
+
+    // keys are declared, and the probes need none
+    let schemes = &spec["components"]["securitySchemes"];
+    assert_eq!((schemes["bearer"]["scheme"].as_str(), schemes["api_key"]["name"].as_str()), (Some("bearer"), Some("x-api-key")));
+    assert_eq!(spec["paths"]["/healthz"]["get"]["security"], json!([{}]));
+
+    // and what the server sends fits what the spec says
+    let post = |path: &str, body: Value| http.post(server.url(path)).bearer_auth(ADMIN).json(&body).send();
+    assert_eq!(post("/create", json!({"name": "t", "dimension": 2})).await.unwrap().status(), 200);
+    let inserted: Value = post("/db/t/insert", json!({"values": [1.0, 2.0], "meta": {"tag": "a"}})).await.unwrap().json().await.unwrap();
+    fits(&spec, "InsertResp", &inserted);
+    let info: Value = http.get(server.url("/db/t/info")).bearer_auth(ADMIN).send().await.unwrap().json().await.unwrap();
+    fits(&spec, "InfoResp", &info);
+    let found: Value = post("/db/t/find", json!({"values": [1.0, 2.0], "k": 1})).await.unwrap().json().await.unwrap();
+    fits(&spec, "FindItem", &found[0]);
+    let missing: Value = http.get(server.url("/db/nope/info")).bearer_auth(ADMIN).send().await.unwrap().json().await.unwrap();
+    fits(&spec, "ErrorBody", &missing);
+}
+
+#[tokio::test]
+async fn the_docs_ui_is_served_without_a_key() {
+    let dir = TempDir::new("openapi-docs");
+    let server = start(&dir);
+    let http = reqwest::Client::new();
+    let resp = http.get(server.url("/docs")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    assert!(resp.text().await.unwrap().contains("swagger-ui"));
+    // the UI's own assets, and its pointer at the spec
+    let initializer = http.get(server.url("/docs/swagger-initializer.js")).send().await.unwrap();
+    assert_eq!(initializer.status(), 200);
+    assert!(initializer.text().await.unwrap().contains("/openapi.json"));
+    assert_eq!(http.get(server.url("/docs/swagger-ui-bundle.js")).send().await.unwrap().status(), 200);
+
+    // while the API itself wants a key
+    assert_eq!(http.get(server.url("/dbs")).send().await.unwrap().status(), 401);
+}
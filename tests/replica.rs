@@ -0,0 +1,147 @@
+//! Followers: the first copy of the leader, keeping up with its change feed, copying again
+//! after the leader restarts with nothing left to replay, and refusing writes from clients.
+
+mod common;
+
+use std::time::Duration;
+use common::{eventually, free_port, Server, TempDir};
+use serde_json::{json, Value};
+
+async fn call(http: &reqwest::Client, req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = http.execute(req.build().unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+async fn ok(http: &reqwest::Client, req: reqwest::RequestBuilder) -> Value {
+    let (status, body) = call(http, req).await;
+    assert_eq!(status, 200, "{}", body);
+    body
+}
+
+fn follow(dir: &TempDir, leader: &Server) -> Server {
+    let follower = Server::start(&dir.join("follower"), &["serve", "--follow", &leader.url("")]);
+    follower.wait_listening();
+    follower
+}
+
+async fn insert(http: &reqwest::Client, server: &Server, db: &str, from: usize, n: usize) {
+    let rows: Vec<Value> = (from..from + n).map(|i| json!({"values": [i as f64, 1.0], "meta": {"n": i.to_string()}})).collect();
+    ok(http, http.post(server.url(&format!("/db/{}/insert_batch", db))).json(&rows)).await;
+}
+
+// Names and counts of every database, as `/dbs` lists them.
+async fn contents(http: &reqwest::Client, server: &Server) -> Vec<(String, u64)> {
+    let listed = ok(http, http.get(server.url("/dbs"))).await;
+    let mut out: Vec<(String, u64)> = listed.as_array().unwrap().iter().map(|d| (d["name"].as_str().unwrap().to_string(), d["count"].as_u64().unwrap())).collect();
+    out.sort();
+    out
+}
+
+// Waits until the follower holds what the leader does and has applied its last event.
+async fn in_sync(http: &reqwest::Client, leader: &Server, follower: &Server) -> Value {
+    let want = contents(http, leader).await;
+    let seq = ok(http, http.get(leader.url("/status"))).await["event_seq"].clone();
+    let synced = eventually(Duration::from_secs(30), async || {
+        let status = ok(http, http.get(follower.url("/status"))).await;
+        status["replication"]["applied_seq"] == seq && contents(http, follower).await == want
+    }).await;
+    let status = ok(http, http.get(follower.url("/status"))).await;
+    assert!(synced, "follower never caught up to {:?} at seq {}: {}", want, seq, status["replication"]);
+    status["replication"].clone()
+}
+
+#[tokio::test]
+async fn a_follower_copies_the_leader_then_follows_its_changes() {
+    let dir = TempDir::new("replica");
+    let leader = Server::start(&dir.join("leader"), &["serve"]);
+    leader.wait_listening();
+    let http = reqwest::Client::new();
+    ok(&http, http.post(leader.url("/create")).json(&json!({"name": "docs", "dimension": 2}))).await;
+    insert(&http, &leader, "docs", 0, 25).await;
+    ok(&http, http.post(leader.url("/create")).json(&json!({"name": "empty", "dimension": 2}))).await;
+
+    // bootstrap: not ready until the first copy is done, then it holds everything
+    let follower = follow(&dir, &leader);
+    assert!(eventually(Duration::from_secs(30), async || http.get(follower.url("/readyz")).send().await.unwrap().status() == 200).await);
+    let replication = in_sync(&http, &leader, &follower).await;
+    assert_eq!((replication["state"].as_str(), replication["bootstraps"].as_u64(), replication["lag_events"].as_u64()), (Some("streaming"), Some(1), Some(0)));
+
+    // then every kind of change arrives through the feed, without copying again
+    insert(&http, &leader, "docs", 25, 10).await;
+    ok(&http, http.post(leader.url("/db/docs/clone")).json(&json!({"to": "copy"}))).await;
+    ok(&http, http.post(leader.url("/db/empty/rename")).json(&json!({"to": "renamed"}))).await;
+    insert(&http, &leader, "renamed", 0, 3).await;
+    ok(&http, http.delete(leader.url("/db/copy"))).await;
+    let replication = in_sync(&http, &leader, &follower).await;
+    assert_eq!(replication["bootstraps"], 1);
+    assert_eq!(contents(&http, &follower).await, [("docs".to_string(), 35), ("renamed".to_string(), 3)]);
+
+    // and reads answer the same on both
+    let query = json!({"values": [30.2, 1.0], "k": 3});
+    let on_leader = ok(&http, http.post(leader.url("/db/docs/find")).json(&query)).await;
+    let on_follower = ok(&http, http.post(follower.url("/db/docs/find")).json(&query)).await;
+    assert_eq!(on_leader, on_follower);
+    assert_eq!(ok(&http, http.get(follower.url("/db/docs/vectors/34"))).await["metadata"]["n"], "34");
+}
+
+#[tokio::test]
+async fn a_follower_copies_again_when_the_leader_lost_the_events_it_needs() {
+    let dir = TempDir::new("replica-gap");
+    let (leader_dir, leader_addr) = (dir.join("leader"), format!("127.0.0.1:{}", free_port()));
+    let args = ["serve", "--write-mode", "write-through"];
+    let leader = Server::start_at(&leader_dir, &args, &leader_addr);
+    leader.wait_listening();
+    // no pooled connection may outlive the first leader
+    let http = reqwest::Client::builder().pool_max_idle_per_host(0).build().unwrap();
+    ok(&http, http.post(leader.url("/create")).json(&json!({"name": "docs", "dimension": 2}))).await;
+    insert(&http, &leader, "docs", 0, 5).await;
+    let follower = follow(&dir, &leader);
+    in_sync(&http, &leader, &follower).await;
+
+    // a restarted leader has an empty event buffer, so the follower can't replay what
+    // happened while it was gone and has to take a new copy
+    drop(leader);
+    let leader = Server::start_at(&leader_dir, &args, &leader_addr);
+    leader.wait_listening();
+    insert(&http, &leader, "docs", 5, 7).await;
+    ok(&http, http.post(leader.url("/create")).json(&json!({"name": "later", "dimension": 2}))).await;
+    let replication = in_sync(&http, &leader, &follower).await;
+    assert_eq!(replication["bootstraps"], 2, "{}", replication);
+    assert_eq!(contents(&http, &follower).await, [("docs".to_string(), 12), ("later".to_string(), 0)]);
+
+    // and it goes on streaming from there
+    insert(&http, &leader, "later", 0, 2).await;
+    assert_eq!(in_sync(&http, &leader, &follower).await["bootstraps"], 2);
+}
+
+#[tokio::test]
+async fn a_follower_refuses_writes_and_names_its_leader() {
+    let dir = TempDir::new("replica-readonly");
+    let leader = Server::start(&dir.join("leader"), &["serve"]);
+    leader.wait_listening();
+    let http = reqwest::Client::new();
+    ok(&http, http.post(leader.url("/create")).json(&json!({"name": "docs", "dimension": 2}))).await;
+    insert(&http, &leader, "docs", 0, 2).await;
+    let follower = follow(&dir, &leader);
+    in_sync(&http, &leader, &follower).await;
+
+    let writes = [
+        ("create", http.post(follower.url("/create")).json(&json!({"name": "x", "dimension": 2}))),
+        ("insert", http.post(follower.url("/db/docs/insert")).json(&json!({"values": [1.0, 1.0]}))),
+        ("insert into a new db", http.post(follower.url("/db/new/insert")).json(&json!({"values": [1.0, 1.0]}))),
+        ("insert_batch", http.post(follower.url("/db/docs/insert_batch")).json(&json!([{"values": [1.0, 1.0]}]))),
+        ("drop", http.delete(follower.url("/db/docs"))),
+        ("rename", http.post(follower.url("/db/docs/rename")).json(&json!({"to": "y"}))),
+        ("clone", http.post(follower.url("/db/docs/clone")).json(&json!({"to": "y"}))),
+        ("webhook", http.post(follower.url("/db/docs/webhooks")).json(&json!({"url": "http://127.0.0.1:9/"}))),
+    ];
+    for (what, req) in writes {
+        let (status, body) = call(&http, req).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (403, Some("read_only")), "{}", what);
+        assert!(body["error"]["message"].as_str().unwrap().contains(&leader.url("")), "{}: {}", what, body);
+    }
+    // nothing changed here, and reads still work
+    assert_eq!(contents(&http, &follower).await, [("docs".to_string(), 2)]);
+    assert_eq!(ok(&http, http.get(follower.url("/db/docs/info"))).await["count"], 2);
+}
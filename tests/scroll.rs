@@ -0,0 +1,80 @@
+//! Reading stored vectors back: by id, and page by page with cursors, a metadata filter and
+//! without values, over REST and with the CLI `scan`.
+
+mod common;
+
+use common::{run, Server, TempDir};
+use serde_json::{json, Value};
+
+async fn call(http: &reqwest::Client, req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = http.execute(req.build().unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+// Every page of `body`, following `next_cursor` until there is none.
+async fn pages(http: &reqwest::Client, server: &Server, body: Value) -> Vec<Value> {
+    let mut out = Vec::new();
+    let mut body = body;
+    loop {
+        let (status, page) = call(http, http.post(server.url("/db/t/scroll")).json(&body)).await;
+        assert_eq!(status, 200, "{}", page);
+        let next = page["next_cursor"].clone();
+        out.push(page);
+        if next.is_null() { return out; }
+        body["cursor"] = next;
+        assert!(out.len() < 100, "cursors never ran out");
+    }
+}
+
+fn ids(page: &Value) -> Vec<u64> { page["items"].as_array().unwrap().iter().map(|i| i["id"].as_u64().unwrap()).collect() }
+
+#[tokio::test]
+async fn cursors_page_through_every_matching_vector_once() {
+    let dir = TempDir::new("scroll");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    let rows: Vec<Value> = (0..25).map(|i| json!({"values": [i as f64, -(i as f64)], "meta": {"kind": if i % 3 == 0 { "a" } else { "b" }}})).collect();
+    call(&http, http.post(server.url("/db/t/insert_batch")).json(&rows)).await;
+
+    let got = call(&http, http.get(server.url("/db/t/vectors/7"))).await.1;
+    assert_eq!((&got["id"], &got["values"], &got["metadata"]["kind"]), (&json!(7), &json!([7.0, -7.0]), &json!("b")));
+    let got = call(&http, http.get(server.url("/db/t/vectors/7?with_values=false"))).await.1;
+    assert!(got.get("values").is_none(), "{}", got);
+    let (status, body) = call(&http, http.get(server.url("/db/t/vectors/25"))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (404, Some("vector_not_found")));
+
+    // ten at a time: three pages, in id order, nothing twice, and no cursor after the last
+    let all = pages(&http, &server, json!({"limit": 10})).await;
+    assert_eq!(all.iter().map(|p| ids(p).len()).collect::<Vec<_>>(), [10, 10, 5]);
+    assert_eq!(all.iter().flat_map(ids).collect::<Vec<_>>(), (0..25).collect::<Vec<_>>());
+    assert_eq!((&all[1]["items"][0]["values"], &all[1]["items"][0]["metadata"]["kind"]), (&json!([10.0, -10.0]), &json!("b")));
+
+    // a filter skips over what doesn't match, and values can be left out
+    let only_a = pages(&http, &server, json!({"limit": 4, "filter": {"kind": "a"}, "with_values": false})).await;
+    assert_eq!(only_a.iter().flat_map(ids).collect::<Vec<_>>(), [0, 3, 6, 9, 12, 15, 18, 21, 24]);
+    assert!(only_a.iter().flat_map(|p| p["items"].as_array().unwrap().clone()).all(|i| i.get("values").is_none()));
+    // a page that fills up exactly on the last match still says there's nothing after it
+    let exact = pages(&http, &server, json!({"limit": 9, "filter": {"kind": "a"}})).await;
+    assert_eq!(exact.len(), 1);
+
+    for (body, code) in [(json!({"cursor": "zz"}), "invalid_cursor"), (json!({"limit": 0}), "bad_request")] {
+        let (status, resp) = call(&http, http.post(server.url("/db/t/scroll")).json(&body)).await;
+        assert_eq!((status, resp["error"]["code"].as_str()), (400, Some(code)), "{}", body);
+    }
+
+    // `scan` pages the same way from the files
+    call(&http, http.post(server.url("/db/t/flush"))).await;
+    drop(server);
+    let first = run(&data, &["scan", "t", "--limit", "10", "-m", "kind=b"]);
+    let lines: Vec<&str> = first.lines().collect();
+    assert!(lines[0].starts_with("id=1\t") && lines[0].contains("kind=b") && lines[0].ends_with("\tvalues=[1.0, -1.0]"), "{}", lines[0]);
+    let cursor = lines[10].strip_prefix("next_cursor=").unwrap();
+    let rest = run(&data, &["scan", "t", "--cursor", cursor, "--no-values", "-m", "kind=b"]);
+    let rest: Vec<&str> = rest.lines().collect();
+    assert_eq!(rest.len(), 6);
+    assert!(rest[0].starts_with("id=16\t") && rest[5].starts_with("id=23\t"), "{:?}", rest);
+    assert!(rest.iter().all(|l| !l.contains("values=")), "{:?}", rest);
+}
@@ -0,0 +1,114 @@
+//! Searches over REST and the CLI: radius mode under every metric, with its cap and validation,
+//! and batches of queries answering exactly what one search per query would.
+
+mod common;
+
+use common::{run, Server, TempDir};
+use serde_json::{json, Value};
+
+async fn call(http: &reqwest::Client, req: reqwest::RequestBuilder) -> (u16, Value) {
+    let resp = http.execute(req.build().unwrap()).await.unwrap();
+    let status = resp.status().as_u16();
+    (status, resp.json().await.unwrap_or(Value::Null))
+}
+
+async fn ok(http: &reqwest::Client, req: reqwest::RequestBuilder) -> Value {
+    let (status, body) = call(http, req).await;
+    assert_eq!(status, 200, "{}", body);
+    body
+}
+
+fn indexes(found: &Value) -> Vec<u64> {
+    found.as_array().unwrap().iter().map(|r| r["index"].as_u64().unwrap()).collect()
+}
+
+// `line`: (i, 0) for i in 0..10, tagged odd or even; `angles`: unit-ish vectors around the circle.
+async fn load(http: &reqwest::Client, server: &Server) {
+    let line: Vec<Value> = (0..10).map(|i| json!({"values": [i as f64, 0.0], "meta": {"parity": if i % 2 == 0 { "even" } else { "odd" }}})).collect();
+    ok(http, http.post(server.url("/db/line/insert_batch")).json(&line)).await;
+    let angles = json!([{"values": [1.0, 0.0]}, {"values": [1.0, 1.0]}, {"values": [0.0, 1.0]}, {"values": [-1.0, 0.0]}]);
+    ok(http, http.post(server.url("/db/angles/insert_batch")).json(&angles)).await;
+}
+
+#[tokio::test]
+async fn radius_search_returns_everything_within_the_distance() {
+    let dir = TempDir::new("search-radius");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    load(&http, &server).await;
+    let find = |db: &str, body: Value| http.post(server.url(&format!("/db/{}/find", db))).json(&body);
+
+    // the bound is inclusive, and results come nearest first
+    let found = ok(&http, find("line", json!({"values": [3.25, 0.0], "radius": 1.25}))).await;
+    assert_eq!(indexes(&found), [3, 4, 2]);
+    let found = ok(&http, find("line", json!({"values": [0.0, 0.0], "radius": 3.0, "f": "l1"}))).await;
+    assert_eq!(indexes(&found), [0, 1, 2, 3]);
+    assert_eq!(found[3]["distance"], 3.0);
+    // 1 - cos: 0 for the same direction, about 0.29 at 45 degrees, 1 at a right angle
+    let found = ok(&http, find("angles", json!({"values": [2.0, 0.0], "radius": 0.3, "f": "cs"}))).await;
+    assert_eq!(indexes(&found), [0, 1]);
+    // nothing in range is an empty list, not an error
+    assert_eq!(ok(&http, find("line", json!({"values": [50.0, 50.0], "radius": 1.0}))).await, json!([]));
+
+    // radius wins over k; max_results keeps the nearest few; a filter narrows what is in range
+    let found = ok(&http, find("line", json!({"values": [0.0, 0.0], "radius": 5.0, "k": 2}))).await;
+    assert_eq!(found.as_array().unwrap().len(), 6);
+    let found = ok(&http, find("line", json!({"values": [0.0, 0.0], "radius": 5.0, "max_results": 2}))).await;
+    assert_eq!(indexes(&found), [0, 1]);
+    let found = ok(&http, find("line", json!({"values": [0.0, 0.0], "radius": 5.0, "filter": {"parity": "odd"}}))).await;
+    assert_eq!(indexes(&found), [1, 3, 5]);
+
+    for radius in [json!(-1.0), json!(-0.0001)] {
+        let (status, body) = call(&http, find("line", json!({"values": [0.0, 0.0], "radius": radius}))).await;
+        assert_eq!((status, body["error"]["code"].as_str()), (400, Some("bad_request")), "{}", body);
+    }
+
+    // the CLI answers the same, from the files
+    http.post(server.url("/db/line/flush")).send().await.unwrap();
+    drop(server);
+    let out = run(&data, &["find", "line", "-v", "3.25,0", "--radius", "1.25"]);
+    let idx: Vec<&str> = out.lines().map(|l| l.split('\t').nth(1).unwrap()).collect();
+    assert_eq!(idx, ["idx=3", "idx=4", "idx=2"]);
+    assert_eq!(run(&data, &["find", "line", "-v", "0,0", "-r", "9", "--max-results", "4"]).lines().count(), 4);
+}
+
+#[tokio::test]
+async fn a_batch_answers_like_one_find_per_query() {
+    let dir = TempDir::new("search-batch");
+    let data = dir.join("data");
+    let server = Server::start(&data, &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    load(&http, &server).await;
+    let queries: Vec<Vec<f64>> = (0..40).map(|i| vec![(i as f64 * 0.7).sin() * 10.0, (i as f64 * 1.3).cos()]).collect();
+
+    // shared options apply to every query, and answers come back in query order
+    for opts in [json!({"k": 3}), json!({"k": 4, "f": "l1", "filter": {"parity": "even"}}), json!({"radius": 2.5, "f": "cs"})] {
+        let mut body = opts.clone();
+        body["queries"] = json!(queries);
+        let batch = ok(&http, http.post(server.url("/db/line/find_batch")).json(&body)).await;
+        assert_eq!(batch.as_array().unwrap().len(), queries.len());
+        for (q, query) in queries.iter().enumerate() {
+            let mut one = opts.clone();
+            one["values"] = json!(query);
+            let single = ok(&http, http.post(server.url("/db/line/find")).json(&one)).await;
+            assert_eq!(batch[q], single, "query {} with {}", q, opts);
+        }
+    }
+
+    // one query of the wrong length fails the whole batch
+    let (status, body) = call(&http, http.post(server.url("/db/line/find_batch")).json(&json!({"queries": [[1.0, 0.0], [1.0]], "k": 1}))).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (422, Some("dimension_mismatch")), "{}", body);
+    assert_eq!(ok(&http, http.post(server.url("/db/line/find_batch")).json(&json!({"queries": [], "k": 1}))).await, json!([]));
+
+    // the CLI reads queries from a file, one per line, comma or space separated
+    http.post(server.url("/db/line/flush")).send().await.unwrap();
+    drop(server);
+    let file = dir.join("queries.txt");
+    std::fs::write(&file, "# nearest two\n0.1,0\n\n8.9 0\n").unwrap();
+    let out = run(&data, &["find-batch", "line", "--queries", &file, "-k", "2"]);
+    let hits: Vec<(&str, &str)> = out.lines().map(|l| { let f: Vec<&str> = l.split('\t').collect(); (f[0], f[2]) }).collect();
+    assert_eq!(hits, [("q=0", "idx=0"), ("q=0", "idx=1"), ("q=1", "idx=9"), ("q=1", "idx=8")]);
+}
@@ -0,0 +1,86 @@
+//! Search deadlines: a per-request `timeout_ms` or the server's `--query-timeout-ms` stops a scan
+//! part way, and `on_timeout` decides between a `504 timeout` and the partial ranking.
+
+mod common;
+
+use common::{Server, TempDir};
+use serde_json::{json, Value};
+
+const ROWS: usize = 40_000;
+
+// Row `i` is `[i, 0, …]`, so the nearest rows to a query at the end are the last ones inserted.
+async fn load(http: &reqwest::Client, server: &Server) {
+    for start in (0..ROWS).step_by(10_000) {
+        let rows: Vec<Value> = (start..start + 10_000).map(|i| json!({"values": [i as f64, 0.0, 0.0, 0.0]})).collect();
+        let resp = http.post(server.url("/db/t/insert_batch")).json(&rows).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+}
+
+async fn find(http: &reqwest::Client, server: &Server, opts: Value) -> (u16, Option<String>, Value) {
+    let mut body = json!({"values": [ROWS as f64, 0.0, 0.0, 0.0], "k": 3});
+    body.as_object_mut().unwrap().extend(opts.as_object().unwrap().clone());
+    let resp = http.post(server.url("/db/t/find")).json(&body).send().await.unwrap();
+    let incomplete = resp.headers().get("x-search-incomplete").map(|v| v.to_str().unwrap().to_string());
+    (resp.status().as_u16(), incomplete, resp.json().await.unwrap())
+}
+
+fn indexes(results: &Value) -> Vec<u64> {
+    results.as_array().unwrap().iter().map(|r| r["index"].as_u64().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn a_request_deadline_fails_or_returns_what_was_ranked() {
+    let dir = TempDir::new("timeouts");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    load(&http, &server).await;
+    let last = ROWS as u64 - 1;
+
+    let (status, incomplete, body) = find(&http, &server, json!({})).await;
+    assert_eq!((status, incomplete, indexes(&body)), (200, None, vec![last, last - 1, last - 2]));
+
+    // a deadline already past stops the scan at its first check
+    let (status, _, body) = find(&http, &server, json!({"timeout_ms": 0})).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (504, Some("timeout")), "{}", body);
+    let (status, _, body) = find(&http, &server, json!({"timeout_ms": 0, "on_timeout": "error"})).await;
+    assert_eq!(status, 504, "{}", body);
+
+    // partial: the best of the rows scanned before stopping, flagged as such
+    let (status, incomplete, body) = find(&http, &server, json!({"timeout_ms": 0, "on_timeout": "partial"})).await;
+    assert_eq!((status, incomplete.as_deref()), (200, Some("true")));
+    let got = indexes(&body);
+    assert_eq!(got.len(), 3);
+    assert!(got.windows(2).all(|w| w[0] > w[1]) && got[0] < last - 2, "{:?}", got);
+
+    // the same for every query of a batch
+    let batch = json!({"queries": [[0.0, 0.0, 0.0, 0.0], [ROWS as f64, 0.0, 0.0, 0.0]], "k": 2, "timeout_ms": 0, "on_timeout": "partial"});
+    let resp = http.post(server.url("/db/t/find_batch")).json(&batch).send().await.unwrap();
+    assert_eq!((resp.status().as_u16(), resp.headers()["x-search-incomplete"].to_str().unwrap()), (200, "true"));
+    let lists: Vec<Value> = resp.json().await.unwrap();
+    assert_eq!(indexes(&lists[0]), [0, 1]);
+    assert!(indexes(&lists[1])[0] < last);
+
+    let (status, _, body) = find(&http, &server, json!({"on_timeout": "sometimes"})).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (422, Some("invalid_body")));
+}
+
+#[tokio::test]
+async fn the_server_default_applies_unless_a_request_sets_its_own() {
+    let dir = TempDir::new("timeouts-default");
+    let server = Server::start(&dir.join("data"), &["serve", "--query-timeout-ms", "1"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    load(&http, &server).await;
+
+    // a full scan of 40k rows in a debug build takes well over a millisecond
+    let (status, _, body) = find(&http, &server, json!({})).await;
+    assert_eq!((status, body["error"]["code"].as_str()), (504, Some("timeout")), "{}", body);
+    let (status, incomplete, _) = find(&http, &server, json!({"on_timeout": "partial"})).await;
+    assert_eq!((status, incomplete.as_deref()), (200, Some("true")));
+
+    let (status, incomplete, body) = find(&http, &server, json!({"timeout_ms": 60_000})).await;
+    assert_eq!((status, incomplete), (200, None));
+    assert_eq!(indexes(&body)[0], ROWS as u64 - 1);
+}
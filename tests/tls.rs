@@ -0,0 +1,87 @@
+//! HTTPS serving: client certificates under mutual TLS and hot reload of rotated files.
+
+mod common;
+
+use std::time::Duration;
+use common::{eventually, Server, TempDir};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+
+struct Ca { cert: Certificate, key: KeyPair }
+
+impl Ca {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "vectra test ca");
+        let key = KeyPair::generate().unwrap();
+        Ca { cert: params.self_signed(&key).unwrap(), key }
+    }
+
+    // A leaf certificate signed by this CA, returned as (certificate, key).
+    fn issue(&self, name: &str, usage: ExtendedKeyUsagePurpose) -> (Certificate, KeyPair) {
+        let mut params = CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![usage];
+        let key = KeyPair::generate().unwrap();
+        (params.signed_by(&key, &self.cert, &self.key).unwrap(), key)
+    }
+}
+
+fn write_server_files(dir: &TempDir, cert: &Certificate, key: &KeyPair) {
+    std::fs::write(dir.join("server.key"), key.serialize_pem()).unwrap();
+    std::fs::write(dir.join("server.pem"), cert.pem()).unwrap();
+}
+
+// A fresh client per call, so every request makes a new handshake.
+fn client(ca: &Ca, identity: Option<&(Certificate, KeyPair)>) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.cert.pem().as_bytes()).unwrap())
+        .tls_info(true)
+        .timeout(Duration::from_secs(5));
+    if let Some((cert, key)) = identity {
+        let pem = format!("{}{}", key.serialize_pem(), cert.pem());
+        builder = builder.identity(reqwest::Identity::from_pem(pem.as_bytes()).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+// The DER certificate the server presented for this response.
+fn peer_cert(resp: &reqwest::Response) -> Vec<u8> {
+    resp.extensions().get::<reqwest::tls::TlsInfo>().and_then(|i| i.peer_certificate()).unwrap().to_vec()
+}
+
+#[tokio::test]
+async fn mtls_requires_a_client_cert_and_picks_up_rotated_files() {
+    let dir = TempDir::new("tls");
+    let ca = Ca::new();
+    std::fs::write(dir.join("ca.pem"), ca.cert.pem()).unwrap();
+    let first = ca.issue("server one", ExtendedKeyUsagePurpose::ServerAuth);
+    write_server_files(&dir, &first.0, &first.1);
+    let user = ca.issue("client", ExtendedKeyUsagePurpose::ClientAuth);
+
+    let (cert, key, client_ca) = (dir.join("server.pem"), dir.join("server.key"), dir.join("ca.pem"));
+    let server = Server::start(&dir.join("data"), &["serve", "--tls-cert", &cert, "--tls-key", &key, "--tls-client-ca", &client_ca, "--tls-reload-sec", "1"]);
+    server.wait_listening();
+    let url = format!("https://{}/healthz", server.addr);
+
+    assert!(client(&ca, None).get(&url).send().await.is_err(), "a client without a certificate must be turned away");
+
+    let resp = client(&ca, Some(&user)).get(&url).send().await.unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(peer_cert(&resp), first.0.der().to_vec());
+
+    // mtimes can have one-second granularity, so make sure the rotation is a visible change
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let second = ca.issue("server two", ExtendedKeyUsagePurpose::ServerAuth);
+    write_server_files(&dir, &second.0, &second.1);
+
+    let want = second.0.der().to_vec();
+    let rotated = eventually(Duration::from_secs(10), async || {
+        match client(&ca, Some(&user)).get(&url).send().await {
+            Ok(resp) => peer_cert(&resp) == want,
+            Err(_) => false,
+        }
+    }).await;
+    assert!(rotated, "new handshakes should present the rotated certificate");
+}
@@ -0,0 +1,123 @@
+//! Diagnostic logs: JSON lines with the request and flush spans, the slow-query warning and its
+//! threshold, `RUST_LOG` filtering, and the import batch spans of the CLI.
+
+mod common;
+
+use std::process::Command;
+use std::time::Duration;
+use common::{eventually, Server, TempDir};
+use serde_json::{json, Value};
+
+// Every line of a JSON log, failing the test on one that isn't JSON.
+fn read_log(log: &str) -> Vec<Value> {
+    std::fs::read_to_string(log).unwrap().lines()
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("{}: {:?}", e, l)))
+        .collect()
+}
+
+fn slow_queries(lines: &[Value]) -> Vec<&Value> {
+    lines.iter().filter(|l| l["target"] == "vectra::slow_query").collect()
+}
+
+async fn load(http: &reqwest::Client, server: &Server, rows: usize) {
+    for start in (0..rows).step_by(10_000) {
+        let batch: Vec<Value> = (start..rows.min(start + 10_000)).map(|i| json!({"values": [i as f64, 0.0, 0.0, 0.0]})).collect();
+        let resp = http.post(server.url("/db/t/insert_batch")).json(&batch).send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+}
+
+async fn find(http: &reqwest::Client, server: &Server, f: &str) {
+    let resp = http.post(server.url("/db/t/find")).json(&json!({"values": [0.0, 0.0, 0.0, 0.0], "k": 3, "f": f})).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn json_logs_carry_spans_and_slow_queries() {
+    let dir = TempDir::new("tracing-json");
+    let log = dir.join("server.log");
+    let server = Server::start_logging(&dir.join("data"), &["--log-format", "json", "serve", "--slow-query-ms", "1"], "info,Vectra=debug", &log);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    // a full scan of 40k rows in a debug build takes well over a millisecond
+    load(&http, &server, 40_000).await;
+    find(&http, &server, "cs").await;
+    assert_eq!(http.post(server.url("/db/t/flush")).send().await.unwrap().status(), 200);
+    assert!(eventually(Duration::from_secs(5), async || read_log(&log).iter().any(|l| l["fields"]["message"] == "flushed")).await);
+
+    let lines = read_log(&log);
+    assert!(lines.iter().any(|l| l["fields"]["message"] == "listening"));
+    // each request is logged in its own span
+    let done = lines.iter().find(|l| l["span"]["uri"] == "/db/t/find" && l["fields"]["message"] == "finished processing request").unwrap();
+    assert_eq!((done["span"]["name"].as_str(), done["span"]["method"].as_str(), done["fields"]["status"].as_u64()), (Some("request"), Some("POST"), Some(200)));
+
+    let slow = slow_queries(&lines);
+    assert_eq!(slow.len(), 1, "{:?}", slow);
+    assert_eq!(slow[0]["level"], "WARN");
+    let fields = &slow[0]["fields"];
+    assert_eq!((fields["db"].as_str(), fields["metric"].as_str(), fields["k"].as_u64(), fields["queries"].as_u64()), (Some("t"), Some("cs"), Some(3), Some(1)));
+    assert!(fields["duration_ms"].as_u64().unwrap() >= 1, "{}", fields);
+    assert_eq!(slow[0]["span"]["uri"], "/db/t/find");
+
+    // flushes run in a span naming the database, inside the request that asked for them
+    let flushed = lines.iter().find(|l| l["fields"]["message"] == "flushed").unwrap();
+    assert_eq!((flushed["span"]["name"].as_str(), flushed["span"]["db"].as_str()), (Some("flush"), Some("t")));
+    assert!(flushed["span"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(flushed["spans"][0]["uri"], "/db/t/flush");
+}
+
+#[tokio::test]
+async fn the_threshold_and_the_filter_decide_what_is_logged() {
+    let dir = TempDir::new("tracing-filter");
+    let log = dir.join("server.log");
+    // under the default threshold of 500ms, and with only warnings let through
+    let server = Server::start_logging(&dir.join("data"), &["--log-format", "json", "serve"], "warn", &log);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    load(&http, &server, 100).await;
+    find(&http, &server, "eu").await;
+    assert_eq!(http.post(server.url("/db/t/flush")).send().await.unwrap().status(), 200);
+    drop(server);
+    let lines = read_log(&log);
+    assert!(slow_queries(&lines).is_empty());
+    assert!(lines.iter().all(|l| l["level"] == "WARN" || l["level"] == "ERROR"), "{:?}", lines);
+
+    // 0 turns the slow-query log off altogether
+    let log = dir.join("off.log");
+    let server = Server::start_logging(&dir.join("data"), &["--log-format", "json", "serve", "--slow-query-ms", "0"], "info", &log);
+    server.wait_listening();
+    find(&http, &server, "eu").await;
+    drop(server);
+    let lines = read_log(&log);
+    assert!(slow_queries(&lines).is_empty());
+    assert!(lines.iter().any(|l| l["span"]["uri"] == "/db/t/find"));
+}
+
+#[test]
+fn imports_log_progress_inside_batch_spans() {
+    let dir = TempDir::new("tracing-import");
+    let sqlite = dir.join("rows.sqlite");
+    let conn = rusqlite::Connection::open(&sqlite).unwrap();
+    conn.execute("CREATE TABLE t (a REAL, b REAL)", []).unwrap();
+    for i in 0..2500 { conn.execute("INSERT INTO t VALUES (?1, ?2)", rusqlite::params![i as f64, 1.0]).unwrap(); }
+    drop(conn);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_Vectra"))
+        .args(["--dir", &dir.join("data"), "import-sqlite", "--sqlite", &sqlite, "--table", "t", "--name", "docs", "--vec-cols", "a,b", "--batch-size", "1000"])
+        .env("RUST_LOG", "info")
+        .output().unwrap();
+    assert!(out.status.success());
+    // text is the default format, without colours when stderr isn't a terminal; the span and
+    // its fields come before the event
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(!stderr.contains('\x1b'), "{:?}", stderr);
+    let progress: Vec<&str> = stderr.lines().filter(|l| l.contains("import progress")).collect();
+    assert_eq!(progress.len(), 2, "{}", stderr);
+    assert!(progress[1].contains("count=2000 skipped=0"), "{}", progress[1]);
+    let shards: Vec<&str> = stderr.lines().filter(|l| l.contains("saved shard")).collect();
+    assert_eq!(shards.len(), 3, "{}", stderr);
+    for (i, rows) in [1000, 1000, 500].into_iter().enumerate() {
+        assert!(shards[i].contains(&format!("import_batch{{db=docs shard={} rows={}}}", i, rows)), "{}", shards[i]);
+        assert!(shards[i].contains(&format!("docs_part_{}.bin", i)), "{}", shards[i]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Crash recovery in `--write-mode wal`: servers are killed without a flush, the logs are left
+//! the way a crash at various points would leave them, and a restarted server (or the CLI)
+//! must see every acknowledged insert exactly once.
+
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use common::{run, Server, TempDir};
+use serde_json::{json, Value};
+
+fn start(data: &str) -> Server {
+    let server = Server::start(data, &["serve", "--write-mode", "wal", "--flush-interval-sec", "3600"]);
+    server.wait_listening();
+    server
+}
+
+async fn insert(http: &reqwest::Client, server: &Server, x: f64) {
+    let resp = http.post(server.url("/db/t/insert")).json(&json!({"values": [x, -x]})).send().await.unwrap();
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+}
+
+async fn get(http: &reqwest::Client, server: &Server, path: &str) -> Value {
+    let resp = http.get(server.url(path)).send().await.unwrap();
+    assert_eq!(resp.status(), 200, "{}", resp.text().await.unwrap());
+    resp.json().await.unwrap()
+}
+
+// The first value of every stored vector, in id order.
+async fn firsts(http: &reqwest::Client, server: &Server) -> Vec<f64> {
+    let page = http.post(server.url("/db/t/scroll")).json(&json!({"limit": 100})).send().await.unwrap().json::<Value>().await.unwrap();
+    page["items"].as_array().unwrap().iter().map(|i| i["values"][0].as_f64().unwrap()).collect()
+}
+
+// A server that logged `xs` into `t` and was then killed before any flush.
+async fn crashed_with(data: &str, xs: &[f64]) {
+    let server = start(data);
+    let http = reqwest::Client::new();
+    for &x in xs { insert(&http, &server, x).await; }
+}
+
+#[tokio::test]
+async fn a_rotated_log_left_by_a_crash_is_replayed() {
+    let dir = TempDir::new("wal-flushing");
+    let data = dir.join("data");
+    crashed_with(&data, &[1.0, 2.0, 3.0]).await;
+    // as if the crash came between rotating the log and writing the snapshot
+    fs::rename(dir.path().join("data/t.wal"), dir.path().join("data/t.wal.flushing")).unwrap();
+
+    let server = start(&data);
+    let http = reqwest::Client::new();
+    assert_eq!(firsts(&http, &server).await, [1.0, 2.0, 3.0]);
+    // new inserts go to a fresh log next to it, and a flush retires both
+    insert(&http, &server, 4.0).await;
+    http.post(server.url("/db/t/flush")).send().await.unwrap();
+    assert!(!dir.path().join("data/t.wal.flushing").exists());
+    assert!(!dir.path().join("data/t.wal").exists());
+    drop(server);
+    let server = start(&data);
+    assert_eq!(firsts(&http, &server).await, [1.0, 2.0, 3.0, 4.0]);
+}
+
+#[tokio::test]
+async fn a_torn_last_record_is_dropped_and_later_inserts_survive() {
+    let dir = TempDir::new("wal-torn");
+    let data = dir.join("data");
+    crashed_with(&data, &[1.0, 2.0]).await;
+    // the length prefix of a record that never got written in full
+    let mut log = OpenOptions::new().append(true).open(dir.path().join("data/t.wal")).unwrap();
+    log.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(log);
+
+    let server = start(&data);
+    let http = reqwest::Client::new();
+    assert_eq!(firsts(&http, &server).await, [1.0, 2.0]);
+    // these land behind the torn bytes unless loading cut them off
+    insert(&http, &server, 3.0).await;
+    insert(&http, &server, 4.0).await;
+    drop(server);
+
+    let server = start(&data);
+    assert_eq!(firsts(&http, &server).await, [1.0, 2.0, 3.0, 4.0]);
+}
+
+#[tokio::test]
+async fn records_already_in_the_snapshot_are_not_applied_twice() {
+    let dir = TempDir::new("wal-snapshot");
+    let data = dir.join("data");
+    crashed_with(&data, &[1.0, 2.0, 3.0]).await;
+    let stale = fs::read(dir.path().join("data/t.wal")).unwrap();
+
+    let server = start(&data);
+    let http = reqwest::Client::new();
+    insert(&http, &server, 4.0).await;
+    http.post(server.url("/db/t/flush")).send().await.unwrap();
+    insert(&http, &server, 5.0).await;
+    drop(server);
+    // as if the crash came after the snapshot but before the rotated log was removed
+    fs::write(dir.path().join("data/t.wal.flushing"), stale).unwrap();
+
+    let server = start(&data);
+    assert_eq!(firsts(&http, &server).await, [1.0, 2.0, 3.0, 4.0, 5.0]);
+    assert_eq!(get(&http, &server, "/db/t/info").await["count"], 5);
+}
+
+#[test]
+fn the_cli_sees_rows_that_are_only_in_the_log() {
+    let dir = TempDir::new("wal-cli");
+    let data = dir.join("data");
+    tokio::runtime::Runtime::new().unwrap().block_on(crashed_with(&data, &[1.0, 2.0, 3.0]));
+    assert!(!dir.path().join("data/t.bin").exists(), "nothing should have been flushed");
+
+    assert!(run(&data, &["info", "t"]).contains("count=3"));
+    assert!(run(&data, &["list"]).contains("t\tdimension=2\tcount=3"));
+    assert_eq!(run(&data, &["scan", "t", "--no-values"]).lines().count(), 3);
+    assert!(run(&data, &["find", "t", "-v", "3.0,-3.0", "-k", "1"]).contains("idx=2"));
+
+    // a CLI insert writes a snapshot holding the logged rows too, and retires the log
+    run(&data, &["insert", "t", "-v", "4.0,-4.0"]);
+    assert!(!dir.path().join("data/t.wal").exists());
+    assert!(run(&data, &["info", "t"]).contains("count=4"));
+}
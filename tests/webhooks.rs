@@ -0,0 +1,169 @@
+//! Webhook delivery against a local receiver: batching, signatures, retries, hooks following
+//! their database through a rename and a drop, events raised during shutdown, and the
+//! registration file's permissions.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::{body::Bytes, extract::State, http::{HeaderMap, StatusCode}, routing::post, Router};
+use common::{eventually, Server, TempDir};
+use ring::hmac;
+use serde_json::{json, Value};
+
+const SECRET: &str = "test-secret";
+
+#[derive(Clone)]
+struct Delivery { headers: HeaderMap, body: Bytes }
+
+impl Delivery {
+    fn header(&self, name: &str) -> &str { self.headers.get(name).unwrap().to_str().unwrap() }
+
+    fn json(&self) -> Value { serde_json::from_slice(&self.body).unwrap() }
+
+    fn event_types(&self) -> Vec<String> {
+        self.json()["events"].as_array().unwrap().iter().map(|e| e["type"].as_str().unwrap().to_string()).collect()
+    }
+
+    // The signature must be HMAC-SHA256 over `<timestamp>.<body>`.
+    fn verify(&self) {
+        let signed = [self.header("x-vectra-timestamp").as_bytes(), b".", &self.body].concat();
+        let hex = self.header("x-vectra-signature").strip_prefix("sha256=").unwrap();
+        let signature: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes());
+        hmac::verify(&key, &signed, &signature).expect("signature should match timestamp.body");
+    }
+}
+
+type Received = Arc<Mutex<Vec<Delivery>>>;
+
+// Records every request and fails the first one with a 500.
+async fn receive(State(received): State<Received>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let mut received = received.lock().unwrap();
+    received.push(Delivery { headers, body });
+    if received.len() == 1 { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK }
+}
+
+async fn receiver() -> (String, Received) {
+    let received = Received::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let app = Router::new().route("/hook", post(receive)).with_state(received.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, received)
+}
+
+async fn post_json(http: &reqwest::Client, url: String, body: Value) -> Value {
+    let resp = http.post(url).json(&body).send().await.unwrap();
+    assert!(resp.status().is_success(), "{}", resp.text().await.unwrap());
+    resp.json().await.unwrap()
+}
+
+async fn hooks_of(http: &reqwest::Client, server: &Server, db: &str) -> Vec<Value> {
+    http.get(server.url(&format!("/db/{}/webhooks", db))).send().await.unwrap().json().await.unwrap()
+}
+
+async fn wait_for(received: &Received, count: usize) {
+    let arrived = eventually(Duration::from_secs(15), async || received.lock().unwrap().len() >= count).await;
+    assert!(arrived, "expected {} deliveries, got {}", count, received.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn batches_are_signed_retried_and_follow_their_database() {
+    let dir = TempDir::new("webhooks");
+    let (hook_url, received) = receiver().await;
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+
+    post_json(&http, server.url("/create"), json!({"name": "t", "dimension": 2})).await;
+    let hook = post_json(&http, server.url("/db/t/webhooks"), json!({"url": hook_url, "events": ["insert", "rename", "drop"], "secret": SECRET})).await;
+    let id = hook["id"].as_str().unwrap().to_string();
+
+    // three inserts inside one batch window arrive as one batch
+    for i in 0..3 {
+        post_json(&http, server.url("/db/t/insert"), json!({"values": [i as f64, 1.0]})).await;
+    }
+    wait_for(&received, 2).await;
+    {
+        let received = received.lock().unwrap();
+        let (first, retry) = (&received[0], &received[1]);
+        assert_eq!(first.header("x-vectra-webhook"), id);
+        assert_eq!(first.header("x-vectra-attempt"), "1");
+        assert_eq!(retry.header("x-vectra-attempt"), "2", "a 5xx should be retried");
+        assert_eq!(first.body, retry.body, "a retry resends the same batch");
+        assert_eq!(retry.json()["db"], "t");
+        assert_eq!(retry.event_types(), ["insert", "insert", "insert"]);
+        first.verify();
+        retry.verify();
+    }
+
+    // after a rename the hook belongs to the new name
+    post_json(&http, server.url("/db/t/rename"), json!({"to": "u"})).await;
+    assert!(eventually(Duration::from_secs(5), async || hooks_of(&http, &server, "u").await.len() == 1).await);
+    assert!(hooks_of(&http, &server, "t").await.is_empty());
+    wait_for(&received, 3).await;
+    assert_eq!(received.lock().unwrap()[2].event_types(), ["rename"]);
+
+    post_json(&http, server.url("/db/u/insert"), json!({"values": [5.0, 5.0]})).await;
+    wait_for(&received, 4).await;
+    let moved = received.lock().unwrap()[3].clone();
+    moved.verify();
+    assert_eq!(moved.json()["db"], "u");
+    assert_eq!(moved.event_types(), ["insert"]);
+
+    // dropping the database delivers the drop and then removes the hook
+    let resp = http.delete(server.url("/db/u")).send().await.unwrap();
+    assert!(resp.status().is_success());
+    wait_for(&received, 5).await;
+    assert_eq!(received.lock().unwrap()[4].event_types(), ["drop"]);
+    assert!(eventually(Duration::from_secs(5), async || {
+        let saved: Vec<Value> = serde_json::from_slice(&std::fs::read(dir.path().join("data/webhooks.json")).unwrap()).unwrap();
+        saved.is_empty()
+    }).await, "the registration should be gone from webhooks.json");
+
+    // a new database under the old name doesn't inherit anything
+    post_json(&http, server.url("/create"), json!({"name": "u", "dimension": 2})).await;
+    assert!(hooks_of(&http, &server, "u").await.is_empty());
+}
+
+#[tokio::test]
+async fn the_final_flush_on_shutdown_is_delivered() {
+    let dir = TempDir::new("webhooks-shutdown");
+    let (hook_url, received) = receiver().await;
+    let server = Server::start(&dir.join("data"), &["serve", "--flush-interval-sec", "3600"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    post_json(&http, server.url("/create"), json!({"name": "t", "dimension": 2})).await;
+    post_json(&http, server.url("/db/t/webhooks"), json!({"url": hook_url, "events": ["insert", "flush"], "secret": SECRET})).await;
+    post_json(&http, server.url("/db/t/insert"), json!({"values": [1.0, 1.0]})).await;
+    // the first attempt is failed by the receiver, the retry goes through
+    wait_for(&received, 2).await;
+
+    // nothing has flushed `t` yet, so shutdown does, and the hook hears about it before exit
+    // waiting for the process off the runtime, which also runs the receiver
+    let status = tokio::task::spawn_blocking(move || server.terminate()).await.unwrap();
+    assert!(status.success());
+    let received = received.lock().unwrap();
+    let last = received.last().unwrap();
+    last.verify();
+    assert_eq!(last.event_types(), ["flush"]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn the_registration_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new("webhooks-mode");
+    let server = Server::start(&dir.join("data"), &["serve"]);
+    server.wait_listening();
+    let http = reqwest::Client::new();
+    post_json(&http, server.url("/create"), json!({"name": "t", "dimension": 2})).await;
+    // a file left by an older version, readable by everyone
+    let path = dir.path().join("data/webhooks.json");
+    std::fs::write(&path, "[]").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    post_json(&http, server.url("/db/t/webhooks"), json!({"url": "http://127.0.0.1:9/hook"})).await;
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+}